@@ -0,0 +1,33 @@
+// anonymous connections never log in, so the server can't tell who sent the requests made over them.
+// every request opens a connection of its own so requests can't be linked to each other either
+
+use std::net::SocketAddr;
+use bytes::Buf;
+use quinn::ClientConfig;
+use crate::dns;
+use crate::network::NetworkClient;
+use crate::packet::{ClientPacket, ServerPacket};
+use crate::protocol::RWBytes;
+
+/// sends `packet` to the server at `addr` and returns its answer, `config` has to negotiate the anonymous protocol
+pub async fn request(config: ClientConfig, name_server: Option<SocketAddr>, addr: &str, packet: &ClientPacket) -> anyhow::Result<ServerPacket<'static>> {
+    let connection = connect(config, name_server, addr).await?;
+    let response = exchange(&connection, packet).await;
+    let _ = connection.close().await;
+    response
+}
+
+/// opens an anonymous connection to the server at `addr`, `config` has to negotiate the anonymous protocol
+pub async fn connect(config: ClientConfig, name_server: Option<SocketAddr>, addr: &str) -> anyhow::Result<NetworkClient> {
+    let resolver = dns::resolver(name_server)?;
+    let resolved = dns::resolve(&resolver, addr).await?;
+    dns::connect(config, &resolved).await
+}
+
+/// sends `packet` over an anonymous connection and waits for the server's answer
+pub async fn exchange(connection: &NetworkClient, packet: &ClientPacket) -> anyhow::Result<ServerPacket<'static>> {
+    connection.send_reliable(&packet.encode()?).await?;
+    let size = connection.read_reliable(8).await?.get_u64_le();
+    let mut payload = connection.read_reliable(size as usize).await?;
+    ServerPacket::read(&mut payload)
+}
@@ -1,11 +1,32 @@
 use quinn::ClientConfig;
 
+// connections that negotiate this protocol never log in, the server can't tell who sent requests over them
+pub(crate) const ALPN_ANONYMOUS: &[u8] = b"rustspeak-anon";
+
 pub(crate) mod secure_authority {
     use quinn::ClientConfig;
+    use rustls::{Certificate, RootCertStore};
+    use std::sync::Arc;
+    use super::ALPN_ANONYMOUS;
 
     pub fn config() -> ClientConfig {
         ClientConfig::with_native_roots()
     }
+
+    pub fn anonymous_config() -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        // like `ClientConfig::with_native_roots` we skip certificates we can't use
+        for cert in rustls_native_certs::load_native_certs().unwrap_or_default() {
+            let _ = roots.add(&Certificate(cert.0));
+        }
+        let mut crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN_ANONYMOUS.to_vec()];
+
+        ClientConfig::new(Arc::new(crypto))
+    }
 }
 
 // FIXME: we should probably remove this once we are out of the testing stage
@@ -14,16 +35,26 @@ pub(crate) mod insecure_local {
     use rustls::client::{ServerCertVerified, ServerCertVerifier};
     use rustls::{Certificate, ServerName};
     use std::sync::Arc;
+    use super::ALPN_ANONYMOUS;
 
     pub fn config() -> ClientConfig {
-        let crypto = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_custom_certificate_verifier(SkipServerVerification::new())
-            .with_no_client_auth();
+        ClientConfig::new(Arc::new(crypto()))
+    }
+
+    pub fn anonymous_config() -> ClientConfig {
+        let mut crypto = crypto();
+        crypto.alpn_protocols = vec![ALPN_ANONYMOUS.to_vec()];
 
         ClientConfig::new(Arc::new(crypto))
     }
 
+    fn crypto() -> rustls::ClientConfig {
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(SkipServerVerification::new())
+            .with_no_client_auth()
+    }
+
     // Implementation of `ServerCertVerifier` that verifies everything as trustworthy.
     struct SkipServerVerification;
 
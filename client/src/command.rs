@@ -1,9 +1,15 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter, Write};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use clitty::core::CommandImpl;
 use openssl::pkey::PKey;
+use pollster::FutureExt;
+use ruint::aliases::U256;
 use crate::{Client, DbProfile, generate_token_num, uuid_from_pub_key};
+use crate::packet::{ChannelLifetime, ClientUpdateServerGroups, Perms, ServerGroupEdit};
+use crate::profile_db::Contact;
+use crate::protocol::UserUuid;
 use crate::ui::InterUiMessage;
 
 pub struct CommandProfiles();
@@ -47,6 +53,397 @@ impl CommandImpl for CommandProfiles {
     }
 }
 
+pub struct CommandContacts();
+
+impl CommandImpl for CommandContacts {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        match input[0] {
+            "list" => {
+                for profile in client.profile_db.cache_ref().iter() {
+                    if input.len() > 1 && profile.name != input[1] {
+                        continue;
+                    }
+                    client.println(format!("Contacts of {} ({}):", profile.name, profile.contacts.len()).as_str());
+                    for contact in profile.contacts.iter() {
+                        let presence = client.presence(&contact.uuid).block_on();
+                        let presence = if presence.is_empty() {
+                            String::from("offline")
+                        } else {
+                            format!("online on {}", presence.join(", "))
+                        };
+                        let home_server = contact.home_server.map(|addr| addr.to_string()).unwrap_or_else(|| String::from("unknown"));
+                        client.println(format!("{} {:?} home server: {} ({})", contact.nickname, contact.uuid, home_server, presence).as_str());
+                    }
+                }
+            }
+            "add" => {
+                if input.len() < 4 {
+                    client.println("Usage: contacts add <profile> <nickname> <key file or uuid> [home server]");
+                    return Ok(());
+                }
+                let home_server = match input.get(4) {
+                    Some(addr) => Some(addr.parse::<SocketAddr>()?),
+                    None => None,
+                };
+                let contact = match std::fs::read(input[3]) {
+                    Ok(pem) => Contact::from_pub_key(input[2].to_string(), PKey::public_key_from_pem(&pem)?.public_key_to_der()?, home_server),
+                    Err(_) => Contact::from_uuid(input[2].to_string(), UserUuid::from_u256(U256::from_str_radix(input[3], 16)?), home_server),
+                };
+                if client.profile_db.insert_contact(input[1], contact)? {
+                    client.inter_ui_msg_queue.send(InterUiMessage::UpdateContacts);
+                    client.println(format!("{} was added to the contacts of {}.", input[2], input[1]).as_str());
+                } else {
+                    client.println(format!("Couldn't find profile {}", input[1]).as_str());
+                }
+            }
+            "remove" => {
+                if input.len() < 3 {
+                    client.println("Usage: contacts remove <profile> <nickname>");
+                    return Ok(());
+                }
+                if client.profile_db.remove_contact(input[1], input[2])? {
+                    client.inter_ui_msg_queue.send(InterUiMessage::UpdateContacts);
+                    client.println(format!("{} was removed from the contacts of {}.", input[2], input[1]).as_str());
+                } else {
+                    client.println(format!("Couldn't find contact {} of profile {}", input[2], input[1]).as_str());
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+pub struct CommandPoke();
+
+impl CommandImpl for CommandPoke {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let text = if input.len() > 1 {
+            Some(input[1..].join(" "))
+        } else {
+            None
+        };
+        let server = client.active_server();
+        match server {
+            Some(server) => {
+                let receiver = server.clients.iter().find(|profile| profile.name == input[0]).map(|profile| profile.uuid);
+                match receiver {
+                    Some(receiver) => server.poke(receiver, text).block_on()?,
+                    None => client.println(format!("Couldn't find user {}", input[0]).as_str()),
+                }
+            }
+            None => client.println("You are not connected to any server."),
+        }
+        Ok(())
+    }
+}
+
+pub struct CommandChannelGroup();
+
+impl CommandImpl for CommandChannelGroup {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let server = client.active_server();
+        let server = match server {
+            Some(server) => server,
+            None => {
+                client.println("You are not connected to any server.");
+                return Ok(());
+            }
+        };
+        let channel = server.channels.load().values().find(|channel| channel.name.eq_ignore_ascii_case(input[0])).map(|channel| channel.id);
+        let user = server.clients.iter().find(|profile| profile.name == input[1]).map(|profile| profile.uuid);
+        let group = if input[2].eq_ignore_ascii_case("none") {
+            Some(None)
+        } else {
+            server.channel_groups.iter().find(|group| group.name.eq_ignore_ascii_case(input[2])).map(|group| Some(group.uuid))
+        };
+        match (channel, user, group) {
+            (None, _, _) => client.println(format!("Couldn't find channel {}", input[0]).as_str()),
+            (_, None, _) => client.println(format!("Couldn't find user {}", input[1]).as_str()),
+            (_, _, None) => client.println(format!("Couldn't find channel group {}", input[2]).as_str()),
+            (Some(channel), Some(user), Some(group)) => server.assign_channel_group(channel, user, group).block_on()?,
+        }
+        Ok(())
+    }
+}
+
+pub struct CommandCreateChannel();
+
+impl CommandImpl for CommandCreateChannel {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let server = client.active_server();
+        let server = match server {
+            Some(server) => server,
+            None => {
+                client.println("You are not connected to any server.");
+                return Ok(());
+            }
+        };
+        let lifetime = match input[1].to_lowercase().as_str() {
+            "permanent" => ChannelLifetime::Permanent,
+            "semipermanent" => ChannelLifetime::SemiPermanent,
+            "temporary" => ChannelLifetime::Temporary,
+            _ => unreachable!(),
+        };
+        let parent = match input.get(2) {
+            Some(parent) => match server.channels.load().values().find(|channel| channel.name.eq_ignore_ascii_case(parent)) {
+                Some(parent) => Some(parent.id),
+                None => {
+                    client.println(format!("Couldn't find channel {}", parent).as_str());
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+        server.create_channel(parent, input[0].to_string(), lifetime).block_on()
+    }
+}
+
+pub struct CommandSubscribe();
+
+impl CommandImpl for CommandSubscribe {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let server = client.active_server();
+        let server = match server {
+            Some(server) => server,
+            None => {
+                client.println("You are not connected to any server.");
+                return Ok(());
+            }
+        };
+        let channel = match server.channels.load().values().find(|channel| channel.name.eq_ignore_ascii_case(input[1])) {
+            Some(channel) => channel.id,
+            None => {
+                client.println(format!("Couldn't find channel {}", input[1]).as_str());
+                return Ok(());
+            }
+        };
+        if input[0].eq_ignore_ascii_case("subscribe") {
+            server.subscribe_channel(channel).block_on()
+        } else {
+            server.unsubscribe_channel(channel).block_on()
+        }
+    }
+}
+
+pub struct CommandRedeemToken();
+
+impl CommandImpl for CommandRedeemToken {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let server = client.active_server();
+        match server {
+            Some(server) => server.redeem_token(input[0].to_string()).block_on()?,
+            None => client.println("You are not connected to any server."),
+        }
+        Ok(())
+    }
+}
+
+pub struct CommandServerGroup();
+
+impl CommandImpl for CommandServerGroup {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let server = client.active_server();
+        let server = match server {
+            Some(server) => server,
+            None => {
+                client.println("You are not connected to any server.");
+                return Ok(());
+            }
+        };
+        let action = input[0].to_lowercase();
+        if action == "create" {
+            // new groups don't grant any perms, they can be edited on the server afterwards
+            return server.edit_server_group(ServerGroupEdit::Create {
+                name: input[1].to_string(),
+                perms: Perms::default(),
+            }).block_on();
+        }
+        let group = match server.groups.iter().find(|group| group.name.load().eq_ignore_ascii_case(input[1])).map(|group| group.uuid) {
+            Some(group) => group,
+            None => {
+                client.println(format!("Couldn't find server group {}", input[1]).as_str());
+                return Ok(());
+            }
+        };
+        match action.as_str() {
+            "delete" => server.edit_server_group(ServerGroupEdit::Delete(group)).block_on(),
+            "rename" | "assign" | "unassign" => {
+                let Some(arg) = input.get(2) else {
+                    client.println(format!("{} requires a third argument", action).as_str());
+                    return Ok(());
+                };
+                if action == "rename" {
+                    return server.edit_server_group(ServerGroupEdit::Rename { group, name: arg.to_string() }).block_on();
+                }
+                let user = match server.clients.iter().find(|profile| profile.name == *arg).map(|profile| profile.uuid) {
+                    Some(user) => user,
+                    None => {
+                        client.println(format!("Couldn't find user {}", arg).as_str());
+                        return Ok(());
+                    }
+                };
+                let update = if action == "assign" {
+                    ClientUpdateServerGroups::Add(group)
+                } else {
+                    ClientUpdateServerGroups::Remove(group)
+                };
+                server.update_client_server_groups(user, update).block_on()
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub struct CommandDirectMessage();
+
+impl CommandImpl for CommandDirectMessage {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let pem = std::fs::read(input[0])?;
+        let receiver_key = PKey::public_key_from_pem(&pem)?.public_key_to_der()?;
+        let text = input[1..].join(" ");
+        let server = client.active_server();
+        match server {
+            Some(server) => {
+                server.send_direct_message(client, receiver_key, text).block_on()?;
+                client.println("Message sent.");
+            }
+            None => client.println("You are not connected to any server."),
+        }
+        Ok(())
+    }
+}
+
+pub struct CommandDirectMessages();
+
+impl CommandImpl for CommandDirectMessages {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let pem = std::fs::read(input[0])?;
+        let sender = UserUuid::from_u256(uuid_from_pub_key(&PKey::public_key_from_pem(&pem)?.public_key_to_der()?));
+        let server = client.active_server();
+        match server {
+            Some(server) => {
+                let messages = client.conversation_db.messages(&server.profile.uuid(), &sender)?;
+                if messages.is_empty() {
+                    client.println("You didn't receive any messages from this user.");
+                }
+                for msg in messages.iter().filter(|msg| !msg.text.is_empty()) {
+                    client.println(format!("[{:?}]: {}", msg.sender, msg.text).as_str());
+                }
+            }
+            None => client.println("You are not connected to any server."),
+        }
+        Ok(())
+    }
+}
+
+pub struct CommandSealedGrant();
+
+impl CommandImpl for CommandSealedGrant {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let pem = std::fs::read(input[0])?;
+        let receiver_key = PKey::public_key_from_pem(&pem)?.public_key_to_der()?;
+        let server = client.active_server();
+        match server {
+            Some(server) => {
+                server.grant_sealed_sender(client, receiver_key).block_on()?;
+                client.println("Keys for sealed messages were handed out.");
+            }
+            None => client.println("You are not connected to any server."),
+        }
+        Ok(())
+    }
+}
+
+pub struct CommandServers();
+
+impl CommandImpl for CommandServers {
+    type CTX = Arc<Client>;
+
+    fn execute(&self, client: &Self::CTX, input: &[&str]) -> anyhow::Result<()> {
+        let servers = client.servers.read().block_on().clone();
+        if input.is_empty() || input[0].eq_ignore_ascii_case("list") {
+            if servers.is_empty() {
+                client.println("You are not connected to any server.");
+                return Ok(());
+            }
+            let mut list = String::new();
+            for (idx, server) in servers.iter().enumerate() {
+                let state = if server.state.is_connected() {
+                    "connected"
+                } else if server.is_reconnecting() {
+                    "reconnecting"
+                } else {
+                    "connecting"
+                };
+                write!(list, "{}. {} ({})", idx + 1, server.name, state)?;
+                if client.is_active_server(server) {
+                    list.push_str(" [active]");
+                }
+                if client.is_voice_server(server) {
+                    list.push_str(" [voice]");
+                }
+                list.push('\n');
+            }
+            client.println(list.trim_end());
+            return Ok(());
+        }
+        if input.len() < 2 {
+            client.println("Please specify a server.");
+            return Ok(());
+        }
+        // servers can be chosen by their name or their position in the list
+        let server = match input[1].parse::<usize>() {
+            Ok(idx) => idx.checked_sub(1).and_then(|idx| servers.get(idx)),
+            Err(_) => servers.iter().find(|server| server.name.eq_ignore_ascii_case(input[1])),
+        };
+        let server = match server {
+            Some(server) => server.clone(),
+            None => {
+                client.println(format!("Couldn't find server {}", input[1]).as_str());
+                return Ok(());
+            }
+        };
+        if input[0].eq_ignore_ascii_case("switch") {
+            client.active_server.store(Some(server.clone()));
+            client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(server.clone()));
+            client.println(format!("Switched to {}", server.name).as_str());
+        } else if input[0].eq_ignore_ascii_case("voice") {
+            // the voice thread notices the change and restarts capturing for the new server
+            client.voice_server.store(Some(server.clone()));
+            client.println(format!("Voice is now sent to {}", server.name).as_str());
+        } else {
+            client.remove_server(&server).block_on()?;
+            if let Some(active) = client.active_server.load().as_ref() {
+                client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(active.clone()));
+            }
+            client.println(format!("Disconnected from {}", server.name).as_str());
+        }
+        Ok(())
+    }
+}
+
 struct ProfileAlreadyExistsError(String);
 
 impl Debug for ProfileAlreadyExistsError {
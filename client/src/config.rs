@@ -4,7 +4,7 @@ use serde_derive::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::mem::size_of;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use ruint::aliases::U256;
 use crate::protocol::UserUuid;
@@ -14,6 +14,8 @@ pub struct Config {
     pub fav_servers: Vec<ServerEntry>,
     pub last_server: Option<SocketAddr>,
     default_account: Option<UserUuidContainer>,
+    #[serde(default)]
+    pub dns_server: Option<SocketAddr>, // the name server used to resolve server addresses, the system's if none
 }
 
 impl Config {
@@ -28,6 +30,7 @@ impl Config {
             fav_servers: self.fav_servers.clone(),
             last_server: self.last_server.clone(),
             default_account: Some(UserUuidContainer::new(account)),
+            dns_server: self.dns_server,
         }
     }
 
@@ -38,11 +41,14 @@ impl Default for Config {
         Self {
             fav_servers: vec![ServerEntry {
                 name: "local".to_string(),
-                addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 20354)),
+                addr: String::from("127.0.0.1:20354"),
                 profile: None,
+                channel: None,
+                channel_password: None,
             }],
             last_server: None,
             default_account: None,
+            dns_server: None,
         }
     }
 }
@@ -87,8 +93,12 @@ impl Config {
 pub struct ServerEntry {
     pub name: String,
     // FIXME: should we use Cow?
-    pub addr: SocketAddr,
+    pub addr: String, // host[:port], the host may be a domain or an ip address
     profile: Option<UserUuidContainer>,
+    #[serde(default)]
+    pub channel: Option<u128>, // the channel to log into, none for our last or the default channel
+    #[serde(default)]
+    pub channel_password: Option<String>,
     // FIXME: we need a favicon (image) for each server image
 }
 
@@ -114,6 +124,8 @@ impl ServerEntry {
             name,
             addr,
             profile: profile.map(|uuid| UserUuidContainer::new(uuid)),
+            channel: None,
+            channel_password: None,
         }
     }
 
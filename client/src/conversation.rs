@@ -0,0 +1,847 @@
+// end-to-end encryption for direct messages, the home server only ever stores the resulting ciphertexts
+//
+// every conversation starts from a root key that is established once via the profile's rsa keys. time is
+// split into mid-term periods of `REHASH_INTERVAL` which are aligned so both sides start new periods at the
+// same time. entering a period derives the period's mid-term key and the root key of the next period from the
+// current root key which gets deleted afterwards, so the stored root key never reveals the current or earlier
+// periods. each direction of the conversation has its own chain key derived from the mid-term key which is
+// hash-ratcheted every `RATCHET_MESSAGES` messages or every `RATCHET_INTERVAL`, again deleting the previous key.
+// so whoever steals the stored keys can't read earlier messages (forward secrecy). there is no new key agreement
+// though, which means that they are able to read all later messages of the conversation.
+// the content of every message is signed by the sender's profile key so peers can't be impersonated
+
+use std::time::Duration;
+use bytes::{Bytes, BytesMut};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sha::sha256;
+use openssl::sign::{Signer, Verifier};
+use openssl::symm::{Cipher, decrypt_aead, encrypt_aead};
+use ruint::aliases::U256;
+use sled::{Db, IVec, Tree};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use uuid::Uuid;
+use crate::packet::Encrypted;
+use crate::profile_db::uuid_from_pub_key;
+use crate::protocol::{RWBytes, UserUuid};
+use crate::sealed_sender::{IssuedSealedKey, SealedSenderGrant};
+use crate::utils::current_time_millis;
+
+pub const RATCHET_MESSAGES: u64 = 64;
+pub const RATCHET_INTERVAL: Duration = Duration::from_secs(60 * 60);
+pub const REHASH_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// the number of epochs a received message may be ahead of our chain key, this
+/// limits the work a forged message can make us do before it gets authenticated
+pub const MAX_SKIP: u64 = 1024;
+/// the number of keys of epochs we moved past that are kept for messages arriving out of order
+const MAX_SKIPPED_KEYS: usize = 16;
+
+pub(crate) const KEY_LEN: usize = 32;
+pub(crate) const NONCE_LEN: usize = 12;
+pub(crate) const TAG_LEN: usize = 16;
+
+const RATCHET_LABEL: &[u8] = b"RustSpeak ratchet";
+const MID_TERM_LABEL: &[u8] = b"RustSpeak mid-term";
+const ROOT_LABEL: &[u8] = b"RustSpeak root";
+const INITIATOR_LABEL: &[u8] = b"RustSpeak initiator";
+const RESPONDER_LABEL: &[u8] = b"RustSpeak responder";
+
+fn hash(parts: &[&[u8]]) -> [u8; KEY_LEN] {
+    let mut data = vec![];
+    for part in parts {
+        data.extend_from_slice(part);
+    }
+    sha256(&data)
+}
+
+/// hashes a key irreversibly so that the previous key can't be recovered from the new one
+pub fn ratchet_key(key: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    hash(&[key, RATCHET_LABEL])
+}
+
+/// returns the start of the mid-term period `now` lies in
+fn rehash_period(now: Duration) -> Duration {
+    Duration::from_secs(now.as_secs() / REHASH_INTERVAL.as_secs() * REHASH_INTERVAL.as_secs())
+}
+
+/// derives the mid-term key of the period starting at `rehash_time` from the period's root key
+pub fn derive_mid_term_key(root: &[u8; KEY_LEN], rehash_time: Duration) -> [u8; KEY_LEN] {
+    hash(&[root, &rehash_time.as_secs().to_le_bytes(), MID_TERM_LABEL])
+}
+
+/// derives the root key of the period after the one starting at `rehash_time`, the mid-term key can't be derived from it
+fn derive_next_root(root: &[u8; KEY_LEN], rehash_time: Duration) -> [u8; KEY_LEN] {
+    hash(&[root, &rehash_time.as_secs().to_le_bytes(), ROOT_LABEL])
+}
+
+#[derive(Clone)]
+pub struct ChainKey {
+    key: [u8; KEY_LEN],
+    epoch: u64,
+    epoch_start: Duration,
+    messages: u64, // the number of messages that were sent in the current epoch
+}
+
+impl ChainKey {
+    fn new(key: [u8; KEY_LEN], now: Duration) -> Self {
+        Self {
+            key,
+            epoch: 0,
+            epoch_start: now,
+            messages: 0,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    fn ratchet(&mut self, now: Duration) {
+        self.key = ratchet_key(&self.key);
+        self.epoch += 1;
+        self.epoch_start = now;
+        self.messages = 0;
+    }
+
+    fn needs_ratchet(&self, now: Duration) -> bool {
+        self.messages >= RATCHET_MESSAGES || now.saturating_sub(self.epoch_start) >= RATCHET_INTERVAL
+    }
+
+    /// moves the key forward to `epoch` which may be at most `MAX_SKIP` epochs ahead, older epochs can't be reached anymore
+    fn advance_to(&mut self, epoch: u64, now: Duration) -> anyhow::Result<()> {
+        if epoch < self.epoch {
+            return Err(anyhow::Error::from(ErrorOutdatedKey));
+        }
+        if epoch - self.epoch > MAX_SKIP {
+            return Err(anyhow::Error::from(ErrorTooFarAhead));
+        }
+        while self.epoch < epoch {
+            self.ratchet(now);
+        }
+        Ok(())
+    }
+}
+
+impl RWBytes for ChainKey {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let key = read_key(src)?;
+        let epoch = u64::read(src)?;
+        let epoch_start = Duration::read(src)?;
+        let messages = u64::read(src)?;
+
+        Ok(Self {
+            key,
+            epoch,
+            epoch_start,
+            messages,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.key.to_vec().write(dst)?;
+        self.epoch.write(dst)?;
+        self.epoch_start.write(dst)?;
+        self.messages.write(dst)?;
+
+        Ok(())
+    }
+}
+
+/// the receiving key of an epoch we moved past, the keys of later epochs of the same period can be derived from it
+#[derive(Clone)]
+struct SkippedKey {
+    rehash_time: Duration,
+    epoch: u64,
+    key: [u8; KEY_LEN],
+}
+
+impl RWBytes for SkippedKey {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let rehash_time = Duration::read(src)?;
+        let epoch = u64::read(src)?;
+        let key = read_key(src)?;
+
+        Ok(Self {
+            rehash_time,
+            epoch,
+            key,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.rehash_time.write(dst)?;
+        self.epoch.write(dst)?;
+        self.key.to_vec().write(dst)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn read_key(src: &mut Bytes) -> anyhow::Result<[u8; KEY_LEN]> {
+    let raw = Vec::<u8>::read(src)?;
+    raw.try_into().map_err(|_| anyhow::Error::from(ErrorInvalidMessage))
+}
+
+#[derive(Clone)]
+pub struct Conversation {
+    pub id: Uuid,
+    pub owner: UserUuid, // the profile of ours this conversation belongs to
+    pub peer: UserUuid,
+    pub peer_pub_key: Vec<u8>,
+    root: [u8; KEY_LEN], // the root key of the next mid-term period
+    rehash_time: Duration, // the start of the current mid-term period
+    initiator: bool,
+    key_init: Option<Vec<u8>>, // the rsa encrypted key material which has to be sent along until the peer replied
+    send: ChainKey,
+    recv: ChainKey,
+    skipped: Vec<SkippedKey>, // the oldest keys come first
+    pub sealed_grant: Option<SealedSenderGrant>, // allows us to send sealed messages to the peer
+    pub issued_sealed_key: Option<IssuedSealedKey>, // allows the peer to send sealed messages to us
+}
+
+impl Conversation {
+    /// starts a new conversation between our profile `owner` and the owner of `peer_pub_key`
+    pub fn start(owner: UserUuid, peer: UserUuid, peer_pub_key: Vec<u8>) -> anyhow::Result<Self> {
+        let mut root = [0; KEY_LEN];
+        openssl::rand::rand_bytes(&mut root)?;
+        // the peer needs to know when the first period started to derive the same keys
+        let start = Duration::from_secs(current_time_millis().as_secs());
+        let mut material = root.to_vec();
+        material.extend_from_slice(&start.as_secs().to_le_bytes());
+        let rsa = Rsa::public_key_from_der(&peer_pub_key)?;
+        let mut key_init = vec![0; rsa.size() as usize];
+        let len = rsa.public_encrypt(&material, &mut key_init, Padding::PKCS1_OAEP)?;
+        key_init.truncate(len);
+        Ok(Self::new(Uuid::new_v4(), owner, peer, peer_pub_key, &root, start, true, Some(key_init)))
+    }
+
+    /// recovers the root key and the start of the first period the peer sent us when they started the conversation
+    fn accept(key_init: &[u8], own_key: &Rsa<Private>) -> anyhow::Result<([u8; KEY_LEN], Duration)> {
+        let mut material = vec![0; own_key.size() as usize];
+        let len = own_key.private_decrypt(key_init, &mut material, Padding::PKCS1_OAEP)?;
+        if len != KEY_LEN + 8 {
+            return Err(anyhow::Error::from(ErrorInvalidMessage));
+        }
+        let mut root = [0; KEY_LEN];
+        root.copy_from_slice(&material[..KEY_LEN]);
+        let start = u64::from_le_bytes(material[KEY_LEN..len].try_into().unwrap());
+        Ok((root, Duration::from_secs(start)))
+    }
+
+    fn new(id: Uuid, owner: UserUuid, peer: UserUuid, peer_pub_key: Vec<u8>, root: &[u8; KEY_LEN], rehash_time: Duration, initiator: bool, key_init: Option<Vec<u8>>) -> Self {
+        let (send, recv) = chain_keys(&derive_mid_term_key(root, rehash_time), rehash_time, initiator);
+        Self {
+            id,
+            owner,
+            peer,
+            peer_pub_key,
+            root: derive_next_root(root, rehash_time),
+            rehash_time,
+            initiator,
+            key_init,
+            send,
+            recv,
+            skipped: vec![],
+            sealed_grant: None,
+            issued_sealed_key: None,
+        }
+    }
+
+    /// enters the mid-term period beginning at `rehash_time`, all chain keys get derived anew. the periods
+    /// in between are entered as well as the root keys of later periods can only be derived from them
+    pub fn rehash(&mut self, rehash_time: Duration) {
+        let prev_rehash_time = self.rehash_time;
+        let mut mid_term = None;
+        while self.rehash_time < rehash_time {
+            let next = rehash_period(self.rehash_time) + REHASH_INTERVAL;
+            mid_term = Some(derive_mid_term_key(&self.root, next));
+            self.root = derive_next_root(&self.root, next);
+            self.rehash_time = next;
+        }
+        if let Some(mid_term) = mid_term {
+            // messages of the previous period may still arrive after the first message of the new one
+            let prev = self.recv.clone();
+            self.skip(prev_rehash_time, &prev);
+            let (send, recv) = chain_keys(&mid_term, self.rehash_time, self.initiator);
+            self.send = send;
+            self.recv = recv;
+        }
+    }
+
+    /// keeps the key of `chain` around for messages of its epoch or later ones that arrive out of order
+    fn skip(&mut self, rehash_time: Duration, chain: &ChainKey) {
+        self.skipped.push(SkippedKey {
+            rehash_time,
+            epoch: chain.epoch,
+            key: chain.key,
+        });
+        if self.skipped.len() > MAX_SKIPPED_KEYS {
+            self.skipped.remove(0);
+        }
+    }
+
+    /// returns the key of `epoch` in the period starting at `rehash_time` if it can be derived from one of the skipped keys
+    fn skipped_key(&self, rehash_time: Duration, epoch: u64) -> Option<[u8; KEY_LEN]> {
+        let skipped = self.skipped.iter()
+            .filter(|skipped| skipped.rehash_time == rehash_time && skipped.epoch <= epoch && epoch - skipped.epoch <= MAX_SKIP)
+            .max_by_key(|skipped| skipped.epoch)?;
+        let mut key = skipped.key;
+        for _ in skipped.epoch..epoch {
+            key = ratchet_key(&key);
+        }
+        Some(key)
+    }
+
+    /// encrypts `content` and signs it with `own_key`, the result can be handed to the home server of the peer
+    pub fn encrypt(&mut self, content: &DirectMessageContent, own_key: &Rsa<Private>) -> anyhow::Result<Encrypted<DirectMessageContent>> {
+        let now = current_time_millis();
+        let period = rehash_period(now);
+        if period > self.rehash_time {
+            self.rehash(period);
+        }
+        if self.send.needs_ratchet(now) {
+            self.send.ratchet(now);
+        }
+        let mut encoded = BytesMut::new();
+        content.write(&mut encoded)?;
+        let pkey = PKey::from_rsa(own_key.clone())?;
+        let signature = Signer::new(MessageDigest::sha256(), &pkey)?.sign_oneshot_to_vec(&signed_data(&self.id, &encoded))?;
+        let mut plain = BytesMut::new();
+        signature.write(&mut plain)?;
+        plain.extend_from_slice(&encoded);
+        let mut nonce = [0; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce)?;
+        let header = DirectMessageHeader {
+            conversation: self.id,
+            key_init: self.key_init.clone(),
+            rehash_time: self.rehash_time,
+            epoch: self.send.epoch,
+        };
+        let mut aad = BytesMut::new();
+        header.write(&mut aad)?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = encrypt_aead(Cipher::aes_256_gcm(), &self.send.key, Some(&nonce), &aad, &plain, &mut tag)?;
+        self.send.messages += 1;
+
+        let msg = DirectMessage {
+            header,
+            nonce: nonce.to_vec(),
+            ciphertext,
+            tag: tag.to_vec(),
+        };
+        let mut buf = BytesMut::new();
+        msg.write(&mut buf)?;
+        Ok(Encrypted::new(buf.to_vec()))
+    }
+
+    pub fn decrypt(&mut self, msg: &DirectMessage) -> anyhow::Result<DirectMessageContent> {
+        let now = current_time_millis();
+        // the keys are only updated on a copy which is committed once we know the message was authentic
+        let mut next = self.clone();
+        let key = if msg.header.rehash_time > next.rehash_time {
+            // the peer started a new mid-term period which has to be aligned and can't lie in the future
+            let rehash_time = msg.header.rehash_time;
+            if rehash_period(rehash_time) != rehash_time || rehash_time > rehash_period(now) + REHASH_INTERVAL {
+                return Err(anyhow::Error::from(ErrorInvalidMessage));
+            }
+            next.rehash(rehash_time);
+            next.advance_recv(msg.header.epoch, now)?
+        } else if msg.header.rehash_time == next.rehash_time && msg.header.epoch >= next.recv.epoch {
+            next.advance_recv(msg.header.epoch, now)?
+        } else {
+            // the message was overtaken by messages that were sent after it
+            next.skipped_key(msg.header.rehash_time, msg.header.epoch).ok_or(anyhow::Error::from(ErrorOutdatedKey))?
+        };
+        let mut aad = BytesMut::new();
+        msg.header.write(&mut aad)?;
+        let mut plain = Bytes::from(decrypt_aead(Cipher::aes_256_gcm(), &key, Some(&msg.nonce), &aad, &msg.ciphertext, &msg.tag)?);
+        let signature = Vec::<u8>::read(&mut plain)?;
+        let encoded = plain.clone();
+        let content = DirectMessageContent::read(&mut plain)?;
+        // the sender's uuid is the hash of their key, so only the owner of the key can sign as them
+        if UserUuid::from_u256(uuid_from_pub_key(&content.sender_pub_key)) != content.sender {
+            return Err(anyhow::Error::from(ErrorInvalidMessage));
+        }
+        let pkey = PKey::public_key_from_der(&content.sender_pub_key)?;
+        if !Verifier::new(MessageDigest::sha256(), &pkey)?.verify_oneshot(&signature, &signed_data(&self.id, &encoded))? {
+            return Err(anyhow::Error::from(ErrorInvalidMessage));
+        }
+        // only commit the new keys once we know the message was authentic
+        *self = next;
+        // the peer obviously knows the key, so we don't have to send it anymore
+        self.key_init = None;
+        Ok(content)
+    }
+
+    /// moves the receiving key forward to `epoch` and returns it, the key we move past is kept as a skipped key
+    fn advance_recv(&mut self, epoch: u64, now: Duration) -> anyhow::Result<[u8; KEY_LEN]> {
+        let prev = self.recv.clone();
+        self.recv.advance_to(epoch, now)?;
+        if self.recv.epoch != prev.epoch {
+            self.skip(self.rehash_time, &prev);
+        }
+        Ok(self.recv.key)
+    }
+}
+
+// the signature covers the conversation as well, so a message can't be replayed in other conversations
+fn signed_data(conversation: &Uuid, encoded_content: &[u8]) -> Vec<u8> {
+    let mut data = conversation.as_bytes().to_vec();
+    data.extend_from_slice(encoded_content);
+    data
+}
+
+fn chain_keys(mid_term: &[u8; KEY_LEN], rehash_time: Duration, initiator: bool) -> (ChainKey, ChainKey) {
+    let initiator_key = ChainKey::new(hash(&[mid_term, INITIATOR_LABEL]), rehash_time);
+    let responder_key = ChainKey::new(hash(&[mid_term, RESPONDER_LABEL]), rehash_time);
+    if initiator {
+        (initiator_key, responder_key)
+    } else {
+        (responder_key, initiator_key)
+    }
+}
+
+impl RWBytes for Conversation {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let id = Uuid::read(src)?;
+        let owner = UserUuid::read(src)?;
+        let peer = UserUuid::read(src)?;
+        let peer_pub_key = Vec::<u8>::read(src)?;
+        let root = read_key(src)?;
+        let rehash_time = Duration::read(src)?;
+        let initiator = bool::read(src)?;
+        let key_init = Option::<Vec<u8>>::read(src)?;
+        let send = ChainKey::read(src)?;
+        let recv = ChainKey::read(src)?;
+        let skipped = Vec::<SkippedKey>::read(src)?;
+        let sealed_grant = Option::<SealedSenderGrant>::read(src)?;
+        let issued_sealed_key = Option::<IssuedSealedKey>::read(src)?;
+
+        Ok(Self {
+            id,
+            owner,
+            peer,
+            peer_pub_key,
+            root,
+            rehash_time,
+            initiator,
+            key_init,
+            send,
+            recv,
+            skipped,
+            sealed_grant,
+            issued_sealed_key,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.id.write(dst)?;
+        self.owner.write(dst)?;
+        self.peer.write(dst)?;
+        self.peer_pub_key.write(dst)?;
+        self.root.to_vec().write(dst)?;
+        self.rehash_time.write(dst)?;
+        self.initiator.write(dst)?;
+        self.key_init.write(dst)?;
+        self.send.write(dst)?;
+        self.recv.write(dst)?;
+        self.skipped.write(dst)?;
+        self.sealed_grant.write(dst)?;
+        self.issued_sealed_key.write(dst)?;
+
+        Ok(())
+    }
+}
+
+pub struct DirectMessageHeader {
+    pub conversation: Uuid,
+    pub key_init: Option<Vec<u8>>, // only present until the peer replied for the first time
+    pub rehash_time: Duration,
+    pub epoch: u64,
+}
+
+impl RWBytes for DirectMessageHeader {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let conversation = Uuid::read(src)?;
+        let key_init = Option::<Vec<u8>>::read(src)?;
+        let rehash_time = Duration::read(src)?;
+        let epoch = u64::read(src)?;
+
+        Ok(Self {
+            conversation,
+            key_init,
+            rehash_time,
+            epoch,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.conversation.write(dst)?;
+        self.key_init.write(dst)?;
+        self.rehash_time.write(dst)?;
+        self.epoch.write(dst)?;
+
+        Ok(())
+    }
+}
+
+/// the encrypted form of a direct message, this is what the home server stores
+pub struct DirectMessage {
+    pub header: DirectMessageHeader, // authenticated but not encrypted
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub tag: Vec<u8>,
+}
+
+impl RWBytes for DirectMessage {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let header = DirectMessageHeader::read(src)?;
+        let nonce = Vec::<u8>::read(src)?;
+        let ciphertext = Vec::<u8>::read(src)?;
+        let tag = Vec::<u8>::read(src)?;
+
+        Ok(Self {
+            header,
+            nonce,
+            ciphertext,
+            tag,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.header.write(dst)?;
+        self.nonce.write(dst)?;
+        self.ciphertext.write(dst)?;
+        self.tag.write(dst)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DirectMessageContent {
+    pub sender: UserUuid,
+    pub sender_pub_key: Vec<u8>, // required by the receiver to be able to reply
+    pub sent: Duration,
+    pub text: String,
+    pub sealed_grant: Option<SealedSenderGrant>, // keys the sender hands out so we can message them anonymously
+}
+
+impl RWBytes for DirectMessageContent {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let sender = UserUuid::read(src)?;
+        let sender_pub_key = Vec::<u8>::read(src)?;
+        let sent = Duration::read(src)?;
+        let text = String::read(src)?;
+        let sealed_grant = Option::<SealedSenderGrant>::read(src)?;
+
+        Ok(Self {
+            sender,
+            sender_pub_key,
+            sent,
+            text,
+            sealed_grant,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.sender.write(dst)?;
+        self.sender_pub_key.write(dst)?;
+        self.sent.write(dst)?;
+        self.text.write(dst)?;
+        self.sealed_grant.write(dst)?;
+
+        Ok(())
+    }
+}
+
+/// persists the keys of all conversations so messages which were received while
+/// the client was offline can still be decrypted, along with the messages we received
+pub struct ConversationDb {
+    db: Db,
+    messages: Tree,
+}
+
+impl ConversationDb {
+    pub fn new(path: String) -> anyhow::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            messages: db.open_tree("messages")?,
+            db,
+        })
+    }
+
+    /// returns all messages our profile `owner` received from `peer` in the order they were sent
+    pub fn messages(&self, owner: &UserUuid, peer: &UserUuid) -> anyhow::Result<Vec<DirectMessageContent>> {
+        let mut messages = vec![];
+        for entry in self.messages.scan_prefix(owner.into_u256().as_le_slice()) {
+            let (_, raw) = entry?;
+            let content = DirectMessageContent::read(&mut Bytes::from(raw.to_vec()))?;
+            if &content.sender == peer {
+                messages.push(content);
+            }
+        }
+        Ok(messages)
+    }
+
+    pub fn get(&self, id: &Uuid) -> anyhow::Result<Option<Conversation>> {
+        Ok(match self.db.get(id.as_bytes())? {
+            None => None,
+            Some(raw) => Some(Conversation::read(&mut Bytes::from(raw.to_vec()))?),
+        })
+    }
+
+    /// returns the conversation between our profile `owner` and `peer`
+    pub fn find_by_peer(&self, owner: &UserUuid, peer: &UserUuid) -> anyhow::Result<Option<Conversation>> {
+        for entry in self.db.iter() {
+            let (_, raw) = entry?;
+            let conversation = Conversation::read(&mut Bytes::from(raw.to_vec()))?;
+            if &conversation.owner == owner && &conversation.peer == peer {
+                return Ok(Some(conversation));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn insert(&self, conversation: &Conversation) -> anyhow::Result<()> {
+        let mut buf = BytesMut::new();
+        conversation.write(&mut buf)?;
+        self.db.insert(conversation.id.as_bytes(), IVec::from(buf.to_vec()))?;
+        Ok(())
+    }
+
+    /// decrypts a message received from the home server and stores it, conversations which were
+    /// started by the peer get created on the fly. once this returns the home server may delete the message
+    pub fn decrypt(&self, raw: &Encrypted<DirectMessageContent>, own_key: &Rsa<Private>) -> anyhow::Result<DirectMessageContent> {
+        let msg = DirectMessage::read(&mut Bytes::copy_from_slice(&raw.ciphertext))?;
+        let owner = UserUuid::from_u256(uuid_from_pub_key(&own_key.public_key_to_der()?));
+        let mut conversation = match self.get(&msg.header.conversation)? {
+            // conversations of our other profiles can't be continued with this one
+            Some(conversation) if conversation.owner != owner => return Err(anyhow::Error::from(ErrorUnknownConversation)),
+            Some(conversation) => conversation,
+            None => {
+                let key_init = msg.header.key_init.as_ref().ok_or(anyhow::Error::from(ErrorUnknownConversation))?;
+                let (root, start) = Conversation::accept(key_init, own_key)?;
+                // the peer's identity is only known after decryption, so we fill it in below
+                Conversation::new(msg.header.conversation, owner, UserUuid::from_u256(U256::ZERO), vec![], &root, start, false, None)
+            }
+        };
+        let content = conversation.decrypt(&msg)?;
+        if conversation.peer_pub_key.is_empty() {
+            conversation.peer = content.sender;
+            conversation.peer_pub_key = content.sender_pub_key.clone();
+        } else if conversation.peer != content.sender {
+            return Err(anyhow::Error::from(ErrorInvalidMessage));
+        }
+        if let Some(grant) = content.sealed_grant.as_ref() {
+            conversation.sealed_grant = Some(grant.clone());
+        }
+        // the message is stored before the ratcheted key, so a message can't get lost in between. the key
+        // is unique for every message, so storing it again when the message gets redelivered doesn't duplicate it
+        let mut buf = BytesMut::new();
+        content.write(&mut buf)?;
+        self.messages.insert(message_key(&conversation.owner, content.sent, &msg.nonce), IVec::from(buf.to_vec()))?;
+        self.messages.flush()?;
+        self.insert(&conversation)?;
+        Ok(content)
+    }
+
+    /// opens a message that was sealed with one of the access keys we handed out and decrypts it
+    pub fn unseal(&self, key_id: &Uuid, raw: &Encrypted<DirectMessageContent>, own_key: &Rsa<Private>) -> anyhow::Result<DirectMessageContent> {
+        let owner = UserUuid::from_u256(uuid_from_pub_key(&own_key.public_key_to_der()?));
+        for entry in self.db.iter() {
+            let (_, val) = entry?;
+            let conversation = Conversation::read(&mut Bytes::from(val.to_vec()))?;
+            if conversation.owner != owner {
+                continue;
+            }
+            if let Some(issued) = conversation.issued_sealed_key.as_ref().filter(|issued| &issued.key_id == key_id) {
+                let content = self.decrypt(&Encrypted::new(issued.unseal(&raw.ciphertext)?), own_key)?;
+                // make sure a contact can't impersonate another one using their own access key
+                if content.sender != conversation.peer {
+                    return Err(anyhow::Error::from(ErrorInvalidMessage));
+                }
+                return Ok(content);
+            }
+        }
+        Err(anyhow::Error::from(ErrorUnknownConversation))
+    }
+}
+
+// the owner is used as a prefix so all messages of a profile can be found via a prefix scan,
+// the send time is stored in big endian so messages are iterated in the order they were sent
+fn message_key(owner: &UserUuid, sent: Duration, nonce: &[u8]) -> Vec<u8> {
+    let mut key = owner.into_u256().as_le_slice().to_vec();
+    key.extend_from_slice(&(sent.as_millis() as u64).to_be_bytes());
+    key.extend_from_slice(nonce);
+    key
+}
+
+pub struct ErrorInvalidMessage;
+
+impl Debug for ErrorInvalidMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the direct message is malformed")
+    }
+}
+
+impl Display for ErrorInvalidMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorInvalidMessage {}
+
+pub struct ErrorOutdatedKey;
+
+impl Debug for ErrorOutdatedKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the direct message was encrypted with a key that was already ratcheted away")
+    }
+}
+
+impl Display for ErrorOutdatedKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorOutdatedKey {}
+
+pub struct ErrorUnknownConversation;
+
+impl Debug for ErrorUnknownConversation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the direct message belongs to an unknown conversation")
+    }
+}
+
+impl Display for ErrorUnknownConversation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownConversation {}
+
+pub struct ErrorTooFarAhead;
+
+impl Debug for ErrorTooFarAhead {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the direct message was encrypted with a key that lies too many epochs ahead")
+    }
+}
+
+impl Display for ErrorTooFarAhead {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorTooFarAhead {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Peer {
+        key: Rsa<Private>,
+        pub_key: Vec<u8>,
+        uuid: UserUuid,
+    }
+
+    fn peer() -> Peer {
+        let key = Rsa::generate(2048).unwrap();
+        let pub_key = key.public_key_to_der().unwrap();
+        Peer {
+            uuid: UserUuid::from_u256(uuid_from_pub_key(&pub_key)),
+            key,
+            pub_key,
+        }
+    }
+
+    // returns the initiator's and the responder's side of a new conversation
+    fn conversation(alice: &Peer, bob: &Peer) -> (Conversation, Conversation) {
+        let initiator = Conversation::start(alice.uuid, bob.uuid, bob.pub_key.clone()).unwrap();
+        let (root, start) = Conversation::accept(initiator.key_init.as_ref().unwrap(), &bob.key).unwrap();
+        let responder = Conversation::new(initiator.id, bob.uuid, alice.uuid, alice.pub_key.clone(), &root, start, false, None);
+        (initiator, responder)
+    }
+
+    fn send(conversation: &mut Conversation, sender: &Peer, text: &str) -> DirectMessage {
+        let content = DirectMessageContent {
+            sender: sender.uuid,
+            sender_pub_key: sender.pub_key.clone(),
+            sent: current_time_millis(),
+            text: text.to_string(),
+            sealed_grant: None,
+        };
+        let encrypted = conversation.encrypt(&content, &sender.key).unwrap();
+        DirectMessage::read(&mut Bytes::from(encrypted.ciphertext)).unwrap()
+    }
+
+    #[test]
+    fn forged_epochs_are_rejected() {
+        let (alice, bob) = (peer(), peer());
+        let (mut initiator, mut responder) = conversation(&alice, &bob);
+        let mut forged = send(&mut initiator, &alice, "forged");
+        forged.header.epoch = u64::MAX;
+        assert!(responder.decrypt(&forged).unwrap_err().is::<ErrorTooFarAhead>());
+        let mut forged = send(&mut initiator, &alice, "forged");
+        forged.header.rehash_time = rehash_period(current_time_millis()) + REHASH_INTERVAL * 8;
+        assert!(responder.decrypt(&forged).unwrap_err().is::<ErrorInvalidMessage>());
+        let mut forged = send(&mut initiator, &alice, "forged");
+        forged.header.epoch = 1;
+        assert!(responder.decrypt(&forged).is_err());
+
+        // none of the forged messages changed our keys
+        let genuine = send(&mut initiator, &alice, "genuine");
+        assert_eq!(responder.decrypt(&genuine).unwrap().text, "genuine");
+        assert_eq!(responder.recv.epoch(), 0);
+    }
+
+    #[test]
+    fn messages_arriving_out_of_order_are_decrypted() {
+        let (alice, bob) = (peer(), peer());
+        let (mut initiator, mut responder) = conversation(&alice, &bob);
+        let mut messages = (0..=RATCHET_MESSAGES).map(|idx| send(&mut initiator, &alice, &idx.to_string())).collect::<Vec<_>>();
+        let last = messages.pop().unwrap();
+        assert_eq!(last.header.epoch, 1);
+
+        assert_eq!(responder.decrypt(&last).unwrap().text, RATCHET_MESSAGES.to_string());
+        assert_eq!(responder.decrypt(&messages[0]).unwrap().text, "0");
+        assert_eq!(responder.decrypt(&messages[1]).unwrap().text, "1");
+    }
+
+    #[test]
+    fn stored_keys_dont_reveal_earlier_epochs() {
+        let (alice, bob) = (peer(), peer());
+        let (mut initiator, mut responder) = conversation(&alice, &bob);
+        let messages = (0..=RATCHET_MESSAGES).map(|idx| send(&mut initiator, &alice, &idx.to_string())).collect::<Vec<_>>();
+        for msg in messages.iter() {
+            responder.decrypt(msg).unwrap();
+        }
+
+        // once the skipped keys are gone the first epoch can't be decrypted from the stored keys anymore
+        responder.skipped.clear();
+        let mut buf = BytesMut::new();
+        responder.write(&mut buf).unwrap();
+        let mut stolen = Conversation::read(&mut buf.freeze()).unwrap();
+        assert!(stolen.decrypt(&messages[0]).unwrap_err().is::<ErrorOutdatedKey>());
+    }
+}
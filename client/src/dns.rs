@@ -0,0 +1,268 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::{system_conf, TokioAsyncResolver};
+use quinn::ClientConfig;
+use crate::network::NetworkClient;
+
+pub const DEFAULT_PORT: u16 = 20354;
+// servers can announce their port (and host) via this srv record so users only have to enter a domain
+const SRV_SERVICE: &str = "_rustspeak._udp";
+
+pub struct ResolvedAddr {
+    pub addrs: Vec<SocketAddr>, // all addresses of the server, in the order they should be tried
+    pub host: String, // the name the server's certificate is checked against
+}
+
+/// creates a resolver that queries `name_server` or the system's name servers if none was provided,
+/// a custom name server allows pointing the client at a local stub resolver
+pub fn resolver(name_server: Option<SocketAddr>) -> anyhow::Result<TokioAsyncResolver> {
+    let (config, mut opts) = match name_server {
+        Some(name_server) => {
+            let mut config = ResolverConfig::new();
+            config.add_name_server(NameServerConfig::new(name_server, Protocol::Udp));
+            (config, ResolverOpts::default())
+        }
+        None => system_conf::read_system_conf()?,
+    };
+    // look up both address families so we can fall back to the other one if the server isn't reachable via one of them
+    opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+    Ok(TokioAsyncResolver::tokio(config, opts))
+}
+
+/// resolves `input` which has the form `host[:port]`, the host may be an ip address (v6 addresses have to be
+/// enclosed in brackets if a port is provided), if no port was provided the srv record of the host is used if there is one
+pub async fn resolve(resolver: &TokioAsyncResolver, input: &str) -> anyhow::Result<ResolvedAddr> {
+    let input = input.trim();
+    if let Ok(addr) = input.parse::<SocketAddr>() {
+        return Ok(ResolvedAddr { addrs: vec![addr], host: addr.ip().to_string() });
+    }
+    if let Ok(ip) = input.parse::<IpAddr>() {
+        return Ok(ResolvedAddr { addrs: vec![SocketAddr::new(ip, DEFAULT_PORT)], host: ip.to_string() });
+    }
+    let (host, port) = split_host_port(input)?;
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ResolvedAddr { addrs: vec![SocketAddr::new(ip, port.unwrap_or(DEFAULT_PORT))], host: ip.to_string() });
+    }
+    let (target, port) = match port {
+        Some(port) => (host.to_string(), port),
+        None => lookup_srv(resolver, host).await.unwrap_or_else(|| (host.to_string(), DEFAULT_PORT)),
+    };
+    let addrs = resolver.lookup_ip(target.as_str()).await?.iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(anyhow::Error::from(ErrorNoAddress(target)));
+    }
+    Ok(ResolvedAddr {
+        addrs,
+        host: host.to_string(),
+    })
+}
+
+/// connects to the first address of `resolved` that is reachable, the error of the last attempt is returned if none is
+pub async fn connect(config: ClientConfig, resolved: &ResolvedAddr) -> anyhow::Result<NetworkClient> {
+    let mut last_err = anyhow::Error::from(ErrorNoAddress(resolved.host.clone()));
+    for addr in resolved.addrs.iter() {
+        match NetworkClient::new(config.clone(), *addr, resolved.host.as_str()).await {
+            Ok(client) => return Ok(client),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// returns the target and port of the most preferred srv record of `host`
+async fn lookup_srv(resolver: &TokioAsyncResolver, host: &str) -> Option<(String, u16)> {
+    // a missing srv record is the common case, so lookup failures simply fall back to the host itself
+    let records = resolver.srv_lookup(format!("{}.{}", SRV_SERVICE, host)).await.ok()?;
+    let record = records.iter()
+        .min_by_key(|record| (record.priority(), u16::MAX - record.weight()))?;
+    Some((record.target().to_utf8(), record.port()))
+}
+
+fn split_host_port(input: &str) -> anyhow::Result<(&str, Option<u16>)> {
+    let invalid = || anyhow::Error::from(ErrorInvalidAddress(input.to_string()));
+    if let Some(rest) = input.strip_prefix('[') {
+        // [v6] or [v6]:port
+        let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+        return match rest.strip_prefix(':') {
+            Some(port) => Ok((host, Some(port.parse().map_err(|_| invalid())?))),
+            None if rest.is_empty() => Ok((host, None)),
+            None => Err(invalid()),
+        };
+    }
+    match input.split_once(':') {
+        Some((host, port)) if !host.is_empty() => Ok((host, Some(port.parse().map_err(|_| invalid())?))),
+        Some(_) => Err(invalid()),
+        None if !input.is_empty() => Ok((input, None)),
+        None => Err(invalid()),
+    }
+}
+
+pub struct ErrorInvalidAddress(String);
+
+impl Debug for ErrorInvalidAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("\"")?;
+        f.write_str(self.0.as_str())?;
+        f.write_str("\" is not a valid server address, use host[:port]")
+    }
+}
+
+impl Display for ErrorInvalidAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorInvalidAddress {}
+
+pub struct ErrorNoAddress(String);
+
+impl Debug for ErrorNoAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there is no address for ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorNoAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorNoAddress {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+    use hickory_resolver::proto::rr::rdata::{A, AAAA, SRV};
+    use hickory_resolver::proto::rr::{Name, RData, Record, RecordType};
+    use tokio::net::UdpSocket;
+    use super::*;
+
+    #[test]
+    fn split_bracketed_v6_with_port() {
+        assert_eq!(split_host_port("[::1]:4000").unwrap(), ("::1", Some(4000)));
+    }
+
+    #[test]
+    fn split_bracketed_v6() {
+        assert_eq!(split_host_port("[fe80::1]").unwrap(), ("fe80::1", None));
+    }
+
+    #[test]
+    fn split_host() {
+        assert_eq!(split_host_port("example.com").unwrap(), ("example.com", None));
+    }
+
+    #[test]
+    fn split_host_with_port() {
+        assert_eq!(split_host_port("example.com:4000").unwrap(), ("example.com", Some(4000)));
+    }
+
+    #[test]
+    fn split_invalid() {
+        for input in ["", ":4000", "example.com:", "example.com:port", "example.com:70000", "[::1", "[::1]4000", "[::1]:"] {
+            assert!(split_host_port(input).is_err(), "{} was accepted", input);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_ips() {
+        let resolver = resolver(Some(stub_name_server().await)).unwrap();
+        assert_eq!(resolve(&resolver, "[::1]:4000").await.unwrap().addrs, vec!["[::1]:4000".parse().unwrap()]);
+        assert_eq!(resolve(&resolver, "::1").await.unwrap().addrs, vec![SocketAddr::new(Ipv6Addr::LOCALHOST.into(), DEFAULT_PORT)]);
+        assert_eq!(resolve(&resolver, "127.0.0.1").await.unwrap().addrs, vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_PORT)]);
+    }
+
+    #[tokio::test]
+    async fn resolve_srv() {
+        let resolver = resolver(Some(stub_name_server().await)).unwrap();
+        let resolved = resolve(&resolver, "srv.test").await.unwrap();
+        // the certificate is still checked against the name the user entered, not the srv target
+        assert_eq!(resolved.host, "srv.test");
+        assert_eq!(resolved.addrs, vec![SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 4000)]);
+    }
+
+    #[tokio::test]
+    async fn resolve_explicit_port_skips_srv() {
+        let resolver = resolver(Some(stub_name_server().await)).unwrap();
+        let resolved = resolve(&resolver, "srv.test:5000").await.unwrap();
+        assert_eq!(resolved.addrs, vec![SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 5000)]);
+    }
+
+    #[tokio::test]
+    async fn resolve_all_addresses() {
+        let resolver = resolver(Some(stub_name_server().await)).unwrap();
+        let resolved = resolve(&resolver, "dual.test").await.unwrap();
+        assert_eq!(resolved.addrs.len(), 3);
+        assert!(resolved.addrs.contains(&SocketAddr::new(Ipv4Addr::new(10, 0, 0, 3).into(), DEFAULT_PORT)));
+        assert!(resolved.addrs.contains(&SocketAddr::new(Ipv4Addr::new(10, 0, 0, 4).into(), DEFAULT_PORT)));
+        assert!(resolved.addrs.contains(&SocketAddr::new(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 3).into(), DEFAULT_PORT)));
+    }
+
+    #[tokio::test]
+    async fn resolve_unknown_host() {
+        let resolver = resolver(Some(stub_name_server().await)).unwrap();
+        assert!(resolve(&resolver, "unknown.test").await.is_err());
+    }
+
+    /// starts a name server on localhost which knows a few records below `test.`
+    async fn stub_name_server() -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let Ok((len, sender)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                let Ok(query) = Message::from_vec(&buf[..len]) else {
+                    continue;
+                };
+                let response = stub_response(&query).to_vec().unwrap();
+                let _ = socket.send_to(&response, sender).await;
+            }
+        });
+        addr
+    }
+
+    fn stub_response(query: &Message) -> Message {
+        let mut response = Message::new();
+        response.set_id(query.id());
+        response.set_message_type(MessageType::Response);
+        response.set_op_code(query.op_code());
+        response.set_recursion_desired(query.recursion_desired());
+        response.set_recursion_available(true);
+        response.add_queries(query.queries().to_vec());
+        let mut known = false;
+        for question in query.queries() {
+            let name = question.name().to_ascii();
+            let name = name.trim_end_matches('.');
+            let rdata = match (name, question.query_type()) {
+                ("_rustspeak._udp.srv.test", RecordType::SRV) => vec![
+                    RData::SRV(SRV::new(10, 0, 4001, Name::from_ascii("backup.srv.test.").unwrap())),
+                    RData::SRV(SRV::new(0, 0, 4000, Name::from_ascii("voice.srv.test.").unwrap())),
+                ],
+                ("srv.test", RecordType::A) => vec![RData::A(A::new(10, 0, 0, 1))],
+                ("voice.srv.test", RecordType::A) => vec![RData::A(A::new(10, 0, 0, 2))],
+                ("dual.test", RecordType::A) => vec![RData::A(A::new(10, 0, 0, 3)), RData::A(A::new(10, 0, 0, 4))],
+                ("dual.test", RecordType::AAAA) => vec![RData::AAAA(AAAA::new(0xfd00, 0, 0, 0, 0, 0, 0, 3))],
+                _ => vec![],
+            };
+            known |= matches!(name, "srv.test" | "voice.srv.test" | "dual.test" | "_rustspeak._udp.srv.test");
+            for rdata in rdata {
+                response.add_answer(Record::from_rdata(question.name().clone(), 60, rdata));
+            }
+        }
+        if !known {
+            response.set_response_code(ResponseCode::NXDomain);
+        }
+        response
+    }
+}
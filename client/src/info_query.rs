@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use quinn::ClientConfig;
+use crate::anonymous;
+use crate::packet::{ClientPacket, ServerInfo, ServerPacket};
+
+pub struct QueriedInfo {
+    pub info: ServerInfo,
+    pub ping: Duration, // the time it took the server to answer our query
+}
+
+/// asks the server at `addr` for its info without logging in, `config` has to negotiate the anonymous protocol.
+/// servers limit how often an address may query them, so queries may fail if they are repeated too quickly
+pub async fn query_info(config: ClientConfig, name_server: Option<SocketAddr>, addr: &str) -> anyhow::Result<QueriedInfo> {
+    let connection = anonymous::connect(config, name_server, addr).await?;
+    let start = Instant::now();
+    let response = anonymous::exchange(&connection, &ClientPacket::InfoRequest).await;
+    let ping = start.elapsed();
+    let _ = connection.close().await;
+    match response? {
+        ServerPacket::ServerInfo(info) => Ok(QueriedInfo {
+            info,
+            ping,
+        }),
+        _ => Err(anyhow::Error::from(ErrorNoServerInfo(addr.to_string()))),
+    }
+}
+
+pub struct ErrorNoServerInfo(String);
+
+impl Debug for ErrorNoServerInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.as_str())?;
+        f.write_str(" didn't answer our info query with its info")
+    }
+}
+
+impl Display for ErrorNoServerInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorNoServerInfo {}
@@ -4,16 +4,18 @@
 
 extern crate core;
 
-use crate::command::CommandProfiles;
+use crate::command::{CommandChannelGroup, CommandContacts, CommandCreateChannel, CommandDirectMessage, CommandDirectMessages, CommandPoke, CommandProfiles, CommandRedeemToken, CommandSealedGrant, CommandServerGroup, CommandServers, CommandSubscribe};
+use crate::conversation::ConversationDb;
 use crate::config::{Config, DATA_DIR_PATH, CONFIG_FILE, data_path};
-use crate::network::{AddressMode, NetworkClient};
+use crate::network::NetworkClient;
 use crate::packet::{Channel, ClientPacket};
 use crate::profile::Profile;
-use crate::protocol::{PROTOCOL_VERSION, RWBytes};
+use crate::protocol::{FEATURES, RWBytes, UserUuid, VersionRange};
 use crate::profile_db::{DbProfile, ProfileDb, uuid_from_pub_key};
 use crate::utils::current_time_millis;
 use bytes::{Bytes, BytesMut};
 use clitty::core::{CLICore, CmdParamStrConstraints, CommandBuilder, CommandParam, CommandParamTy, UsageBuilder};
+use std::num::NonZeroUsize;
 use clitty::ui::{CLIBuilder, CmdLineInterface, PrintFallback};
 use quinn::ClientConfig;
 use tokio::sync::RwLock;
@@ -34,8 +36,11 @@ use crate::server::Server;
 use swap_arc::{SwapArc, SwapArcOption};
 use crate::ui::{InterUiMessage, UiImpl};
 
+mod anonymous;
 mod certificate;
 mod config;
+mod dns;
+mod info_query;
 mod network;
 mod packet;
 mod profile;
@@ -46,12 +51,15 @@ mod utils;
 mod server;
 mod command;
 mod audio;
+mod conversation;
+mod sealed_sender;
 pub mod data_structures;
 mod ui;
 
 // FIXME: review all the endianness related shit!
 
 const RELATIVE_PROFILE_DB_PATH: &str = "user_db";
+const RELATIVE_CONVERSATION_DB_PATH: &str = "conversation_db";
 const VOICE_THRESHOLD: i16 = 50/*100*/; // 0-6 even occurs in idle (if nobody is near the input device)
 
 const MIN_BUF_SIZE: usize = 480;
@@ -68,7 +76,7 @@ async fn main() -> anyhow::Result<()> {
             println!("{:?}", cfg);
         }   
     }
-    let (cfg, profile_db) = load_data()?;
+    let (cfg, profile_db, conversation_db) = load_data()?;
     println!("loaded config!");
     let cfg = Arc::new(SwapArc::new(Arc::new(cfg)));
     let profile_db = Arc::new(profile_db);
@@ -82,9 +90,106 @@ async fn main() -> anyhow::Result<()> {
         }).optional(CommandParam { // FIXME: add ability to make following arguments depend on the value of the previous argument (maybe by integrating the following arguments into the variants list)
             name: "name",
             ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("contacts", CommandContacts()).desc("manage the contacts of a profile")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "action",
+            ty: CommandParamTy::String(CmdParamStrConstraints::Variants { variants: &["list", "add", "remove"], ignore_case: true }),
+        }).optional(CommandParam {
+            name: "profile",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).optional(CommandParam {
+            name: "nickname",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).optional(CommandParam {
+            name: "key", // either a path to a file containing the contact's public key in PEM format or the contact's uuid in hex
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).optional(CommandParam {
+            name: "home_server",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("poke", CommandPoke()).desc("pokes a user to get their attention")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "user",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).optional(CommandParam {
+            name: "text",
+            ty: CommandParamTy::Unbound { minimum: NonZeroUsize::new(1).unwrap(), param: Box::new(CommandParamTy::String(CmdParamStrConstraints::None)) },
+        })))
+        .command(CommandBuilder::new("createchannel", CommandCreateChannel()).desc("creates a channel and moves you into it")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "name",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).required(CommandParam {
+            name: "lifetime",
+            ty: CommandParamTy::String(CmdParamStrConstraints::Variants { variants: &["permanent", "semipermanent", "temporary"], ignore_case: true }),
+        }).optional(CommandParam {
+            name: "parent",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("channelgroup", CommandChannelGroup()).desc("assigns a channel group to a user in a channel")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "channel",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).required(CommandParam {
+            name: "user",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).required(CommandParam {
+            name: "group", // the name of the channel group or "none"
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("channelsub", CommandSubscribe()).desc("subscribes to a channel to see its users or unsubscribes from it")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "action",
+            ty: CommandParamTy::String(CmdParamStrConstraints::Variants { variants: &["subscribe", "unsubscribe"], ignore_case: true }),
+        }).required(CommandParam {
+            name: "channel",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("redeem", CommandRedeemToken()).desc("redeems a privilege token to get the group it grants")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "token",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("servergroup", CommandServerGroup()).desc("creates, renames, deletes and assigns server groups")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "action",
+            ty: CommandParamTy::String(CmdParamStrConstraints::Variants { variants: &["create", "rename", "delete", "assign", "unassign"], ignore_case: true }),
+        }).required(CommandParam {
+            name: "group",
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).optional(CommandParam {
+            name: "arg", // the new name when renaming or the user when assigning and unassigning
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("dm", CommandDirectMessage()).desc("sends an end-to-end encrypted direct message")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "receiver_key", // a path to a file containing the receiver's public key in PEM format
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        }).required(CommandParam {
+            name: "message",
+            ty: CommandParamTy::Unbound { minimum: NonZeroUsize::new(1).unwrap(), param: Box::new(CommandParamTy::String(CmdParamStrConstraints::None)) },
+        })))
+        .command(CommandBuilder::new("dms", CommandDirectMessages()).desc("shows the direct messages a user sent us")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "sender_key", // a path to a file containing the sender's public key in PEM format
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("sealgrant", CommandSealedGrant()).desc("allows a contact to message you without your home server learning who they are")
+        .params(UsageBuilder::new().required(CommandParam {
+            name: "receiver_key", // a path to a file containing the contact's public key in PEM format
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
+        })))
+        .command(CommandBuilder::new("servers", CommandServers()).desc("lists the servers we are connected to, switches between them, picks the voice server or leaves one")
+        .params(UsageBuilder::new().optional(CommandParam {
+            name: "action",
+            ty: CommandParamTy::String(CmdParamStrConstraints::Variants { variants: &["list", "switch", "voice", "disconnect"], ignore_case: true }),
+        }).optional(CommandParam {
+            name: "server", // the server's name or its position in the list
+            ty: CommandParamTy::String(CmdParamStrConstraints::None),
         }))).build();
     let cli = Arc::new(CmdLineInterface::new(cli));
-    let client = Arc::new(Client {config:cfg.clone(),profile_db:profile_db.clone(),cli,audio:SwapArcOption::new(AudioConfig::new()?.map(|cfg|Audio::from_cfg(&cfg).unwrap()).flatten().map(|audio|Arc::new(audio))),inter_ui_msg_queue:ui::ui_queue(UI), servers: RwLock::new(vec![]), voice_server: SwapArcOption::empty() });
+    let client = Arc::new(Client {config:cfg.clone(),profile_db:profile_db.clone(),conversation_db:Arc::new(conversation_db),cli,audio:SwapArcOption::new(AudioConfig::new()?.map(|cfg|Audio::from_cfg(&cfg).unwrap()).flatten().map(|audio|Arc::new(audio))),inter_ui_msg_queue:ui::ui_queue(UI), servers: RwLock::new(vec![]), voice_server: SwapArcOption::empty(), active_server: SwapArcOption::empty() });
 
     let tmp = client.clone();
     thread::spawn(move || {
@@ -112,8 +217,9 @@ async fn main() -> anyhow::Result<()> {
                     let server = server.clone();
                     let has_err_rec = has_err.clone();
                     let glob_buf = Arc::new(Mutex::new(vec![]));
-                    if let Some(audio) = client.audio.load().as_ref() {
-                        let stream = audio.start_record(move |data, input| {
+                    let rec_server = server.clone();
+                    let stream = client.audio.load().as_ref().map(|audio| {
+                        audio.start_record(move |data, input| {
                             // println!("recorded!");
                             let has_err = &has_err_rec;
                             // FIXME: handle endianness of `data`
@@ -132,10 +238,15 @@ async fn main() -> anyhow::Result<()> {
                                     if glob_buf.len() >= MIN_BUF_SIZE {
                                         let mut buffer = vec![0; 2048];
                                         // println!("max diff: {}", max_diff);
-                                        let tmp = client.voice_server.load();
-                                        let tmp_conn = tmp.as_ref().unwrap().connection.get();
+                                        let server = &rec_server;
+                                        let tmp_conn = server.connection.load_full();
                                         let data = server.audio.as_ref().unwrap().encode(&glob_buf.as_slice()[0..MIN_BUF_SIZE], &mut buffer);
                                         glob_buf.drain(0..MIN_BUF_SIZE);
+                                        if !server.state.is_connected() {
+                                            // stop recording until we are reconnected
+                                            has_err.store(true, Ordering::Release);
+                                            return;
+                                        }
                                         if let Err(err) = pollster::block_on(tmp_conn.unwrap().send_unreliable::<2>(Bytes::copy_from_slice(buffer.as_slice()).slice(0..data))) {
                                             pollster::block_on(server.error(err, &client));
                                             has_err.store(true, Ordering::Release);
@@ -146,12 +257,15 @@ async fn main() -> anyhow::Result<()> {
                                 }
                             }
                             // println!("send audio!");
-                        }).unwrap();
-                    }
+                        }).unwrap()
+                    });
 
-                    while !has_err.load(Ordering::Acquire) {
+                    // keep recording until an error occurs or the user picks another voice server
+                    while !has_err.load(Ordering::Acquire) && client.is_voice_server(&server) {
                         sleep(Duration::from_millis(1));
                     }
+                    // stop capturing before the stream for the next voice server gets started
+                    drop(stream);
             } else {
                 sleep(Duration::from_millis(1));
             }
@@ -165,7 +279,7 @@ async fn main() -> anyhow::Result<()> {
     Ok(ui::start_ui(client, UI)?)
 }
 
-fn load_data() -> anyhow::Result<(Config, ProfileDb)> {
+fn load_data() -> anyhow::Result<(Config, ProfileDb, ConversationDb)> {
     let config = Config::load_or_create()?;
     let data_dir = data_path();
     let profile_db = ProfileDb::new(
@@ -178,7 +292,14 @@ fn load_data() -> anyhow::Result<(Config, ProfileDb)> {
             Ok(vec![DbProfile::new(String::from("default"), String::from("RustSpeakUser")).unwrap()])
         }
     )?;
-    Ok((config, profile_db))
+    let conversation_db = ConversationDb::new(
+        data_dir
+            .join(RELATIVE_CONVERSATION_DB_PATH)
+            .to_str()
+            .unwrap()
+            .to_string(),
+    )?;
+    Ok((config, profile_db, conversation_db))
 }
 
 pub async fn start_connect_to(
@@ -187,7 +308,6 @@ pub async fn start_connect_to(
     profile: &Profile,
 ) -> anyhow::Result<NetworkClient> {
     let client = NetworkClient::new(
-        AddressMode::V4,
         certificate::insecure_local::config(),
         server_addr,
         server_name,
@@ -200,7 +320,8 @@ pub async fn start_connect_to(
     data.extend_from_slice(&ctm.subsec_nanos().to_le_bytes());
     let signed_data = profile.sign_data(&data)?;
     let auth_packet = ClientPacket::AuthRequest {
-        protocol_version: PROTOCOL_VERSION,
+        protocol_versions: VersionRange::SUPPORTED,
+        features: FEATURES,
         pub_key: profile.private_key().public_key_to_der()?,
         name: profile.name.clone(),
         security_proofs: vec![],
@@ -215,12 +336,14 @@ pub struct Client {
     pub config: Arc<SwapArc<Config>>,
     // FIXME: make this somehow mutable (maybe using an ArcSwap or a Mutex)
     pub profile_db: Arc<ProfileDb>,
+    pub conversation_db: Arc<ConversationDb>,
     // pub renderer: Arc<Renderer>,
     // pub screen_sys: Arc<ScreenSystem>,
     // pub atlas: Arc<Atlas>,
     pub cli: Arc<CmdLineInterface<Arc<Client>>>,
     pub servers: RwLock<Vec<Arc<Server>>>,
     pub voice_server: SwapArcOption<Server>, // the currently active voice server
+    pub active_server: SwapArcOption<Server>, // the server that is shown in the ui and receives our commands
     pub audio: SwapArcOption<Audio>,
     pub inter_ui_msg_queue: Box<dyn UiQueue>,
 }
@@ -235,6 +358,44 @@ impl Client {
         self.cli.println(msg);
     }
 
+    /// returns the names of all servers we are connected to on which `user` is currently online
+    pub async fn presence(&self, user: &UserUuid) -> Vec<String> {
+        self.servers.read().await.iter()
+            .filter(|server| server.state.is_connected() && server.clients.contains_key(user))
+            .map(|server| server.name.clone())
+            .collect()
+    }
+
+    /// returns the active server or any other server we are connected to if the active one isn't connected
+    pub fn active_server(&self) -> Option<Arc<Server>> {
+        let active = self.active_server.load();
+        if let Some(server) = active.as_ref().filter(|server| server.state.is_connected()) {
+            return Some(server.clone());
+        }
+        pollster::block_on(self.servers.read()).iter().find(|server| server.state.is_connected()).cloned()
+    }
+
+    pub fn is_voice_server(&self, server: &Arc<Server>) -> bool {
+        self.voice_server.load().as_ref().map_or(false, |voice_server| Arc::ptr_eq(voice_server, server))
+    }
+
+    pub fn is_active_server(&self, server: &Arc<Server>) -> bool {
+        self.active_server.load().as_ref().map_or(false, |active| Arc::ptr_eq(active, server))
+    }
+
+    /// leaves `server` for good and forgets about it, voice and the ui move on to another server
+    pub async fn remove_server(&self, server: &Arc<Server>) -> anyhow::Result<()> {
+        self.servers.write().await.retain(|other| !Arc::ptr_eq(other, server));
+        let next = self.servers.read().await.iter().find(|server| server.state.is_connected()).cloned();
+        if self.is_voice_server(server) {
+            self.voice_server.store(next.clone());
+        }
+        if self.is_active_server(server) {
+            self.active_server.store(next);
+        }
+        server.disconnect().await
+    }
+
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -19,12 +19,12 @@ pub struct NetworkClient {
 
 impl NetworkClient {
     pub async fn new(
-        address_mode: AddressMode,
         config: ClientConfig,
         server: SocketAddr,
         server_name: &str,
     ) -> anyhow::Result<Self> {
-        let endpoint = Endpoint::client(address_mode.local())?;
+        // bind to the address family of the server as a v4 socket can't reach a v6 server and vice versa
+        let endpoint = Endpoint::client(AddressMode::of(&server).local())?;
         let conn = endpoint.connect_with(config, server, server_name)?.await?;
         let (send, recv) = conn.open_bi().await?;
 
@@ -91,6 +91,9 @@ impl NetworkClient {
     }
 
     pub async fn close_with(&self, err_code: u32, reason: &[u8]) -> anyhow::Result<()> {
+        if let Some(keep_alive_handler) = self.keep_alive_handler.load().as_ref() {
+            keep_alive_handler.timer.abort();
+        }
         self.bi_conn.0.lock().await.finish().await?;
         self.connection
             .close(VarInt::from_u32(err_code), reason);
@@ -110,7 +113,9 @@ impl NetworkClient {
                 match maybe_err {
                     Ok(_) => {}
                     Err(err) => {
+                        // the connection is dead, so there is no point in trying again
                         err_handler(err);
+                        break;
                     }
                 }
                 interval.tick().await;
@@ -174,10 +179,17 @@ pub enum AddressMode {
 }
 
 impl AddressMode {
+    pub fn of(addr: &SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(_) => AddressMode::V4,
+            SocketAddr::V6(_) => AddressMode::V6,
+        }
+    }
+
     fn local(self) -> SocketAddr {
         match self {
-            AddressMode::V4 => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
-            AddressMode::V6 => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 0),
+            AddressMode::V4 => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            AddressMode::V6 => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
         }
     }
 }
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::mem::size_of;
+use std::net::SocketAddr;
 use std::sync::Mutex;
 use crate::RWBytes;
 use bytes::{Bytes, BytesMut};
@@ -12,6 +13,7 @@ use openssl::pkey::PKey;
 use openssl::sha::sha256;
 use serde_derive::{Deserialize, Serialize};
 use crate::profile::PRIVATE_KEY_LEN_BITS;
+use crate::protocol::UserUuid;
 use crate::security_level::{DEFAULT_SECURITY_LEVEL, generate_token_num};
 
 pub struct ProfileDb {
@@ -30,23 +32,13 @@ impl ProfileDb {
                 profile_db
             }
             Err(_) => {
-                let default = default()?.into_iter().map(|entry| RawDbProfile {
-                    name: entry.name,
-                    alias: entry.alias,
-                    priv_key: entry.priv_key,
-                    security_proofs: entry.security_proofs.into_iter().map(|entry| U256Container::new(entry)).collect::<Vec<_>>(),
-                }).collect::<Vec<_>>();
+                let default = default()?.iter().map(RawDbProfile::from_profile).collect::<Vec<_>>();
                 write(&path, &default)?;
                 default
             }
         };
         let internal_cache = result.clone();
-        let result = result.into_iter().map(|entry| DbProfile {
-            name: entry.name,
-            alias: entry.alias,
-            priv_key: entry.priv_key,
-            security_proofs: entry.security_proofs.into_iter().map(|entry| entry.unwrap()).collect::<Vec<_>>(),
-        }).collect::<Vec<_>>();
+        let result = result.into_iter().map(|entry| entry.into_profile()).collect::<Vec<_>>();
         Ok(Self {
             profiles: {
                 let mut profiles = DashMap::new();
@@ -60,19 +52,48 @@ impl ProfileDb {
         })
     }
 
+    /// inserts the profile or replaces the existing profile with the same name
     pub fn insert(&self, user: DbProfile) -> anyhow::Result<()> {
         let mut profiles = self.internal_cache.lock().unwrap();
-        profiles.push(RawDbProfile {
-            name: user.name.clone(),
-            alias: user.alias.clone(),
-            priv_key: user.priv_key.clone(),
-            security_proofs: user.security_proofs.iter().map(|entry| U256Container::new(entry.clone())).collect::<Vec<_>>(),
-        });
+        let raw = RawDbProfile::from_profile(&user);
+        match profiles.iter_mut().find(|profile| profile.name == user.name) {
+            Some(profile) => *profile = raw,
+            None => profiles.push(raw),
+        }
         write(&self.path, &profiles)?;
         self.profiles.insert(user.name.clone(), user);
         Ok(())
     }
 
+    /// adds a contact to the contact book of `profile` or replaces the one with the same nickname,
+    /// returns false if the profile doesn't exist
+    pub fn insert_contact(&self, profile: &str, contact: Contact) -> anyhow::Result<bool> {
+        let mut db_profile = match self.profiles.get(profile) {
+            Some(db_profile) => db_profile.value().clone(),
+            None => return Ok(false),
+        };
+        db_profile.contacts.retain(|entry| entry.nickname != contact.nickname);
+        db_profile.contacts.push(contact);
+        self.insert(db_profile)?;
+        Ok(true)
+    }
+
+    /// removes the contact with the given nickname from the contact book of `profile`,
+    /// returns false if there was no such contact
+    pub fn remove_contact(&self, profile: &str, nickname: &str) -> anyhow::Result<bool> {
+        let mut db_profile = match self.profiles.get(profile) {
+            Some(db_profile) => db_profile.value().clone(),
+            None => return Ok(false),
+        };
+        let len = db_profile.contacts.len();
+        db_profile.contacts.retain(|entry| entry.nickname != nickname);
+        if db_profile.contacts.len() == len {
+            return Ok(false);
+        }
+        self.insert(db_profile)?;
+        Ok(true)
+    }
+
     #[inline(always)]
     pub fn cache_ref(&self) -> &DashMap<String, DbProfile> {
         &self.profiles
@@ -97,6 +118,7 @@ pub struct DbProfile {
     pub alias: String, // this is the alias the server will see
     pub priv_key: Vec<u8>,
     pub security_proofs: Vec<U256>,
+    pub contacts: Vec<Contact>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -105,6 +127,76 @@ struct RawDbProfile {
     alias: String, // this is the alias the server will see
     priv_key: Vec<u8>,
     security_proofs: Vec<U256Container>,
+    #[serde(default)]
+    contacts: Vec<RawContact>,
+}
+
+impl RawDbProfile {
+    fn from_profile(profile: &DbProfile) -> Self {
+        Self {
+            name: profile.name.clone(),
+            alias: profile.alias.clone(),
+            priv_key: profile.priv_key.clone(),
+            security_proofs: profile.security_proofs.iter().map(|entry| U256Container::new(entry.clone())).collect::<Vec<_>>(),
+            contacts: profile.contacts.iter().map(|contact| RawContact {
+                nickname: contact.nickname.clone(),
+                uuid: U256Container::new(contact.uuid.into_u256()),
+                pub_key: contact.pub_key.clone(),
+                home_server: contact.home_server,
+            }).collect::<Vec<_>>(),
+        }
+    }
+
+    fn into_profile(self) -> DbProfile {
+        DbProfile {
+            name: self.name,
+            alias: self.alias,
+            priv_key: self.priv_key,
+            security_proofs: self.security_proofs.into_iter().map(|entry| entry.unwrap()).collect::<Vec<_>>(),
+            contacts: self.contacts.into_iter().map(|contact| Contact {
+                nickname: contact.nickname,
+                uuid: UserUuid::from_u256(contact.uuid.unwrap()),
+                pub_key: contact.pub_key,
+                home_server: contact.home_server,
+            }).collect::<Vec<_>>(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Contact {
+    pub nickname: String, // the name under which we know this contact, this is unique per profile
+    pub uuid: UserUuid,
+    pub pub_key: Option<Vec<u8>>, // required to send direct messages to the contact
+    pub home_server: Option<SocketAddr>,
+}
+
+impl Contact {
+    pub fn from_pub_key(nickname: String, pub_key: Vec<u8>, home_server: Option<SocketAddr>) -> Self {
+        Self {
+            nickname,
+            uuid: UserUuid::from_u256(uuid_from_pub_key(&pub_key)),
+            pub_key: Some(pub_key),
+            home_server,
+        }
+    }
+
+    pub fn from_uuid(nickname: String, uuid: UserUuid, home_server: Option<SocketAddr>) -> Self {
+        Self {
+            nickname,
+            uuid,
+            pub_key: None,
+            home_server,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RawContact {
+    nickname: String,
+    uuid: U256Container,
+    pub_key: Option<Vec<u8>>,
+    home_server: Option<SocketAddr>,
 }
 
 pub fn uuid_from_pub_key(pub_key: &[u8]) -> U256 {
@@ -124,6 +216,7 @@ impl DbProfile {
             alias,
             priv_key: priv_key.private_key_to_der()?,
             security_proofs: proofs,
+            contacts: vec![],
         })
     }
 
@@ -11,7 +11,106 @@ use std::time::Duration;
 use uuid::Uuid;
 use crate::profile_db::U256Container;
 
-pub const PROTOCOL_VERSION: u64 = 1;
+/// the newest protocol version we speak, it gets bumped whenever packets change in an incompatible way
+pub const PROTOCOL_VERSION: u64 = 3; // 3 added info queries
+/// the oldest protocol version we still speak, the client and the server agree on the
+/// highest version both of them support so they don't have to be upgraded at the same time
+pub const MIN_PROTOCOL_VERSION: u64 = 2;
+/// the optional features we support, additions to the protocol that don't break older
+/// peers get a feature flag instead of a new protocol version
+pub const FEATURES: Features = Features::NONE;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VersionRange {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl VersionRange {
+    pub const SUPPORTED: Self = Self {
+        min: MIN_PROTOCOL_VERSION,
+        max: PROTOCOL_VERSION,
+    };
+
+    /// returns the highest version contained in both ranges or none if they don't overlap
+    pub fn negotiate(&self, other: &VersionRange) -> Option<u64> {
+        let version = self.max.min(other.max);
+        if version >= self.min.max(other.min) {
+            Some(version)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for VersionRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+impl RWBytes for VersionRange {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let min = src.get_u64_le();
+        let max = src.get_u64_le();
+        Ok(Self { min, max })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u64_le(self.min);
+        dst.put_u64_le(self.max);
+        Ok(())
+    }
+}
+
+/// a set of optional protocol features, unknown flags are simply ignored
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Features(u64);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+
+    #[inline]
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    #[inline]
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// returns the features supported by both sides, only these may be used
+    #[inline]
+    pub const fn intersect(self, other: Features) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl RWBytes for Features {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        Ok(Self(src.get_u64_le()))
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u64_le(self.0);
+        Ok(())
+    }
+}
 
 #[derive(Copy, Clone, Hash, Ord, PartialOrd, Eq, PartialEq, Debug)]
 #[repr(transparent)]
@@ -0,0 +1,191 @@
+// sealed sender, hides who sent a direct message from the receiver's home server
+//
+// the receiver hands out two temporary keys to each of their contacts through an already
+// established conversation: an access key whose public half gets registered with the home server
+// so it can check that the sender may message the receiver at all and a content key which is only
+// known to the two parties and encrypts the whole message including the conversation header
+
+use std::time::Duration;
+use bytes::{BufMut, Bytes, BytesMut};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::sign::Signer;
+use openssl::symm::{Cipher, decrypt_aead, encrypt_aead};
+use uuid::Uuid;
+use crate::conversation::{KEY_LEN, NONCE_LEN, TAG_LEN, read_key};
+use crate::packet::SealedAccessKey;
+use crate::protocol::RWBytes;
+use crate::utils::current_time_millis;
+
+pub const SEALED_KEY_VALIDITY: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+const ACCESS_KEY_LEN_BITS: u32 = 2048;
+
+/// creates a new pair of temporary keys, the access key has to be registered with our home
+/// server, the issued key has to be kept by us and the grant has to be sent to the contact
+pub fn issue(validity: Duration) -> anyhow::Result<(SealedAccessKey, IssuedSealedKey, SealedSenderGrant)> {
+    let access_key = Rsa::generate(ACCESS_KEY_LEN_BITS)?;
+    let mut content_key = [0; KEY_LEN];
+    openssl::rand::rand_bytes(&mut content_key)?;
+    let key_id = Uuid::new_v4();
+    let expiry = current_time_millis() + validity;
+
+    Ok((SealedAccessKey {
+        id: key_id,
+        pub_key: access_key.public_key_to_der()?,
+        expiry,
+    }, IssuedSealedKey {
+        key_id,
+        content_key,
+        expiry,
+    }, SealedSenderGrant {
+        key_id,
+        access_key: access_key.private_key_to_der()?,
+        content_key,
+        expiry,
+    }))
+}
+
+/// the keys a contact handed out to us, these allow us to message them without
+/// their home server learning who we are
+#[derive(Clone, Debug)]
+pub struct SealedSenderGrant {
+    pub key_id: Uuid,
+    access_key: Vec<u8>, // the private half of the access key, DER encoded
+    content_key: [u8; KEY_LEN],
+    pub expiry: Duration,
+}
+
+impl SealedSenderGrant {
+    pub fn is_expired(&self) -> bool {
+        self.expiry < current_time_millis()
+    }
+
+    /// encrypts `payload` with the content key and signs the result with the access key,
+    /// the result is what has to be handed to the peer's home server
+    pub fn seal(&self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce)?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = encrypt_aead(Cipher::aes_256_gcm(), &self.content_key, Some(&nonce), self.key_id.as_bytes(), payload, &mut tag)?;
+        let mut sealed = BytesMut::new();
+        SealedMessage {
+            nonce: nonce.to_vec(),
+            ciphertext,
+            tag: tag.to_vec(),
+        }.write(&mut sealed)?;
+
+        // the server verifies the signature over the payload exactly as it is encoded on the wire
+        let mut encoded = BytesMut::new();
+        sealed.to_vec().write(&mut encoded)?;
+        let pkey = PKey::private_key_from_der(&self.access_key)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+        let signature = signer.sign_oneshot_to_vec(&encoded)?;
+
+        let mut buf = BytesMut::new();
+        signature.write(&mut buf)?;
+        buf.put(encoded);
+        Ok(buf.to_vec())
+    }
+}
+
+impl RWBytes for SealedSenderGrant {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let key_id = Uuid::read(src)?;
+        let access_key = Vec::<u8>::read(src)?;
+        let content_key = read_key(src)?;
+        let expiry = Duration::read(src)?;
+
+        Ok(Self {
+            key_id,
+            access_key,
+            content_key,
+            expiry,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.key_id.write(dst)?;
+        self.access_key.write(dst)?;
+        self.content_key.to_vec().write(dst)?;
+        self.expiry.write(dst)?;
+
+        Ok(())
+    }
+}
+
+/// the counterpart of a grant we handed out, used to open the messages that were sealed with it
+#[derive(Clone)]
+pub struct IssuedSealedKey {
+    pub key_id: Uuid,
+    content_key: [u8; KEY_LEN],
+    pub expiry: Duration,
+}
+
+impl IssuedSealedKey {
+    pub fn is_expired(&self) -> bool {
+        self.expiry < current_time_millis()
+    }
+
+    pub fn unseal(&self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let sealed = SealedMessage::read(&mut Bytes::copy_from_slice(payload))?;
+        Ok(decrypt_aead(Cipher::aes_256_gcm(), &self.content_key, Some(&sealed.nonce), self.key_id.as_bytes(), &sealed.ciphertext, &sealed.tag)?)
+    }
+}
+
+impl RWBytes for IssuedSealedKey {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let key_id = Uuid::read(src)?;
+        let content_key = read_key(src)?;
+        let expiry = Duration::read(src)?;
+
+        Ok(Self {
+            key_id,
+            content_key,
+            expiry,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.key_id.write(dst)?;
+        self.content_key.to_vec().write(dst)?;
+        self.expiry.write(dst)?;
+
+        Ok(())
+    }
+}
+
+struct SealedMessage {
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>, // contains an encrypted `DirectMessage`
+    tag: Vec<u8>,
+}
+
+impl RWBytes for SealedMessage {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let nonce = Vec::<u8>::read(src)?;
+        let ciphertext = Vec::<u8>::read(src)?;
+        let tag = Vec::<u8>::read(src)?;
+
+        Ok(Self {
+            nonce,
+            ciphertext,
+            tag,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.nonce.write(dst)?;
+        self.ciphertext.write(dst)?;
+        self.tag.write(dst)?;
+
+        Ok(())
+    }
+}
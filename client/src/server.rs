@@ -1,35 +1,67 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
-use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::time::Duration;
+use arc_swap::ArcSwapOption;
 use bytes::Buf;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use opus::{Application, Channels, Decoder, Encoder};
 use swap_arc::SwapArc;
 use tokio::sync::Mutex;
 use uuid::Uuid;
-use crate::{AddressMode, Channel, Client, ClientConfig, ClientPacket, NetworkClient, Profile, PROTOCOL_VERSION, RWBytes};
+use crate::{Channel, Client, ClientConfig, ClientPacket, NetworkClient, Profile, RWBytes};
 use crate::audio::{AudioMode, SAMPLE_RATE};
 use crate::data_structures::byte_buf_ring::BBRing;
-use crate::data_structures::conc_once_cell::ConcurrentOnceCell;
+use crate::anonymous;
+use crate::dns;
 use crate::ui::InterUiMessage;
-use crate::packet::{AuthResponse, ChannelSubClientUpdate, ChannelSubUpdate, ChannelUpdate, GroupPerms, RemoteProfile, ServerPacket};
-use crate::protocol::UserUuid;
+use crate::conversation::{Conversation, DirectMessageContent};
+use crate::sealed_sender::{IssuedSealedKey, SEALED_KEY_VALIDITY, SealedSenderGrant};
+use crate::sealed_sender;
+use crate::packet::{AssignChannelGroupResponse, AuthResponse, ChannelGroup, ChannelGroupAssignment, ChannelLifetime, ClientUpdateServerGroups, CreateChannelResponse, ChannelSubClientUpdate, ChannelSubUpdate, ChannelUpdate, DisconnectReason, Perms, PokeResponse, RedeemTokenResponse, RegisterSealedKeysResponse, RemoteProfile, ServerGroupEdit, ServerGroupResponse, ServerGroupUpdate, ServerPacket, StoreMessageResponse};
+use crate::profile_db::uuid_from_pub_key;
+use crate::protocol::{Features, UserUuid, VersionRange, FEATURES};
+use crate::utils::current_time_millis;
 
 pub struct Server {
     pub profile: Profile,
-    pub connection: ConcurrentOnceCell<Arc<NetworkClient>>,
-    pub default_channel: ConcurrentOnceCell<Uuid>,
+    pub connection: ArcSwapOption<NetworkClient>, // gets replaced whenever we reconnect
+    connect_params: ConnectParams,
+    reconnect: AtomicBool, // false once the server told us not to come back
+    reconnect_attempts: AtomicU32, // the attempts since we were last logged in, 0 if we aren't reconnecting
+    pub default_channel: SwapArc<Uuid>, // the server may change its default channel at any time
     pub channels: SwapArc<HashMap<Uuid, Channel>>,
     pub channels_by_name: SwapArc<HashMap<String, Uuid>>, // FIXME: maintain this!
     pub groups: DashMap<Uuid, Arc<ServerGroup>>,
+    pub channel_groups: DashMap<Uuid, Arc<ChannelGroup>>,
     pub clients: DashMap<UserUuid, ConnectedRemoteProfile>,
+    pub subscribed_channels: DashSet<Uuid>, // we only know the clients of channels we are subscribed to
+    pub active_perms: SwapArc<Option<Perms>>, // none until the server told us about our perms
+    pub inbox: DashMap<u64, DirectMessageContent>, // messages that were delivered by the home server
     pub state: ServerState,
+    pub protocol_version: AtomicU64, // the version we agreed on with the server, 0 until we are logged in
+    features: AtomicU64, // the optional features both the server and we support
     pub name: String,
     pub audio: Option<Arc<ServerAudio>>,
 }
 
+struct ConnectParams {
+    addr: String,
+    config: ClientConfig,
+    anonymous_config: ClientConfig, // used for requests the server mustn't be able to link to us
+    channel: Option<Uuid>,
+    channel_password: Option<String>,
+}
+
+// the delay before the first reconnect attempt, it doubles with each failed attempt
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_BASE_DELAY.saturating_mul(1 << attempt.min(16)).min(RECONNECT_MAX_DELAY)
+}
+
 pub struct ServerAudio {
     pub buffer: BBRing<2>,
     encoder: std::sync::Mutex<Encoder>,
@@ -58,23 +90,41 @@ impl ServerAudio {
 }
 
 impl Server {
-    pub fn new(client: Arc<Client>, profile: Profile, address_mode: AddressMode,
+    pub fn new(client: Arc<Client>, profile: Profile,
                config: ClientConfig,
-               server_addr: SocketAddr,
-               server_name: String) -> Arc<Self> {
+               anonymous_config: ClientConfig,
+               server_addr: String, // host[:port], gets resolved before connecting
+               server_name: String,
+               channel: Option<Uuid>, // the channel to log into, none for our last or the default channel
+               channel_password: Option<String>) -> Arc<Self> {
         let channels = client.audio.load().as_ref().map(|audio| match audio.config().get().0.unwrap() {
             AudioMode::Mono => Channels::Mono,
             AudioMode::Stereo => Channels::Stereo,
         });
         let server = Arc::new(Self {
-            profile: profile.clone(),
-            connection: ConcurrentOnceCell::new(),
-            default_channel: ConcurrentOnceCell::new(),
+            profile,
+            connection: ArcSwapOption::empty(),
+            connect_params: ConnectParams {
+                addr: server_addr,
+                config,
+                anonymous_config,
+                channel,
+                channel_password,
+            },
+            reconnect: AtomicBool::new(true),
+            reconnect_attempts: AtomicU32::new(0),
+            default_channel: SwapArc::new(Arc::new(Uuid::nil())),
             channels: Default::default(),
             channels_by_name: Default::default(),
             groups: DashMap::new(),
+            channel_groups: DashMap::new(),
             clients: Default::default(),
+            subscribed_channels: Default::default(),
+            active_perms: SwapArc::new(Arc::new(None)),
+            inbox: Default::default(),
             state: ServerState::new(),
+            protocol_version: AtomicU64::new(0),
+            features: AtomicU64::new(Features::NONE.bits()),
             name: server_name.clone(),
             audio: channels.map(|channels| Arc::new(ServerAudio {
                 buffer: BBRing::new(8096),
@@ -83,96 +133,262 @@ impl Server {
             })),
         });
 
-        let priv_key = profile.private_key();
-        let pub_key = priv_key.public_key_to_der().expect("The profile's cryptographic key is invalid");
-
         let result = server.clone();
         tokio::spawn(async move {
-            match NetworkClient::new(address_mode, config, server_addr, server_name.as_str()).await {
-                Ok(network_client) => {
-                    server.connection.try_init_silent(Arc::new(network_client)).unwrap();
-
-                    server.state.try_set_auth();
-
-                    // now we have to handle auth stuff
-                    let auth_packet = ClientPacket::AuthRequest {
-                        protocol_version: PROTOCOL_VERSION,
-                        pub_key,
-                        name: profile.name,
-                        security_proofs: profile.security_proofs,
-                        signed_data: vec![], // FIXME: sign current time!
-                    };
-                    let mut buf = auth_packet.encode().unwrap();
-                    let tmp_server = server
-                        .connection.get();
-                    tmp_server.unwrap()
-                        .send_reliable(&mut buf)
-                        .await
-                        .unwrap();
-                    let tmp_server = server.clone();
-
-                    // setup packet reader
-                    tokio::spawn(async move {
-                        let server = tmp_server;
-                        'end: loop {
-                            let tmp_conn = server.connection.get();
-                            match tmp_conn.unwrap().read_reliable(8).await {
-                                Ok(mut size) => {
-                                    println!("got packet header!");
-                                    let size = size.get_u64_le();
-                                    let mut payload = match tmp_conn.as_ref().unwrap().read_reliable(size as usize).await {
-                                        Ok(payload) => payload,
-                                        Err(err) => {
-                                            server.error(err, &client).await;
-                                            break 'end;
-                                        }
-                                    };
-                                    let packet = ServerPacket::read(&mut payload);
-                                    match packet {
-                                        Ok(packet) => {
-                                            println!("handle packet: {:?}", packet);
-                                            handle_packet(packet, &client, &server).await;
-                                        }
-                                        Err(err) => {
-                                            server.error(err, &client).await;
-                                            break 'end;
-                                        }
-                                    }
-                                }
-                                Err(err) => {
-                                    server.error(err, &client).await;
-                                    break 'end;
-                                }
-                            }
-                        }
-                    });
-                }
-                Err(_) => {
-                    client.inter_ui_msg_queue.send(InterUiMessage::Error(server.clone(), format!("Failed connecting with \"{}\"", &server_name)));
+            if let Err(err) = server.connect(&client, channel).await {
+                client.inter_ui_msg_queue.send(InterUiMessage::Error(server.clone(), format!("Failed connecting with \"{}\": {}", &server_name, err)));
+            }
+        });
+        result
+    }
+
+    /// connects to the server and logs in, `channel` is the channel we want to log into
+    async fn connect(self: &Arc<Self>, client: &Arc<Client>, channel: Option<Uuid>) -> anyhow::Result<()> {
+        let resolver = dns::resolver(client.config.load().dns_server)?;
+        let resolved = dns::resolve(&resolver, self.connect_params.addr.as_str()).await?;
+        let connection = Arc::new(dns::connect(self.connect_params.config.clone(), &resolved).await?);
+        self.connection.store(Some(connection.clone()));
+
+        self.state.try_set_auth();
+
+        // now we have to handle auth stuff
+        let auth_packet = ClientPacket::AuthRequest {
+            protocol_versions: VersionRange::SUPPORTED,
+            features: FEATURES,
+            pub_key: self.profile.private_key().public_key_to_der().expect("The profile's cryptographic key is invalid"),
+            name: self.profile.name.clone(),
+            security_proofs: self.profile.security_proofs.clone(),
+            signed_data: vec![], // FIXME: sign current time!
+            channel,
+            channel_password: self.connect_params.channel_password.clone(),
+        };
+        connection.send_reliable(&auth_packet.encode()?).await?;
+
+        let server = self.clone();
+        let client = client.clone();
+        // setup packet reader
+        tokio::spawn(async move {
+            let err = loop {
+                let mut size = match connection.read_reliable(8).await {
+                    Ok(size) => size,
+                    Err(err) => break err,
+                };
+                println!("got packet header!");
+                let size = size.get_u64_le();
+                let mut payload = match connection.read_reliable(size as usize).await {
+                    Ok(payload) => payload,
+                    Err(err) => break err,
+                };
+                match ServerPacket::read(&mut payload) {
+                    Ok(packet) => {
+                        println!("handle packet: {:?}", packet);
+                        handle_packet(packet, &client, &server).await;
+                    }
+                    Err(err) => break err,
                 }
+            };
+            if server.is_current(&connection) {
+                server.error(err, &client).await;
             }
+        });
+        Ok(())
+    }
+
+    /// whether `connection` is the connection we currently use, errors of previous connections are ignored
+    fn is_current(&self, connection: &Arc<NetworkClient>) -> bool {
+        self.connection.load().as_ref().map_or(false, |curr| Arc::ptr_eq(curr, connection))
+    }
 
+    /// the optional features that may be used with this server
+    pub fn features(&self) -> Features {
+        Features::from_bits(self.features.load(Ordering::Acquire))
+    }
+
+    /// the address the user entered for this server
+    pub fn addr(&self) -> &str {
+        self.connect_params.addr.as_str()
+    }
+
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnect_attempts.load(Ordering::Acquire) > 0
+    }
+
+    /// tries to reconnect with an exponential backoff until we are logged in again or run out of attempts,
+    /// we rejoin the channel we were in before losing the connection
+    fn schedule_reconnect(self: &Arc<Self>, client: Arc<Client>) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let channel = server.clients.get(&server.profile.uuid()).map(|profile| profile.channel).or(server.connect_params.channel);
+            loop {
+                let attempt = server.reconnect_attempts.fetch_add(1, Ordering::AcqRel);
+                if attempt >= MAX_RECONNECT_ATTEMPTS {
+                    server.reconnect_attempts.store(0, Ordering::Release);
+                    client.inter_ui_msg_queue.send(InterUiMessage::Error(server.clone(), format!("Gave up reconnecting to \"{}\"", &server.name)));
+                    return;
+                }
+                let delay = reconnect_delay(attempt);
+                client.println(format!("Reconnecting to {} in {} seconds...", server.name, delay.as_secs()).as_str());
+                client.inter_ui_msg_queue.send(InterUiMessage::Reconnecting(server.clone()));
+                tokio::time::sleep(delay).await;
+                if !server.reconnect.load(Ordering::Acquire) || !server.state.try_set_pending() {
+                    return;
+                }
+                match server.connect(&client, channel).await {
+                    // if the new connection fails as well, we end up here again
+                    Ok(_) => return,
+                    Err(err) => {
+                        server.state.try_set_disconnected();
+                        client.println(format!("Failed reconnecting to {}: {}", server.name, err).as_str());
+                    }
+                }
+            }
         });
-        result
     }
 
-    pub async fn error(&self, err: anyhow::Error, client: &Arc<Client>) {
+    /// closes the connection and reconnects unless the server told us not to, `msg` tells the user why the connection was lost
+    async fn connection_lost(self: &Arc<Self>, client: &Arc<Client>, msg: String) {
         if self.state.try_set_disconnected() {
-            // FIXME: somehow give feedback to server
-            
             // ignore errors happening on close
-            let _ = self.connection.get().unwrap().close().await;
-            client.println(format!("An error occurred in the connection with {}: {}", self.name, err).as_str());
+            if let Some(connection) = self.connection.load_full() {
+                let _ = connection.close().await;
+            }
+            client.println(msg.as_str());
+            if self.reconnect.load(Ordering::Acquire) {
+                self.schedule_reconnect(client.clone());
+            }
+        }
+    }
+
+    /// leaves the server on our own behalf, we don't try to reconnect afterwards
+    pub async fn disconnect(&self) -> anyhow::Result<()> {
+        self.reconnect.store(false, Ordering::Release);
+        if self.state.try_set_disconnected() {
+            if let Some(connection) = self.connection.load_full() {
+                connection.send_reliable(&ClientPacket::Disconnect.encode()?).await?;
+                connection.close().await?;
+            }
         }
+        Ok(())
+    }
+
+    pub async fn error(self: &Arc<Self>, err: anyhow::Error, client: &Arc<Client>) {
+        // FIXME: somehow give feedback to server
+        self.connection_lost(client, format!("An error occurred in the connection with {}: {}", self.name, err)).await;
+    }
+
+    /// encrypts `text` end-to-end and hands it to the receiver's home server
+    pub async fn send_direct_message(&self, client: &Arc<Client>, receiver_pub_key: Vec<u8>, text: String) -> anyhow::Result<()> {
+        self.send_direct_message_with(client, receiver_pub_key, text, None).await
+    }
+
+    /// hands out a new pair of temporary keys to the owner of `receiver_pub_key`, this allows them
+    /// to send us messages without this server (our home server) learning who sent them
+    pub async fn grant_sealed_sender(&self, client: &Arc<Client>, receiver_pub_key: Vec<u8>) -> anyhow::Result<()> {
+        let (access_key, issued, grant) = sealed_sender::issue(SEALED_KEY_VALIDITY)?;
+        let packet = ClientPacket::RegisterSealedKeys { keys: vec![access_key] }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await?;
+        self.send_direct_message_with(client, receiver_pub_key, String::new(), Some((issued, grant))).await
+    }
+
+    async fn send_direct_message_with(&self, client: &Arc<Client>, receiver_pub_key: Vec<u8>, text: String, sealed_keys: Option<(IssuedSealedKey, SealedSenderGrant)>) -> anyhow::Result<()> {
+        let receiver = UserUuid::from_u256(uuid_from_pub_key(&receiver_pub_key));
+        let mut conversation = match client.conversation_db.find_by_peer(&self.profile.uuid(), &receiver)? {
+            Some(conversation) => conversation,
+            None => Conversation::start(self.profile.uuid(), receiver, receiver_pub_key)?,
+        };
+        let sealed_grant = sealed_keys.map(|(issued, grant)| {
+            conversation.issued_sealed_key = Some(issued);
+            grant
+        });
+        let payload = conversation.encrypt(&DirectMessageContent {
+            sender: self.profile.uuid(),
+            sender_pub_key: self.profile.private_key().public_key_to_der()?,
+            sent: current_time_millis(),
+            text,
+            sealed_grant,
+        }, &self.profile.private_key())?;
+        // persist the ratcheted key before the message leaves the client
+        client.conversation_db.insert(&conversation)?;
+        // if the peer handed out keys to us, their home server doesn't have to know who we are,
+        // so the message is sent over a connection of its own that never logs in
+        match conversation.sealed_grant.as_ref().filter(|grant| !grant.is_expired()) {
+            Some(grant) => {
+                let packet = ClientPacket::StoreSealedMessage {
+                    receiver,
+                    key_id: grant.key_id,
+                    signed_payload: grant.seal(&payload.ciphertext)?,
+                };
+                let response = anonymous::request(self.connect_params.anonymous_config.clone(), client.config.load().dns_server,
+                                                  self.connect_params.addr.as_str(), &packet).await?;
+                match response {
+                    ServerPacket::StoreMessageResponse(response) => print_store_message_response(client, response),
+                    _ => client.println(format!("{} answered our sealed message with an unexpected packet", self.name).as_str()),
+                }
+                Ok(())
+            }
+            None => {
+                let packet = ClientPacket::StoreMessage { receiver, payload }.encode()?;
+                self.connection.load_full().unwrap().send_reliable(&packet).await
+            }
+        }
+    }
+
+    pub async fn poke(&self, receiver: UserUuid, text: Option<String>) -> anyhow::Result<()> {
+        let packet = ClientPacket::Poke { receiver, text }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
+    }
+
+    /// assigns `group` to `user` in `channel`, none removes the user's channel group
+    pub async fn assign_channel_group(&self, channel: Uuid, user: UserUuid, group: Option<Uuid>) -> anyhow::Result<()> {
+        let packet = ClientPacket::AssignChannelGroup { channel, user, group }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
+    }
+
+    /// asks the server to create a channel below `parent`, the server moves us into it afterwards
+    pub async fn create_channel(&self, parent: Option<Uuid>, name: String, lifetime: ChannelLifetime) -> anyhow::Result<()> {
+        let packet = ClientPacket::CreateChannel { parent, name, lifetime }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
+    }
+
+    /// asks the server to send us the clients of `channel` and keep us informed about them
+    pub async fn subscribe_channel(&self, channel: Uuid) -> anyhow::Result<()> {
+        let packet = ClientPacket::SubscribeChannel { channel }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
+    }
+
+    pub async fn unsubscribe_channel(&self, channel: Uuid) -> anyhow::Result<()> {
+        let packet = ClientPacket::UnsubscribeChannel { channel }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
+    }
+
+    /// redeems a privilege token which grants us a server group or channel group
+    pub async fn redeem_token(&self, token: String) -> anyhow::Result<()> {
+        let packet = ClientPacket::RedeemToken { token }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
+    }
+
+    pub async fn edit_server_group(&self, edit: ServerGroupEdit) -> anyhow::Result<()> {
+        let packet = ClientPacket::EditServerGroup(edit).encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
+    }
+
+    /// adds `user` to or removes them from a server group
+    pub async fn update_client_server_groups(&self, user: UserUuid, update: ClientUpdateServerGroups) -> anyhow::Result<()> {
+        let packet = ClientPacket::UpdateClientServerGroups { client: user, update }.encode()?;
+        self.connection.load_full().unwrap().send_reliable(&packet).await
     }
 
     pub async fn finish_auth(self: &Arc<Self>, client: Arc<Client>) {
         self.state.try_set_connected();
 
+        let connection = self.connection.load_full().unwrap();
         let this = self.clone();
         let tmp_client = client.clone();
-        self.connection.get().unwrap().start_do_keep_alive(Duration::from_millis(250), move |err| {
-            pollster::block_on(this.error(err, &tmp_client)); // FIXME: make this async!
+        let tmp_conn = Arc::downgrade(&connection);
+        connection.start_do_keep_alive(Duration::from_millis(250), move |err| {
+            if tmp_conn.upgrade().map_or(false, |conn| this.is_current(&conn)) {
+                pollster::block_on(this.error(err, &tmp_client)); // FIXME: make this async!
+            }
         }).await.unwrap();
 
         let tmp_server = self.clone();
@@ -185,8 +401,7 @@ impl Server {
             let mut buffer = [0; 2048];
             let mut buf_len = 0;
             loop {
-                let tmp_server = server.connection.get();
-                match tmp_server.unwrap().read_unreliable().await {
+                match connection.read_unreliable().await {
                     Ok(data) => {
                         println!("received voice traffic {}", data.len());
                         if data.len() + buf_len * 2 > buffer.len() * 2 {
@@ -209,7 +424,8 @@ impl Server {
                             }
                         };*/
 
-                        if let Some(audio) = server.audio.as_ref() {
+                        // other servers keep their voice connection but we only listen to the voice server
+                        if let Some(audio) = server.audio.as_ref().filter(|_| client.is_voice_server(&server)) {
                             if let Ok(len) = audio.decoder.lock().await.decode(data.as_ref(), &mut buffer, false) {
                                 // println!("decoded voice traffic {}", len);
                                 client.audio.load().as_ref().unwrap().play_back(move |buf, info| {
@@ -228,11 +444,10 @@ impl Server {
                         tokio::time::sleep(Duration::from_millis(10)).await;
                     }
                     Err(err) => {
-                        if server.state.try_set_disconnected() {
-                            // FIXME: somehow give feedback to server
-                            tmp_server.as_ref().unwrap().close().await;
-                            client.println(format!("An error occurred in the connection with {}: {:?}", server.name, err).as_str());
+                        if server.is_current(&connection) {
+                            server.error(anyhow::Error::from(err), &client).await;
                         }
+                        break;
                     }
                 }
             }
@@ -245,7 +460,18 @@ pub async fn handle_packet(packet: ServerPacket<'_>, client: &Arc<Client>, serve
     match packet {
         ServerPacket::AuthResponse(response) => {
             match response {
-                AuthResponse::Success { channels, default_channel_id, server_groups, own_groups } => {
+                AuthResponse::Success { protocol_version, features, channels, default_channel_id, server_groups, channel_groups, own_groups } => {
+                    let reconnected = server.reconnect_attempts.swap(0, Ordering::AcqRel) > 0;
+                    // the server may have been up- or downgraded while we were reconnecting
+                    server.protocol_version.store(protocol_version, Ordering::Release);
+                    server.features.store(features.bits(), Ordering::Release);
+                    // drop what we knew from a previous connection, the server tells us everything again
+                    let prev_subscriptions = server.subscribed_channels.iter().map(|channel| *channel).collect::<Vec<_>>();
+                    server.clients.clear();
+                    server.subscribed_channels.clear();
+                    server.groups.clear();
+                    server.channel_groups.clear();
+                    server.active_perms.store(Arc::new(None));
                     let mut channels_by_uuid = HashMap::new();
                     let mut channels_by_name = HashMap::new();
                     for channel in channels {
@@ -257,24 +483,58 @@ pub async fn handle_packet(packet: ServerPacket<'_>, client: &Arc<Client>, serve
                                 channel: channel.id.clone(),
                             });
                         }
+                        // we are subscribed to the channel we logged into
+                        if channel.clients.contains_key(&server.profile.uuid()) {
+                            server.subscribed_channels.insert(channel.id);
+                        }
                         channels_by_name.insert(channel.name.clone(), channel.id);
                         channels_by_uuid.insert(channel.id, channel);
                     }
                     server.channels.store(Arc::new(channels_by_uuid));
                     server.channels_by_name.store(Arc::new(channels_by_name));
-                    server.default_channel.try_init_silent(default_channel_id).unwrap();
+                    server.default_channel.store(Arc::new(default_channel_id));
                     for group in server_groups {
                         server.groups.insert(group.uuid, Arc::new(ServerGroup {
                             uuid: group.uuid,
                             name: SwapArc::new(Arc::new(group.name.into_owned())),
                             priority: AtomicU64::new(group.priority),
+                            negate: AtomicBool::new(group.negate),
                             perms: RwLock::new(group.perms.clone()),
                         }));
                     }
+                    for group in channel_groups {
+                        server.channel_groups.insert(group.uuid, Arc::new(group));
+                    }
                     server.finish_auth(client.clone()).await;
-                    client.inter_ui_msg_queue.send(InterUiMessage::ServerConnected(server.clone()));
+                    if reconnected {
+                        // the server forgot our subscriptions along with the old connection, channels that are gone are skipped
+                        for channel in prev_subscriptions {
+                            if server.subscribed_channels.contains(&channel) || !server.channels.load().contains_key(&channel) {
+                                continue;
+                            }
+                            if let Err(err) = server.subscribe_channel(channel).await {
+                                client.println(format!("Couldn't resubscribe to a channel of {}: {}", server.name, err).as_str());
+                            }
+                        }
+                        // the ui already shows this server, so it only has to be updated
+                        client.println(format!("Reconnected to {}", server.name).as_str());
+                        client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(server.clone()));
+                    } else {
+                        // the server we just joined is shown in the ui, voice only moves to it if we have no voice server yet
+                        client.active_server.store(Some(server.clone()));
+                        if client.voice_server.load().as_ref().is_none() {
+                            client.voice_server.store(Some(server.clone()));
+                        }
+                        client.inter_ui_msg_queue.send(InterUiMessage::ServerConnected(server.clone()));
+                    }
+                }
+                AuthResponse::Failure(crate::packet::AuthFailure::AlreadyOnline) => {
+                    // the server may not have noticed yet that our previous connection is gone, so we back off and try again
+                    client.println(format!("{} still considers us online, trying again later", server.name).as_str());
                 }
                 AuthResponse::Failure(failure) => {
+                    // whatever made the server refuse us won't change by trying again
+                    server.reconnect.store(false, Ordering::Release);
                     client.inter_ui_msg_queue.send(InterUiMessage::Error(server.clone(), match failure {
                         crate::packet::AuthFailure::Banned { reason, duration } => format!("You are banned reason: {} for {}", reason, match duration {
                             crate::packet::BanDuration::Permanent => String::from("permanent"),
@@ -299,8 +559,14 @@ pub async fn handle_packet(packet: ServerPacket<'_>, client: &Arc<Client>, serve
                             },
 }),
                         crate::packet::AuthFailure::ReqSec(level) => format!("This server requires a security level of {}", level),
-                        crate::packet::AuthFailure::OutOfDate(_) => todo!(),
-                        crate::packet::AuthFailure::AlreadyOnline => String::from("You are already online"),
+                        crate::packet::AuthFailure::OutOfDate(supported) => if supported.max < VersionRange::SUPPORTED.min {
+                            format!("\"{}\" runs an outdated server which speaks protocol version {} but we require {}, ask its admin to upgrade it",
+                                    server.name, supported, VersionRange::SUPPORTED)
+                        } else {
+                            format!("Your client is outdated, it speaks protocol version {} but \"{}\" requires {}, please upgrade it",
+                                    VersionRange::SUPPORTED, server.name, supported)
+                        },
+                        crate::packet::AuthFailure::AlreadyOnline => unreachable!(),
                         crate::packet::AuthFailure::Invalid(reason) => reason.to_string(),
                     }));
                 }
@@ -309,10 +575,19 @@ pub async fn handle_packet(packet: ServerPacket<'_>, client: &Arc<Client>, serve
         ServerPacket::ChannelUpdate(update) => {
             match update {
                 ChannelUpdate::Create(channel) => {
+                    // channels that become visible may already contain clients
+                    for user in &channel.clients {
+                        server.clients.insert(user.key().clone(), ConnectedRemoteProfile {
+                            name: user.value().name.clone(),
+                            uuid: user.value().uuid.clone(),
+                            server_groups: user.value().server_groups.clone(),
+                            channel: channel.id.clone(),
+                        });
+                    }
                     let mut channels = server.channels.load().as_ref().clone();
                     channels.insert(channel.id, channel);
                     server.channels.store(Arc::new(channels));
-                    // FIXME: update screen
+                    client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(server.clone()));
                 }
                 ChannelUpdate::SubUpdate { channel, update } => {
                     match update {
@@ -337,13 +612,35 @@ pub async fn handle_packet(packet: ServerPacket<'_>, client: &Arc<Client>, serve
                             channels.insert(channel, prev_channel);
                             server.channels.store(Arc::new(channels));
                         }
+                        ChannelSubUpdate::Group { user, group } => {
+                            let mut channels = server.channels.load().as_ref().clone();
+                            let mut prev_channel = channels.get(&channel).unwrap().clone();
+                            prev_channel.user_groups.retain(|assignment| assignment.user != user);
+                            if let Some(group) = group {
+                                prev_channel.user_groups.push(ChannelGroupAssignment { user, group });
+                            }
+                            channels.insert(channel, prev_channel);
+                            server.channels.store(Arc::new(channels));
+                        }
+                        ChannelSubUpdate::Move { parent, sort_id } => {
+                            let mut channels = server.channels.load().as_ref().clone();
+                            let mut prev_channel = channels.get(&channel).unwrap().clone();
+                            prev_channel.parent = parent;
+                            prev_channel.sort_id = sort_id;
+                            channels.insert(channel, prev_channel);
+                            server.channels.store(Arc::new(channels));
+                            client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(server.clone()));
+                        }
                         ChannelSubUpdate::Client(update) => {
                             match update {
                                 ChannelSubClientUpdate::Add(user) => {
-                                    let profile = server.clients.get_mut(&user).map(|mut val| {
+                                    // clients that were in a channel we couldn't see when we connected are unknown to us
+                                    let Some(profile) = server.clients.get_mut(&user).map(|mut val| {
                                         val.value_mut().channel = channel;
-                                        val
-                                    }).unwrap().clone();
+                                        val.clone()
+                                    }) else {
+                                        return;
+                                    };
                                     let profile = RemoteProfile {
                                         name: profile.name,
                                         uuid: profile.uuid,
@@ -361,38 +658,269 @@ pub async fn handle_packet(packet: ServerPacket<'_>, client: &Arc<Client>, serve
                     }
                 }
                 ChannelUpdate::Delete(channel) => {
+                    server.subscribed_channels.remove(&channel);
                     let mut channels = server.channels.load().as_ref().clone();
                     channels.remove(&channel);
                     server.channels.store(Arc::new(channels));
-                    // FIXME: update screen
+                    client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(server.clone()));
                 }
             }
         }
-        ServerPacket::ClientConnected(profile) => {
-            let default_channel = server.default_channel.get().cloned().unwrap();
-            server.channels.load().get(&default_channel).unwrap().clients.insert(profile.uuid.clone(), profile.clone());
+        ServerPacket::ClientConnected { profile, channel } => {
             server.clients.insert(profile.uuid.clone(), ConnectedRemoteProfile {
                 name: profile.name.clone(),
                 uuid: profile.uuid.clone(),
                 server_groups: profile.server_groups.clone(),
-                channel: default_channel.clone(),
+                channel,
             });
-            client.inter_ui_msg_queue.send(InterUiMessage::ChannelAddUser(server.clone(), default_channel, profile));
+            if server.subscribed_channels.contains(&channel) {
+                if let Some(target) = server.channels.load().get(&channel) {
+                    target.clients.insert(profile.uuid.clone(), profile.clone());
+                    client.inter_ui_msg_queue.send(InterUiMessage::ChannelAddUser(server.clone(), channel, profile));
+                }
+            }
+            client.inter_ui_msg_queue.send(InterUiMessage::UpdateContacts);
         }
         ServerPacket::ClientDisconnected(profile) => {
-            let client_profile = server.clients.remove(&profile.uuid).unwrap().1;
-            server.channels.load().get(&client_profile.channel).unwrap().clients.insert(profile.uuid.clone(), profile);
+            // the server only tells us about clients in channels we can see, so we may not know the client
+            let Some((_, client_profile)) = server.clients.remove(&profile.uuid) else {
+                return;
+            };
+            if let Some(channel) = server.channels.load().get(&client_profile.channel) {
+                channel.clients.remove(&profile.uuid);
+            }
             client.inter_ui_msg_queue.send(InterUiMessage::ChannelRemoveUser(server.clone(), client_profile.channel, client_profile.uuid));
+            client.inter_ui_msg_queue.send(InterUiMessage::UpdateContacts);
         }
-        ServerPacket::ClientUpdateServerGroups { client, update } => {
-            // server.clients.get(&client).unwrap().server_groups
+        ServerPacket::ClientUpdateServerGroups { client: target, update } => {
+            let apply = |groups: &mut Vec<Uuid>| match &update {
+                ClientUpdateServerGroups::Add(group) => {
+                    if !groups.contains(group) {
+                        groups.push(*group);
+                    }
+                }
+                ClientUpdateServerGroups::Remove(group) => groups.retain(|member| member != group),
+            };
+            if let Some(mut profile) = server.clients.get_mut(&target) {
+                apply(&mut profile.server_groups);
+                if let Some(channel) = server.channels.load().get(&profile.channel) {
+                    if let Some(mut profile) = channel.clients.get_mut(&target) {
+                        apply(&mut profile.server_groups);
+                    }
+                }
+            }
         }
         ServerPacket::KeepAlive { .. } => {}
         ServerPacket::ChallengeRequest { .. } => {}
         ServerPacket::ForceDisconnect { reason } => {
-            todo!()
+            // we only come back if the server went away on its own, reconnecting after being kicked would be pointless
+            let reconnect = matches!(reason, DisconnectReason::Timeout | DisconnectReason::ServerShutdown { restart: true, .. });
+            server.reconnect.store(reconnect, Ordering::Release);
+            let reason = match reason {
+                DisconnectReason::Kicked { message } => format!("you were kicked: {}", message),
+                DisconnectReason::ReceivedInvalidPacketSize { allowed, received } => format!("we sent a packet of {} bytes but only {} bytes are allowed", received, allowed),
+                DisconnectReason::DecodeError(error) => format!("we sent a packet the server couldn't decode: {}", error),
+                DisconnectReason::Timeout => String::from("timed out"),
+                DisconnectReason::ServerShutdown { message, restart } => {
+                    let action = if restart { "the server is restarting" } else { "the server shut down" };
+                    match message {
+                        Some(message) => format!("{}: {}", action, message),
+                        None => action.to_string(),
+                    }
+                }
+            };
+            server.connection_lost(client, format!("Disconnected from {}, {}", server.name, reason)).await;
         }
         ServerPacket::SwitchChannelResponse(_) => {}, // FIXME: use this!
+        ServerPacket::MailboxDelivery { messages } => {
+            let mut ids = vec![];
+            let own_key = server.profile.private_key();
+            for msg in messages {
+                let content = match msg.sealed_key.as_ref() {
+                    Some(key_id) => client.conversation_db.unseal(key_id, &msg.payload, &own_key),
+                    None => client.conversation_db.decrypt(&msg.payload, &own_key),
+                };
+                match content {
+                    Ok(content) => {
+                        if content.sealed_grant.is_some() {
+                            client.println(format!("[{:?}] handed out keys for sending sealed messages to them", content.sender).as_str());
+                        }
+                        if !content.text.is_empty() {
+                            client.println(format!("[{:?}]: {}", content.sender, content.text).as_str());
+                        }
+                        server.inbox.insert(msg.id, content);
+                        ids.push(msg.id);
+                    }
+                    Err(err) => {
+                        // the message stays on the home server until it expires, so it gets delivered again on our next login
+                        client.println(format!("Couldn't decrypt message {} received from {}, it was kept on the server: {}", msg.id, server.name, err).as_str());
+                    }
+                }
+            }
+            // only the messages that are stored on our side now may be deleted by the home server
+            if !ids.is_empty() {
+                let ack = ClientPacket::AckMessages { ids }.encode().unwrap();
+                server.connection.load_full().unwrap().send_reliable(&ack).await.unwrap(); // FIXME: handle errors properly!
+            }
+        }
+        ServerPacket::StoreMessageResponse(response) => print_store_message_response(client, response),
+        ServerPacket::RegisterSealedKeysResponse(response) => {
+            match response {
+                RegisterSealedKeysResponse::Success => {}
+                RegisterSealedKeysResponse::TooManyKeys => client.println("You handed out too many sealed sender keys, wait for some of them to expire."),
+            }
+        }
+        ServerPacket::Poke { sender, text } => {
+            let sender_name = server.clients.get(&sender).map(|profile| profile.name.clone()).unwrap_or_else(|| format!("{:?}", sender));
+            client.println(format!("{} poked you{}", sender_name, text.as_ref().map(|text| format!(": {}", text)).unwrap_or_default()).as_str());
+            client.inter_ui_msg_queue.send(InterUiMessage::Poke(server.clone(), sender_name, text));
+        }
+        ServerPacket::PokeResponse(response) => {
+            match response {
+                PokeResponse::Success => {}
+                PokeResponse::UnknownReceiver => client.println("The user you tried to poke isn't online."),
+                PokeResponse::NoPermission => client.println("You don't have the permission to poke other users."),
+                PokeResponse::RateLimited => client.println("You are poking too fast, slow down a bit."),
+                PokeResponse::TextTooLong => client.println("The text of your poke is too long."),
+            }
+        }
+        ServerPacket::AssignChannelGroupResponse(response) => {
+            match response {
+                AssignChannelGroupResponse::Success => {}
+                AssignChannelGroupResponse::UnknownChannel => client.println("The channel doesn't exist."),
+                AssignChannelGroupResponse::UnknownUser => client.println("The user you tried to assign a channel group to isn't online."),
+                AssignChannelGroupResponse::UnknownGroup => client.println("The channel group doesn't exist."),
+                AssignChannelGroupResponse::NoPermission => client.println("You don't have the permission to assign channel groups in this channel."),
+            }
+        }
+        ServerPacket::CreateChannelResponse(response) => {
+            match response {
+                CreateChannelResponse::Success => {}
+                CreateChannelResponse::UnknownParent => client.println("The parent channel doesn't exist."),
+                CreateChannelResponse::NoPermission => client.println("You don't have the permission to create this kind of channel here."),
+                CreateChannelResponse::InvalidName => client.println("The channel name is empty or already taken."),
+                CreateChannelResponse::InvalidLifetime => client.println("The channel can't be kept longer than its parent channel."),
+                CreateChannelResponse::Failed => client.println("The server couldn't create the channel."),
+            }
+        }
+        ServerPacket::ServerGroupUpdate(update) => {
+            match update {
+                ServerGroupUpdate::Create(group) => {
+                    server.groups.insert(group.uuid, Arc::new(ServerGroup {
+                        uuid: group.uuid,
+                        name: SwapArc::new(Arc::new(group.name.into_owned())),
+                        priority: AtomicU64::new(group.priority),
+                        negate: AtomicBool::new(group.negate),
+                        perms: RwLock::new(group.perms),
+                    }));
+                }
+                ServerGroupUpdate::Rename { group, name } => {
+                    if let Some(group) = server.groups.get(&group) {
+                        group.name.store(Arc::new(name));
+                    }
+                }
+                ServerGroupUpdate::Perms { group, perms } => {
+                    if let Some(group) = server.groups.get(&group) {
+                        *group.perms.write().unwrap() = perms;
+                    }
+                }
+                ServerGroupUpdate::Priority { group, priority, negate } => {
+                    if let Some(group) = server.groups.get(&group) {
+                        group.priority.store(priority, Ordering::Release);
+                        group.negate.store(negate, Ordering::Release);
+                    }
+                }
+                ServerGroupUpdate::Delete(group) => {
+                    server.groups.remove(&group);
+                    // the server doesn't send membership updates for deleted groups
+                    for mut profile in server.clients.iter_mut() {
+                        profile.server_groups.retain(|member| member != &group);
+                    }
+                    for channel in server.channels.load().values() {
+                        for mut profile in channel.clients.iter_mut() {
+                            profile.server_groups.retain(|member| member != &group);
+                        }
+                    }
+                }
+            }
+        }
+        ServerPacket::ServerGroupResponse(response) => {
+            match response {
+                ServerGroupResponse::Success => {}
+                ServerGroupResponse::UnknownGroup => client.println("The server group doesn't exist."),
+                ServerGroupResponse::UnknownUser => client.println("The user doesn't exist on this server."),
+                ServerGroupResponse::NoPermission => client.println("You don't have the permission to manage this server group."),
+                ServerGroupResponse::InvalidName => client.println("The server group name is empty or already taken."),
+                ServerGroupResponse::ProtectedGroup => client.println("The admin and default server groups can't be deleted."),
+                ServerGroupResponse::Failed => client.println("The server couldn't apply the change."),
+            }
+        }
+        ServerPacket::ChannelClients { channel, clients } => {
+            let channels = server.channels.load();
+            let Some(target) = channels.get(&channel) else {
+                return;
+            };
+            // the previous client list is either outdated or we are no longer subscribed
+            for user in target.clients.iter().map(|profile| profile.uuid).collect::<Vec<_>>() {
+                target.clients.remove(&user);
+                client.inter_ui_msg_queue.send(InterUiMessage::ChannelRemoveUser(server.clone(), channel, user));
+            }
+            match clients {
+                Some(clients) => {
+                    server.subscribed_channels.insert(channel);
+                    for profile in clients {
+                        server.clients.insert(profile.uuid, ConnectedRemoteProfile {
+                            name: profile.name.clone(),
+                            uuid: profile.uuid,
+                            server_groups: profile.server_groups.clone(),
+                            channel,
+                        });
+                        target.clients.insert(profile.uuid, profile.clone());
+                        client.inter_ui_msg_queue.send(InterUiMessage::ChannelAddUser(server.clone(), channel, profile));
+                    }
+                }
+                None => {
+                    server.subscribed_channels.remove(&channel);
+                }
+            }
+        }
+        ServerPacket::ActivePerms(perms) => {
+            let prev = server.active_perms.load();
+            server.active_perms.store(Arc::new(Some(perms)));
+            // the server sends us our perms right after we logged in as well
+            if prev.is_some() {
+                client.println("Your permissions on this server were updated.");
+            }
+        }
+        ServerPacket::DefaultChannel(channel) => {
+            server.default_channel.store(Arc::new(channel));
+        }
+        ServerPacket::ShutdownScheduled { delay, message, restart } => {
+            let action = if restart { "restarts" } else { "shuts down" };
+            let message = message.map(|message| format!(": {}", message)).unwrap_or_default();
+            client.println(format!("{} {} in {} seconds{}", server.name, action, delay.as_secs(), message).as_str());
+        }
+        ServerPacket::RedeemTokenResponse(response) => {
+            match response {
+                RedeemTokenResponse::Success => client.println("The token was redeemed successfully."),
+                RedeemTokenResponse::InvalidToken => client.println("The token doesn't exist, expired or was already used."),
+            }
+        }
+        ServerPacket::ServerInfo(_) => {
+            // we only ask for the info on anonymous connections
+        }
+    }
+}
+
+fn print_store_message_response(client: &Arc<Client>, response: StoreMessageResponse) {
+    match response {
+        StoreMessageResponse::Success => {}
+        StoreMessageResponse::UnknownReceiver => client.println("The receiver of your message doesn't have this server as their home server."),
+        StoreMessageResponse::InvalidSize => client.println("Your message is too large."),
+        StoreMessageResponse::QuotaExceeded => client.println("The mailbox of the receiver of your message is full."),
+        StoreMessageResponse::InvalidSignature => client.println("The sealed sender key for the receiver of your message isn't valid anymore."),
+        StoreMessageResponse::NotAnonymous => client.println("The server refused a sealed message that wasn't sent anonymously."),
+        StoreMessageResponse::TooManyMessages => client.println("You sent too many messages recently, try again later."),
     }
 }
 
@@ -417,6 +945,10 @@ impl ServerState {
         self.0.compare_exchange(STATE_AUTH, STATE_CONNECTED, Ordering::AcqRel, Ordering::Acquire).is_ok()
     }
 
+    pub fn try_set_pending(&self) -> bool {
+        self.0.compare_exchange(STATE_DISCONNECTED, STATE_PENDING, Ordering::AcqRel, Ordering::Acquire).is_ok()
+    }
+
     pub fn try_set_disconnected(&self) -> bool {
         // this should be able to happen with any previous connection state except disconnected.
         self.0.swap(STATE_DISCONNECTED, Ordering::AcqRel) != STATE_DISCONNECTED
@@ -440,5 +972,6 @@ pub struct ServerGroup {
     pub uuid: Uuid,
     pub name: SwapArc<String>,
     pub priority: AtomicU64,
-    pub perms: RwLock<GroupPerms>,
+    pub negate: AtomicBool,
+    pub perms: RwLock<Perms>,
 }
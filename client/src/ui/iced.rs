@@ -1,4 +1,3 @@
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
@@ -16,7 +15,6 @@ use uuid::Uuid;
 use crate::{certificate, Client, packet};
 use crate::config::Config;
 use crate::data_structures::conc_once_cell::ConcurrentOnceCell;
-use crate::network::AddressMode;
 use crate::packet::RemoteProfile;
 use crate::profile::Profile;
 use crate::profile_db::DbProfile;
@@ -130,10 +128,10 @@ impl Application for Ui {
                     profile
                 };
                 self.data.active_profile = Some(profile.uuid());
-                if let Ok(server) = pollster::block_on(Server::new(self.client.clone(), profile, AddressMode::V4,
+                if let Ok(server) = pollster::block_on(Server::new(self.client.clone(), profile,
                                                                    certificate::insecure_local::config(),
-                                                                   SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 20354)),
-                                                                   server_name.clone())) {
+                                                                   String::from("127.0.0.1:20354"),
+                                                                   server_name.clone(), None, None)) {
                     self.client.server.store(Some(server));
                 } else {
                     // FIXME: connection failure!
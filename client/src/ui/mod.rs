@@ -38,9 +38,14 @@ pub fn start_ui(client: Arc<Client>, ui: UiImpl) -> anyhow::Result<()> {
 pub enum InterUiMessage {
     ChannelRemoveUser(Arc<Server>, Uuid, UserUuid),
     ChannelAddUser(Arc<Server>, Uuid, RemoteProfile),
+    UpdateChannels(Arc<Server>), // channels were created, moved or deleted
     UpdateProfiles,
+    UpdateContacts,
+    UpdateServerList, // the info of a server in the server list arrived
     Error(Arc<Server>, String),
     ServerConnected(Arc<Server>),
+    Reconnecting(Arc<Server>), // the connection was lost and we are trying to reconnect
+    Poke(Arc<Server>, String, Option<String>), // the name of the sender and the optional text
 }
 
 impl Debug for InterUiMessage {
@@ -48,9 +53,14 @@ impl Debug for InterUiMessage {
         match self {
             Self::ChannelRemoveUser(_, channel_uuid, user_uuid) => f.debug_tuple("ChannelRemoveUser").field(channel_uuid).field(user_uuid).finish(),
             Self::ChannelAddUser(_, channel_uuid, profile) => f.debug_tuple("ChannelAddUser").field(channel_uuid).field(profile).finish(),
+            Self::UpdateChannels(_) => write!(f, "UpdateChannels"),
             Self::UpdateProfiles => write!(f, "UpdateProfiles"),
+            Self::UpdateContacts => write!(f, "UpdateContacts"),
+            Self::UpdateServerList => write!(f, "UpdateServerList"),
             Self::Error(_, err) => f.debug_tuple("Error").field(err).finish(),
             Self::ServerConnected(_) => write!(f, "ServerConnected"),
+            Self::Reconnecting(_) => write!(f, "Reconnecting"),
+            Self::Poke(_, sender, text) => f.debug_tuple("Poke").field(sender).field(text).finish(),
         }
     }
 }
@@ -11,10 +11,11 @@ use crate::ui::wgpu::atlas::Atlas;
 use crate::ui::wgpu::render::Renderer;
 use crate::ui::wgpu::screen::menu_screen::Menu;
 use crate::ui::wgpu::screen::server_list::ServerList;
-use crate::ui::wgpu::screen_sys::ScreenSystem;
+use crate::ui::wgpu::screen_sys::{ScreenSystem, ScreenType};
 
 use self::screen::error_screen::ErrorScreen;
-use self::screen::server_channels::ServerChannelsScreen;
+use self::screen::poke_screen::PokeScreen;
+use self::screen::server_channels::{self, ServerChannelsScreen};
 
 use super::{InterUiMessage, UiQueue, UiQueueSender};
 
@@ -151,6 +152,7 @@ pub fn run(client: Arc<Client>) -> anyhow::Result<()> {
         },
         Event::DeviceEvent { .. } => {},
         Event::UserEvent(event) => {
+            screen_sys.on_message(&client, &event);
             match event {
                 InterUiMessage::ChannelRemoveUser(_, _, _) => {
                     redraw();
@@ -158,13 +160,32 @@ pub fn run(client: Arc<Client>) -> anyhow::Result<()> {
                 InterUiMessage::ChannelAddUser(_, _, _) => {
                     redraw();
                 },
+                InterUiMessage::UpdateChannels(_) => {
+                    redraw();
+                },
                 InterUiMessage::UpdateProfiles => todo!(),
+                InterUiMessage::UpdateContacts => {
+                    redraw();
+                },
+                InterUiMessage::UpdateServerList => {
+                    redraw();
+                },
                 InterUiMessage::Error(_, error) => {
                     screen_sys.push_screen(Box::new(ErrorScreen::new(&client, error)));
                     redraw();
                 },
-                InterUiMessage::ServerConnected(server) => {
-                    screen_sys.push_screen(Box::new(ServerChannelsScreen::new(server)));
+                InterUiMessage::ServerConnected(_) => {
+                    // all servers share one screen which shows the active server in a tab
+                    if screen_sys.current_screen_ty() != ScreenType::Other(String::from(server_channels::SCREEN_TY)) {
+                        screen_sys.push_screen(Box::new(ServerChannelsScreen::new()));
+                    }
+                    redraw();
+                },
+                InterUiMessage::Reconnecting(_) => {
+                    redraw();
+                },
+                InterUiMessage::Poke(server, sender, text) => {
+                    screen_sys.push_screen(Box::new(PokeScreen::new(server, sender, text)));
                     redraw();
                 },
             }
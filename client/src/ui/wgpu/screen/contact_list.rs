@@ -0,0 +1,204 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use pollster::FutureExt;
+use crate::Client;
+use crate::ui::InterUiMessage;
+use crate::ui::wgpu::DARK_GRAY_UI;
+use crate::ui::wgpu::render::GlyphBuilder;
+use crate::ui::wgpu::screen_sys::Screen;
+use crate::ui::wgpu::ui::{Button, Color, Coloring, Container, TextBox};
+
+// FIXME: add contacts via the ui once there is a text input widget, for now this has to be done via the cli
+
+#[derive(Clone)]
+pub struct ContactList {
+    container: Arc<Container>,
+    entries: Arc<RwLock<Vec<ContactEntry>>>, // only refreshed once the contacts or their presence changed
+    selected: Arc<Mutex<Option<(String, String)>>>, // the profile name and nickname of the selected contact
+    page: Arc<AtomicUsize>,
+    dirty: Arc<AtomicBool>, // whether the container has to be rebuilt on the next tick
+}
+
+struct ContactEntry {
+    profile: String,
+    nickname: String,
+    presence: Vec<String>, // the names of the servers the contact is online on
+}
+
+impl ContactList {
+    pub fn new() -> Self {
+        Self {
+            container: Arc::new(Container::new()),
+            entries: Arc::new(RwLock::new(vec![])),
+            selected: Arc::new(Mutex::new(None)),
+            page: Arc::new(AtomicUsize::new(0)),
+            dirty: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// collects the contacts of all profiles along with their presence
+    fn refresh_entries(&self, client: &Arc<Client>) {
+        let mut entries = vec![];
+        for profile in client.profile_db.cache_ref().iter() {
+            for contact in profile.contacts.iter() {
+                entries.push(ContactEntry {
+                    profile: profile.name.clone(),
+                    nickname: contact.nickname.clone(),
+                    presence: client.presence(&contact.uuid).block_on(),
+                });
+            }
+        }
+        // the page may no longer exist if contacts were removed
+        let pages = entries.len().div_ceil(ENTRIES_ON_PAGE).max(1);
+        self.page.fetch_min(pages - 1, Ordering::AcqRel);
+        *self.entries.write().unwrap() = entries;
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    fn build(&self) {
+        let entry_offset = 1.0 / ENTRIES_ON_PAGE as f32;
+        let entries = self.entries.read().unwrap();
+        let page = self.page.load(Ordering::Acquire);
+        let selected = self.selected.lock().unwrap().clone();
+        for (idx, entry) in entries.iter().skip(page * ENTRIES_ON_PAGE).take(ENTRIES_ON_PAGE).enumerate() {
+            let (text, coloring) = if entry.presence.is_empty() {
+                (format!("{} ({})", entry.nickname, entry.profile), DARK_GRAY_UI)
+            } else {
+                (format!("{} ({}) - online on {}", entry.nickname, entry.profile, entry.presence.join(", ")), ONLINE_UI)
+            };
+            let data = (entry.profile.clone(), entry.nickname.clone());
+            let coloring = if selected.as_ref() == Some(&data) {
+                SELECTED_UI
+            } else {
+                coloring
+            };
+            let pos = (0.0, 1.0 - ((idx + 1) as f32 * entry_offset));
+            let list = self.clone();
+            // clicking on a contact selects it, deleting it needs an explicit click on the delete button
+            self.container.add(Arc::new(RwLock::new(Box::new(Button {
+                inner_box: TextBox {
+                    pos,
+                    width: ENTRY_WIDTH,
+                    height: ENTRY_HEIGHT,
+                    coloring: Coloring::Color([coloring; 6]),
+                    texts: vec![GlyphBuilder::new(&text, pos, (ENTRY_WIDTH, ENTRY_HEIGHT)).in_bounds_off((0.05, 4.0)).build()],
+                },
+                data,
+                on_click: Arc::new(Box::new(move |button, _client| {
+                    *list.selected.lock().unwrap() = Some(button.data.clone());
+                    list.dirty.store(true, Ordering::Release);
+                })),
+            }))));
+        }
+        let pages = entries.len().div_ceil(ENTRIES_ON_PAGE).max(1);
+        drop(entries);
+        if selected.is_some() {
+            self.add_control("Delete", 0, |list, client| {
+                let Some((profile, nickname)) = list.selected.lock().unwrap().take() else {
+                    return;
+                };
+                if let Err(err) = client.profile_db.remove_contact(&profile, &nickname) {
+                    client.println(format!("Couldn't remove the contact {}: {}", nickname, err).as_str());
+                }
+                client.inter_ui_msg_queue.send(InterUiMessage::UpdateContacts);
+            });
+        }
+        if page > 0 {
+            self.add_control("Previous", 1, |list, _client| {
+                list.page.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+        if page + 1 < pages {
+            self.add_control("Next", 2, |list, _client| {
+                list.page.fetch_add(1, Ordering::AcqRel);
+            });
+        }
+    }
+
+    /// adds a button next to the list, `slot` is the button's position from the top
+    fn add_control<F: Fn(&ContactList, &Arc<Client>) + Send + Sync + 'static>(&self, text: &str, slot: usize, on_click: F) {
+        let pos = (ENTRY_WIDTH + CONTROL_MARGIN, 1.0 - ((slot + 1) as f32 * ENTRY_HEIGHT));
+        let list = self.clone();
+        self.container.add(Arc::new(RwLock::new(Box::new(Button {
+            inner_box: TextBox {
+                pos,
+                width: CONTROL_WIDTH,
+                height: ENTRY_HEIGHT,
+                coloring: Coloring::Color([DARK_GRAY_UI; 6]),
+                texts: vec![GlyphBuilder::new(text, pos, (CONTROL_WIDTH, ENTRY_HEIGHT)).in_bounds_off((0.05, 4.0)).build()],
+            },
+            data: (),
+            on_click: Arc::new(Box::new(move |_button, client| {
+                on_click(&list, client);
+                list.dirty.store(true, Ordering::Release);
+            })),
+        }))));
+    }
+}
+
+const ENTRIES_ON_PAGE: usize = 9;
+const ENTRY_WIDTH: f32 = 0.4;
+const ENTRY_HEIGHT: f32 = 0.1;
+const CONTROL_WIDTH: f32 = 0.15;
+const CONTROL_MARGIN: f32 = 0.05;
+
+const ONLINE_UI: Color = Color {
+    r: 0.196,
+    g: 0.502,
+    b: 0.275,
+    a: 1.0,
+};
+
+const SELECTED_UI: Color = Color {
+    r: 0.251,
+    g: 0.353,
+    b: 0.525,
+    a: 1.0,
+};
+
+impl Screen for ContactList {
+    fn on_active(&mut self, client: &Arc<Client>) {
+        self.refresh_entries(client);
+    }
+
+    fn on_deactive(&mut self, _client: &Arc<Client>) {
+        self.container.clear();
+    }
+
+    fn tick(&mut self, _client: &Arc<Client>) {
+        if self.dirty.swap(false, Ordering::AcqRel) {
+            self.container.clear();
+            self.build();
+        }
+    }
+
+    fn on_message(&mut self, client: &Arc<Client>, msg: &InterUiMessage) {
+        // presence depends on the contacts that are online and on the servers we are connected to
+        if matches!(msg, InterUiMessage::UpdateContacts | InterUiMessage::ServerConnected(_) | InterUiMessage::Reconnecting(_)) {
+            self.refresh_entries(client);
+        }
+    }
+
+    #[inline(always)]
+    fn is_closable(&self) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn is_tick_always(&self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    fn is_transparent(&self) -> bool {
+        false
+    }
+
+    fn container(&self) -> &Arc<Container> {
+        &self.container
+    }
+
+    fn clone_screen(&self) -> Box<dyn Screen> {
+        Box::new(self.clone())
+    }
+}
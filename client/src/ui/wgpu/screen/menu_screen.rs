@@ -5,7 +5,7 @@ use crate::ui::wgpu::render::GlyphBuilder;
 use crate::ui::wgpu::screen_sys::Screen;
 use crate::ui::wgpu::ui::{Button, Color, Coloring, Container, TextBox};
 
-use super::server_list;
+use super::{contact_list, server_list};
 
 #[derive(Clone)]
 pub struct Menu {
@@ -79,6 +79,20 @@ impl Screen for Menu {
                 // FIXME: refresh screen, disable glyphs for current screen
             })),
         }))));
+        let pos = (pos.0 + BOX_WIDTH + BOX_BORDER, pos.1);
+        self.container.add(Arc::new(RwLock::new(Box::new(Button {
+            inner_box: TextBox {
+                pos,
+                width: BOX_WIDTH,
+                height: BOX_HEIGHT,
+                coloring: Coloring::Color([DARK_GRAY_UI, DARK_GRAY_UI, DARK_GRAY_UI, DARK_GRAY_UI, DARK_GRAY_UI, DARK_GRAY_UI]),
+                texts: vec![GlyphBuilder::new("Contacts", pos, (BOX_WIDTH, BOX_HEIGHT)).in_bounds_off((0.03, 0.03)).build()],
+            },
+            data: (),
+            on_click: Arc::new(Box::new(|button, client| {
+                ctx().screen_sys.push_screen(Box::new(contact_list::ContactList::new()));
+            })),
+        }))));
     }
 
     fn on_deactive(&mut self, _client: &Arc<Client>) {
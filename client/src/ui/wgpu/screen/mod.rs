@@ -1,4 +1,6 @@
 pub(crate) mod error_screen;
 pub(crate) mod server_channels;
 pub(crate) mod server_list;
-pub(crate) mod menu_screen;
\ No newline at end of file
+pub(crate) mod menu_screen;
+pub(crate) mod contact_list;
+pub(crate) mod poke_screen;
\ No newline at end of file
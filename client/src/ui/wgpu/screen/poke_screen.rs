@@ -0,0 +1,96 @@
+use std::sync::{Arc, RwLock};
+use crate::Client;
+use crate::server::Server;
+use crate::ui::wgpu::{ctx, DARK_GRAY_UI};
+use crate::ui::wgpu::render::GlyphBuilder;
+use crate::ui::wgpu::screen_sys::Screen;
+use crate::ui::wgpu::ui::{Button, Coloring, Container, TextBox};
+
+/// an overlay that is shown on top of the current screen whenever somebody pokes us
+#[derive(Clone)]
+pub struct PokeScreen {
+    container: Arc<Container>,
+    server: Arc<Server>,
+    sender: String,
+    text: Option<String>,
+}
+
+impl PokeScreen {
+
+    pub fn new(server: Arc<Server>, sender: String, text: Option<String>) -> Self {
+        Self {
+            container: Arc::new(Default::default()),
+            server,
+            sender,
+            text,
+        }
+    }
+
+    fn build(&self) {
+        let pos = (0.5 - BOX_WIDTH / 2.0, 0.5 - BOX_HEIGHT / 2.0);
+        let mut texts = vec![GlyphBuilder::new(format!("{} poked you on {}", self.sender, self.server.name).as_str(),
+                                               (pos.0, pos.1 + BOX_HEIGHT / 2.0), (BOX_WIDTH, BOX_HEIGHT / 2.0)).in_bounds_off((0.03, 0.03)).build()];
+        if let Some(text) = self.text.as_ref() {
+            texts.push(GlyphBuilder::new(text.as_str(), pos, (BOX_WIDTH, BOX_HEIGHT / 2.0)).in_bounds_off((0.03, 0.03)).build());
+        }
+        // clicking anywhere on the poke dismisses it
+        self.container.add(Arc::new(RwLock::new(Box::new(Button {
+            inner_box: TextBox {
+                pos,
+                width: BOX_WIDTH,
+                height: BOX_HEIGHT,
+                coloring: Coloring::Color([DARK_GRAY_UI; 6]),
+                texts,
+            },
+            data: (),
+            on_click: Arc::new(Box::new(|_button, _client| {
+                ctx().screen_sys.pop_screen();
+            })),
+        }))));
+    }
+
+}
+
+const BOX_WIDTH: f32 = 0.4;
+const BOX_HEIGHT: f32 = 0.2;
+
+impl Screen for PokeScreen {
+    fn on_active(&mut self, _client: &Arc<Client>) {
+        self.build();
+    }
+
+    fn on_deactive(&mut self, _client: &Arc<Client>) {
+        self.container.clear();
+    }
+
+    fn tick(&mut self, _client: &Arc<Client>) {}
+
+    fn on_resize(&mut self, _client: &Arc<Client>) {
+        self.container.clear();
+        self.build();
+    }
+
+    #[inline]
+    fn container(&self) -> &Arc<Container> {
+        &self.container
+    }
+
+    #[inline(always)]
+    fn is_closable(&self) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn is_tick_always(&self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    fn is_transparent(&self) -> bool {
+        true
+    }
+
+    fn clone_screen(&self) -> Box<dyn Screen> {
+        Box::new(self.clone())
+    }
+}
@@ -1,63 +1,231 @@
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use pollster::FutureExt;
+use uuid::Uuid;
 
-use crate::packet::ClientPacket;
+use crate::packet::{Channel as ServerChannel, ClientPacket};
 use crate::server::Server;
 use crate::ui::wgpu::render::GlyphBuilder;
 use crate::Client;
-use crate::ui::wgpu::screen_sys::Screen;
+use crate::ui::InterUiMessage;
+use crate::ui::wgpu::screen_sys::{Screen, ScreenType};
 use crate::ui::wgpu::ui::{Button, Color, Coloring, Container, TextBox};
 
+pub const SCREEN_TY: &str = "server_channels";
+
 #[derive(Clone)]
 pub struct ServerChannelsScreen {
     container: Arc<Container>,
-    server: Arc<Server>,
+    // the channels whose subchannels are hidden keyed by the server's address, channel ids are only unique per
+    // server (every server's default channel has the same id)
+    collapsed: Arc<Mutex<HashSet<(String, Uuid)>>>,
+    servers: Arc<RwLock<Vec<Arc<Server>>>>, // a copy of the client's servers, only refreshed once the ui got notified about a change
+    dirty: Arc<AtomicBool>, // whether the container has to be rebuilt on the next tick
 }
 
 impl ServerChannelsScreen {
 
-    pub fn new(server: Arc<Server>) -> Self {
+    pub fn new() -> Self {
         Self {
             container: Arc::new(Container::new()),
-            server,
+            collapsed: Arc::new(Mutex::new(HashSet::new())),
+            servers: Arc::new(RwLock::new(vec![])),
+            dirty: Arc::new(AtomicBool::new(true)),
         }
     }
 
-}
-
-const CHANNEL_ENTRY_SIZE: (f32, f32) = (0.2, 0.1);
-const SPACING_OFFSET: f32 = CHANNEL_ENTRY_SIZE.1 * 0.1;
+    fn refresh_servers(&self, client: &Arc<Client>) {
+        *self.servers.write().unwrap() = client.servers.read().block_on().clone();
+        self.dirty.store(true, Ordering::Release);
+    }
 
-impl Screen for ServerChannelsScreen {
-    fn on_active(&mut self, client: &Arc<Client>) {
-        // self.container.add(Arc::new(RwLock::new(Box::new())));
-        // FIXME: sort by sort id
-        for (idx, channel) in self.server.channels.load().iter().enumerate() {
-            println!("added channel {idx}");
-            let off_y = (CHANNEL_ENTRY_SIZE.1 + SPACING_OFFSET) * idx as f32 + CHANNEL_ENTRY_SIZE.1;
-            let uuid = channel.0.clone();
+    fn build(&self, client: &Arc<Client>) {
+        let servers = self.servers.read().unwrap().clone();
+        // the active server may have been removed in the meantime
+        let server = match client.active_server.load().as_ref().cloned().or_else(|| servers.first().cloned()) {
+            Some(server) => server,
+            None => return,
+        };
+        self.build_tabs(client, &servers, &server);
+        if server.is_reconnecting() {
+            let pos = (1.0 - RECONNECTING_SIZE.0, 1.0 - TAB_SIZE.1 - RECONNECTING_SIZE.1);
+            self.container.add(Arc::new(RwLock::new(Box::new(TextBox {
+                pos,
+                width: RECONNECTING_SIZE.0,
+                height: RECONNECTING_SIZE.1,
+                coloring: Coloring::Color([Color { r: 1.0, g: 0.5, b: 0.0, a: 1.0 }; 6]),
+                texts: vec![GlyphBuilder::new("Reconnecting...", pos, RECONNECTING_SIZE).build()],
+            }))));
+        }
+        let channels = server.channels.load();
+        let collapsed = self.collapsed.lock().unwrap().iter()
+            .filter(|(addr, _)| addr == server.addr())
+            .map(|(_, channel)| *channel)
+            .collect::<HashSet<_>>();
+        for (idx, (depth, channel)) in visible_channels(&channels, &collapsed).into_iter().enumerate() {
+            let off_y = TAB_SIZE.1 + (CHANNEL_ENTRY_SIZE.1 + SPACING_OFFSET) * idx as f32 + CHANNEL_ENTRY_SIZE.1;
+            let off_x = TOGGLE_SIZE.0 + depth as f32 * DEPTH_OFFSET;
+            if channels.values().any(|other| other.parent == Some(channel.id)) {
+                // channels that have subchannels can be collapsed and expanded
+                let toggle_pos = (off_x - TOGGLE_SIZE.0, 1.0 - off_y);
+                let toggle_text = if collapsed.contains(&channel.id) {
+                    "+"
+                } else {
+                    "-"
+                };
+                self.container.add(Arc::new(RwLock::new(Box::new(Button {
+                    inner_box: TextBox {
+                        pos: toggle_pos,
+                        width: TOGGLE_SIZE.0,
+                        height: TOGGLE_SIZE.1,
+                        coloring: Coloring::Color([Color { r: 0.8, g: 0.8, b: 0.0, a: 1.0 }; 6]),
+                        texts: vec![GlyphBuilder::new(toggle_text, toggle_pos, TOGGLE_SIZE).build()],
+                    },
+                    data: (channel.id, self.collapsed.clone(), server.clone()),
+                    on_click: Arc::new(Box::new(|button, client| {
+                        {
+                            let key = (button.data.2.addr().to_string(), button.data.0);
+                            let mut collapsed = button.data.1.lock().unwrap();
+                            if !collapsed.remove(&key) {
+                                collapsed.insert(key);
+                            }
+                        }
+                        client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(button.data.2.clone()));
+                    })),
+                }))));
+            }
             self.container.add(Arc::new(RwLock::new(Box::new(Button {
                 inner_box: TextBox {
-                    pos: (0.0, 1.0 - off_y),
+                    pos: (off_x, 1.0 - off_y),
                     width: CHANNEL_ENTRY_SIZE.0,
                     height: CHANNEL_ENTRY_SIZE.1,
                     coloring: Coloring::Color([Color { r: 1.0, g: 1.0, b: 0.0, a: 1.0 }; 6]),
-                    texts: vec![GlyphBuilder::new(&channel.1.name, (0.0, 1.0 - off_y), CHANNEL_ENTRY_SIZE).build()],
+                    texts: vec![GlyphBuilder::new(&channel.name, (off_x, 1.0 - off_y), CHANNEL_ENTRY_SIZE).build()],
+                },
+                data: (channel.id, server.clone()),
+                on_click: Arc::new(Box::new(|button, client| {
+                    // the tab stays visible while we aren't connected, so there may be no connection to switch channels with
+                    let server = &button.data.1;
+                    let connection = server.connection.load_full().filter(|_| server.state.is_connected());
+                    let Some(connection) = connection else {
+                        client.println(format!("Can't switch channels while not connected to {}", server.name).as_str());
+                        return;
+                    };
+                    let result = ClientPacket::SwitchChannel { channel: button.data.0 }.encode()
+                        .and_then(|channel_switch| connection.send_reliable(&channel_switch).block_on());
+                    if let Err(err) = result {
+                        client.println(format!("Couldn't switch channels on {}: {}", server.name, err).as_str());
+                    }
+                })),
+            }))));
+        }
+    }
+
+    /// adds a tab for every server we are connected to and a button to move voice to the shown server
+    fn build_tabs(&self, client: &Arc<Client>, servers: &[Arc<Server>], shown: &Arc<Server>) {
+        for (idx, server) in servers.iter().enumerate() {
+            let pos = (TAB_SIZE.0 * idx as f32, 1.0 - TAB_SIZE.1);
+            let color = if Arc::ptr_eq(server, shown) {
+                Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 }
+            } else {
+                Color { r: 0.6, g: 0.6, b: 0.6, a: 1.0 }
+            };
+            // the voice server is marked so users know where their voice goes
+            let text = if client.is_voice_server(server) {
+                format!("{} (voice)", server.name)
+            } else {
+                server.name.clone()
+            };
+            self.container.add(Arc::new(RwLock::new(Box::new(Button {
+                inner_box: TextBox {
+                    pos,
+                    width: TAB_SIZE.0,
+                    height: TAB_SIZE.1,
+                    coloring: Coloring::Color([color; 6]),
+                    texts: vec![GlyphBuilder::new(text.as_str(), pos, TAB_SIZE).build()],
+                },
+                data: server.clone(),
+                on_click: Arc::new(Box::new(|button, client| {
+                    client.active_server.store(Some(button.data.clone()));
+                    client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(button.data.clone()));
+                })),
+            }))));
+        }
+        if !client.is_voice_server(shown) {
+            let pos = (1.0 - VOICE_BUTTON_SIZE.0, 1.0 - VOICE_BUTTON_SIZE.1);
+            self.container.add(Arc::new(RwLock::new(Box::new(Button {
+                inner_box: TextBox {
+                    pos,
+                    width: VOICE_BUTTON_SIZE.0,
+                    height: VOICE_BUTTON_SIZE.1,
+                    coloring: Coloring::Color([Color { r: 0.0, g: 0.8, b: 0.0, a: 1.0 }; 6]),
+                    texts: vec![GlyphBuilder::new("Use for voice", pos, VOICE_BUTTON_SIZE).build()],
                 },
-                data: (uuid, self.server.clone()),
+                data: shown.clone(),
                 on_click: Arc::new(Box::new(|button, client| {
-                    let channel_switch = ClientPacket::SwitchChannel { channel: button.data.0.clone() }.encode().unwrap();
-                    button.data.1.connection.get().unwrap().send_reliable(&channel_switch).block_on().unwrap();
+                    // the voice thread stops capturing for the old server and starts again for this one
+                    client.voice_server.store(Some(button.data.clone()));
+                    client.inter_ui_msg_queue.send(InterUiMessage::UpdateChannels(button.data.clone()));
                 })),
             }))));
         }
     }
 
+}
+
+/// returns the channels that aren't hidden inside a collapsed channel in the order in
+/// which they are displayed alongside their depth in the tree
+fn visible_channels<'a>(channels: &'a HashMap<Uuid, ServerChannel>, collapsed: &HashSet<Uuid>) -> Vec<(usize, &'a ServerChannel)> {
+    fn visit<'a>(channels: &'a HashMap<Uuid, ServerChannel>, collapsed: &HashSet<Uuid>, parent: Option<Uuid>, depth: usize, result: &mut Vec<(usize, &'a ServerChannel)>) {
+        let mut children = channels.values().filter(|channel| channel.parent == parent).collect::<Vec<_>>();
+        children.sort_by_key(|channel| channel.sort_id);
+        for channel in children {
+            result.push((depth, channel));
+            if !collapsed.contains(&channel.id) {
+                visit(channels, collapsed, Some(channel.id), depth + 1, result);
+            }
+        }
+    }
+
+    let mut result = vec![];
+    visit(channels, collapsed, None, 0, &mut result);
+    result
+}
+
+const CHANNEL_ENTRY_SIZE: (f32, f32) = (0.2, 0.1);
+const SPACING_OFFSET: f32 = CHANNEL_ENTRY_SIZE.1 * 0.1;
+const TOGGLE_SIZE: (f32, f32) = (0.03, CHANNEL_ENTRY_SIZE.1);
+const DEPTH_OFFSET: f32 = 0.04;
+const RECONNECTING_SIZE: (f32, f32) = (0.2, 0.05);
+const TAB_SIZE: (f32, f32) = (0.2, 0.05);
+const VOICE_BUTTON_SIZE: (f32, f32) = (0.2, TAB_SIZE.1);
+
+impl Screen for ServerChannelsScreen {
+    fn on_active(&mut self, client: &Arc<Client>) {
+        self.refresh_servers(client);
+    }
+
     fn on_deactive(&mut self, _client: &Arc<Client>) {
         self.container.clear();
     }
 
-    fn tick(&mut self, _client: &Arc<Client>) {}
+    fn tick(&mut self, client: &Arc<Client>) {
+        if self.dirty.swap(false, Ordering::AcqRel) {
+            self.container.clear();
+            self.build(client);
+        }
+    }
+
+    fn on_message(&mut self, client: &Arc<Client>, _msg: &InterUiMessage) {
+        // channels may have been created, moved or deleted, subchannels may have been collapsed, another tab may
+        // have been chosen or servers may have been connected or lost, all of these are announced via messages
+        self.refresh_servers(client);
+    }
+
+    fn ty(&self) -> ScreenType {
+        ScreenType::Other(String::from(SCREEN_TY))
+    }
 
     fn container(&self) -> &Arc<Container> {
         &self.container
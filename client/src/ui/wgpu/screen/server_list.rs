@@ -1,7 +1,11 @@
-use crate::{AddressMode, certificate, Client, Profile, Server};
+use crate::{certificate, Client, Profile, Server};
 use std::sync::{Arc, RwLock};
+use dashmap::DashMap;
 use pollster::FutureExt;
 use rand::Rng;
+use uuid::Uuid;
+use crate::info_query::{self, QueriedInfo};
+use crate::ui::InterUiMessage;
 use crate::ui::wgpu::{ctx, DARK_GRAY_UI};
 use crate::ui::wgpu::render::GlyphBuilder;
 use crate::ui::wgpu::screen_sys::Screen;
@@ -10,30 +14,47 @@ use crate::ui::wgpu::ui::{Button, Coloring, Container, TextBox};
 #[derive(Clone)]
 pub struct ServerList {
     container: Arc<Container>,
+    infos: Arc<DashMap<usize, Option<QueriedInfo>>>, // the info of each entry, none if the server couldn't be reached
 }
 
 impl ServerList {
     pub fn new() -> Self {
         Self {
             container: Arc::new(Container::new()),
+            infos: Arc::new(DashMap::new()),
         }
     }
-}
 
-const ENTRIES_ON_PAGE: usize = 9;
+    /// asks every server in the list for its info, the list gets updated once they answer
+    fn query_infos(&self, client: &Arc<Client>) {
+        self.infos.clear();
+        let config = client.config.load();
+        for (idx, entry) in config.fav_servers.iter().enumerate() {
+            let infos = self.infos.clone();
+            let client = client.clone();
+            let addr = entry.addr.clone();
+            let name_server = config.dns_server;
+            tokio::spawn(async move {
+                let info = info_query::query_info(certificate::insecure_local::anonymous_config(), name_server, addr.as_str()).await.ok();
+                infos.insert(idx, info);
+                client.inter_ui_msg_queue.send(InterUiMessage::UpdateServerList);
+            });
+        }
+    }
 
-impl Screen for ServerList {
-    fn on_active(&mut self, client: &Arc<Client>) {
+    fn build(&self, client: &Arc<Client>) {
         let entry_offset = 1.0 / ENTRIES_ON_PAGE as f32;
         for entry in client.config.load().fav_servers.iter().enumerate() {
             let addr = entry.1.addr.clone();
             let server_name = entry.1.name.clone();
+            let channel = entry.1.channel.map(Uuid::from_u128);
+            let channel_password = entry.1.channel_password.clone();
             let pos = (0.0, 1.0 - ((entry.0 + 1) as f32 * entry_offset));
             self.container.add(Arc::new(RwLock::new(Box::new(Button {
                 inner_box: TextBox {
                     pos,
-                    width: 0.2,
-                    height: 0.1,
+                    width: ENTRY_SIZE.0,
+                    height: ENTRY_SIZE.1,
                     coloring: Coloring::Color([
                         DARK_GRAY_UI,
                         DARK_GRAY_UI,
@@ -42,7 +63,7 @@ impl Screen for ServerList {
                         DARK_GRAY_UI,
                         DARK_GRAY_UI,
                     ]),
-                    texts: vec![GlyphBuilder::new(&entry.1.name, pos, (0.2, 0.1)).in_bounds_off((0.05, 4.0)).build()],
+                    texts: vec![GlyphBuilder::new(&entry.1.name, pos, ENTRY_SIZE).in_bounds_off((0.05, 4.0)).build()],
                 },
                 data: (),
                 on_click: Arc::new(Box::new(move |button, client| {
@@ -62,19 +83,52 @@ impl Screen for ServerList {
                     let mut profiles = client.profile_db.cache_ref().iter().map(|profile| profile.value().clone()).collect::<Vec<_>>();
                     let profile = profiles.remove(rand::thread_rng().gen_range(0..profiles.len()));
                     let profile = Profile::from_existing(profile.name, profile.alias, profile.priv_key, profile.security_proofs);
-                    let server = Server::new(client.clone(), profile, AddressMode::V4,
+                    let server = Server::new(client.clone(), profile,
                                                                        certificate::insecure_local::config(),
+                                                                       certificate::insecure_local::anonymous_config(),
                                                                        addr,
-                                                                       server_name.clone());
+                                                                       server_name.clone(),
+                                                                       channel,
+                                                                       channel_password.clone());
                     client.servers.write().block_on().push(server);
                 }))
             }))));
+            let info_pos = (ENTRY_SIZE.0, pos.1);
+            let info_text = match self.infos.get(&entry.0).as_deref() {
+                Some(Some(queried)) => format!("{}/{} users | {} ms", queried.info.online, queried.info.slots, queried.ping.as_millis()),
+                Some(None) => String::from("Offline"),
+                None => String::from("Querying..."),
+            };
+            self.container.add(Arc::new(RwLock::new(Box::new(TextBox {
+                pos: info_pos,
+                width: INFO_SIZE.0,
+                height: INFO_SIZE.1,
+                coloring: Coloring::Color([DARK_GRAY_UI; 6]),
+                texts: vec![GlyphBuilder::new(info_text.as_str(), info_pos, INFO_SIZE).in_bounds_off((0.05, 4.0)).build()],
+            }))));
         }
     }
+}
+
+const ENTRIES_ON_PAGE: usize = 9;
+const ENTRY_SIZE: (f32, f32) = (0.2, 0.1);
+const INFO_SIZE: (f32, f32) = (0.3, ENTRY_SIZE.1);
+
+impl Screen for ServerList {
+    fn on_active(&mut self, client: &Arc<Client>) {
+        self.query_infos(client);
+        self.build(client);
+    }
 
-    fn on_deactive(&mut self, _client: &Arc<Client>) {}
+    fn on_deactive(&mut self, _client: &Arc<Client>) {
+        self.container.clear();
+    }
 
-    fn tick(&mut self, _client: &Arc<Client>) {}
+    fn tick(&mut self, client: &Arc<Client>) {
+        // the info of the servers arrives over time
+        self.container.clear();
+        self.build(client);
+    }
 
     #[inline(always)]
     fn is_closable(&self) -> bool {
@@ -4,6 +4,7 @@ use winit::dpi::{PhysicalPosition, Position};
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::Window;
 use crate::Client;
+use crate::ui::InterUiMessage;
 use crate::ui::wgpu::ctx;
 use crate::ui::wgpu::render::Model;
 use crate::ui::wgpu::screen_sys::ScreenType::Other;
@@ -34,6 +35,9 @@ pub trait Screen: Send + Sync {
 
     fn on_char_receive(&mut self, _received: char) {}
 
+    // Called for every message the ui receives while the screen is the current one
+    fn on_message(&mut self, _client: &Arc<Client>, _msg: &InterUiMessage) {}
+
     fn is_closable(&self) -> bool {
         false
     }
@@ -203,6 +207,16 @@ impl ScreenSystem {
         }
     }
 
+    pub fn on_message(&self, client: &Arc<Client>, msg: &InterUiMessage) {
+        if let Some(screen) = self.screens.read().unwrap().last() {
+            screen
+                .screen
+                .lock()
+                .unwrap()
+                .on_message(client, msg);
+        }
+    }
+
     #[allow(unused_must_use)]
     pub fn tick(
         self: &Arc<Self>,
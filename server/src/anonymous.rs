@@ -0,0 +1,86 @@
+// anonymous connections never log in, they are used for requests that mustn't reveal who sent them
+// like storing sealed messages and for requests that don't need a login like info queries. every
+// request uses a bidirectional stream of its own and nothing that could identify the sender
+// (not even their address) may be logged in here
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use bytes::Bytes;
+use quinn::{Connection, RecvStream, SendStream};
+use crate::mailbox_db::MessageSender;
+use crate::{info_query, network};
+use crate::packet::{ServerPacket, StoreMessageResponse};
+use crate::{ClientPacket, ErrorInvalidPacketSize, RWBytes, Server, PACKET_SIZE_LIMIT};
+
+impl Server {
+
+    pub async fn handle_anonymous(self: Arc<Self>, conn: Connection) {
+        while let Ok((send, recv)) = conn.accept_bi().await {
+            let server = self.clone();
+            let ip = conn.remote_address().ip();
+            tokio::spawn(async move {
+                if let Err(err) = server.answer_anonymous(ip, send, recv).await {
+                    server.println(format!("An anonymous request failed: {}", err).as_str());
+                }
+            });
+        }
+    }
+
+    async fn answer_anonymous(self: &Arc<Self>, ip: IpAddr, mut send: SendStream, mut recv: RecvStream) -> anyhow::Result<()> {
+        let mut size = [0; 8];
+        recv.read_exact(&mut size).await?;
+        let size = u64::from_le_bytes(size);
+        if size > PACKET_SIZE_LIMIT {
+            return Err(anyhow::Error::from(ErrorInvalidPacketSize(size)));
+        }
+        let mut payload = vec![0; size as usize];
+        recv.read_exact(&mut payload).await?;
+        let response = match ClientPacket::read(&mut Bytes::from(payload))? {
+            ClientPacket::StoreSealedMessage { receiver, key_id, signed_payload } => {
+                // checking signatures and storing messages is expensive, so addresses that send too often are turned away first
+                let cooldown = Duration::from_millis(self.config.load().sealed_message_cooldown_millis);
+                let response = if !info_query::is_request_allowed(&self.sealed_messages, ip, cooldown) {
+                    StoreMessageResponse::TooManyMessages
+                } else {
+                    // the only thing that proves that the sender may message the receiver is the signature of the access key
+                    match self.mailbox_db.open_sealed(&receiver, &key_id, signed_payload)? {
+                        Some(payload) => network::store_message(self, receiver, MessageSender::Sealed(key_id), payload).await,
+                        None => StoreMessageResponse::InvalidSignature,
+                    }
+                };
+                ServerPacket::StoreMessageResponse(response)
+            }
+            ClientPacket::InfoRequest => {
+                // addresses that query too often get no answer
+                if !self.is_info_query_allowed(ip) {
+                    return Ok(());
+                }
+                ServerPacket::ServerInfo(self.server_info().await)
+            }
+            _ => return Err(anyhow::Error::from(ErrorNotAnonymous)),
+        };
+        send.write_all(&response.encode()?).await?;
+        send.finish().await?;
+        Ok(())
+    }
+
+}
+
+struct ErrorNotAnonymous;
+
+impl Debug for ErrorNotAnonymous {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("received a packet that can't be sent anonymously")
+    }
+}
+
+impl Display for ErrorNotAnonymous {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorNotAnonymous {}
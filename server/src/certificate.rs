@@ -1,5 +1,6 @@
 use quinn::ServerConfig;
 use rustls::{Certificate, Error, PrivateKey};
+use std::sync::Arc;
 
 pub(crate) mod secure_authority {
     use rustls::{Certificate, PrivateKey};
@@ -45,6 +46,19 @@ pub(crate) mod insecure_local {
     }
 }
 
+// connections that negotiate this protocol never log in, clients that don't negotiate any protocol open a regular session
+pub(crate) const ALPN_ANONYMOUS: &[u8] = b"rustspeak-anon";
+
 pub(crate) fn create_config(certs: Certificate, key: PrivateKey) -> Result<ServerConfig, Error> {
-    ServerConfig::with_single_cert(vec![certs], key)
+    // this mirrors `ServerConfig::with_single_cert` but lets clients pick the anonymous protocol
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![certs], key)?;
+    crypto.max_early_data_size = u32::MAX;
+    crypto.alpn_protocols = vec![ALPN_ANONYMOUS.to_vec()];
+    Ok(ServerConfig::with_crypto(Arc::new(crypto)))
 }
@@ -1,8 +1,9 @@
 use crate::ChannelPerms;
+use crate::packet::ChannelLifetime;
 use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::mem::size_of;
 use std::path::Path;
@@ -12,13 +13,17 @@ use uuid::Uuid;
 #[derive(Serialize, Deserialize)]
 pub struct ChannelDbEntry<'a> {
     pub id: u128, // channel uuid
-    pub sort_id: u16,
+    #[serde(default)]
+    pub parent: Option<u128>, // the uuid of the parent channel, none for root channels
+    pub sort_id: u16, // the position among the channel's siblings
     pub name: Cow<'a, str>,
     pub desc: Cow<'a, str>,
     pub password: Option<Cow<'a, str>>,
     pub user_groups: Vec<(U256Container, u128)>, // user uuid and channel group uuid
-    pub perms: ChannelPerms,
+    pub perms: Option<ChannelPerms>, // none if the perms are inherited from the parent
     pub slots: i16, // a value of -1 indicates unlimited users, all other negative values are illegal
+    #[serde(default)]
+    pub lifetime: ChannelLifetime, // temporary channels are never persisted
 }
 
 pub struct ChannelDb {
@@ -52,10 +57,17 @@ impl ChannelDb {
     pub fn write(&self, channels: &Vec<ChannelDbEntry>) -> anyhow::Result<()> {
         let val = serde_json::to_string(channels)?;
         let mut file = File::create(self.path.clone())?;
-        file.write(val.as_bytes())?;
+        file.write_all(val.as_bytes())?;
 
         Ok(())
     }
+
+    /// makes sure the last write reached the disk
+    pub fn flush(&self) -> anyhow::Result<()> {
+        // windows only allows syncing files that were opened for writing
+        OpenOptions::new().write(true).open(self.path.clone())?.sync_all()?;
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -67,14 +79,14 @@ pub struct U256Container {
 
 impl U256Container {
 
-    fn new(val: U256) -> Self {
+    pub(crate) fn new(val: U256) -> Self {
         Self {
             raw: val.to_le_bytes(),
             _align: [],
         }
     }
 
-    fn unwrap(&self) -> U256 {
+    pub(crate) fn unwrap(&self) -> U256 {
         U256::from_le_bytes(self.raw.clone())
     }
 
@@ -0,0 +1,56 @@
+use crate::perms::Perms;
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+#[derive(Serialize, Deserialize)]
+pub struct ChannelGroupEntry<'a> {
+    pub uuid: u128,
+    pub name: Cow<'a, str>,
+    pub perms: Perms, // granted to all members of this group in the channel and its subchannels
+}
+
+pub struct ChannelGroupDb {
+    pub(crate) path: String,
+}
+
+impl ChannelGroupDb {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+
+    pub fn read_or_create<'db, F: FnOnce() -> anyhow::Result<Vec<ChannelGroupEntry<'db>>>>(
+        &self,
+        default: F,
+    ) -> anyhow::Result<Vec<ChannelGroupEntry<'db>>> {
+        match File::open(self.path.clone()) {
+            Ok(mut db_file) => {
+                let mut content = String::new();
+                db_file.read_to_string(&mut content)?;
+                let channel_group_db = serde_json::from_str(&content)?;
+                Ok(channel_group_db)
+            }
+            Err(_) => {
+                let default = default()?;
+                self.write(&default)?;
+                Ok(default)
+            }
+        }
+    }
+
+    pub fn write(&self, channel_groups: &Vec<ChannelGroupEntry>) -> anyhow::Result<()> {
+        let val = serde_json::to_string(channel_groups)?;
+        let mut file = File::create(self.path.clone())?;
+        file.write_all(val.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// makes sure the last write reached the disk
+    pub fn flush(&self) -> anyhow::Result<()> {
+        // windows only allows syncing files that were opened for writing
+        OpenOptions::new().write(true).open(self.path.clone())?.sync_all()?;
+        Ok(())
+    }
+}
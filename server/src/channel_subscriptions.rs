@@ -0,0 +1,58 @@
+// users only receive the client lists of and client updates for the channels they are subscribed to,
+// every user is subscribed to their current channel and may subscribe to any other channel they can see
+
+use std::sync::Arc;
+use bytes::BytesMut;
+use uuid::Uuid;
+use crate::packet::{Channel, ServerPacket};
+use crate::{Server, User};
+
+/// returns the copy of `channel` that is sent to `user`, it only contains the channel's clients if the user is subscribed to it
+pub fn channel_for(user: &User, channel: &Channel) -> Channel {
+    if user.subscribed_channels.lock().unwrap().contains(&channel.uuid) {
+        channel.clone()
+    } else {
+        channel.without_clients()
+    }
+}
+
+impl Server {
+
+    /// sends `packet` to all users that are subscribed to `channel`
+    pub async fn send_to_subscribers(&self, channel: &Uuid, packet: &BytesMut) {
+        let subscribers = self.online_users.iter()
+            .filter(|user| user.subscribed_channels.lock().unwrap().contains(channel))
+            .map(|user| user.connection.clone())
+            .collect::<Vec<_>>();
+        for subscriber in subscribers {
+            subscriber.send_reliable(packet).await.unwrap(); // FIXME: handle errors properly!
+        }
+    }
+
+    /// subscribes `user` to `channel` and sends them the channel's clients, channels the user can't see are ignored
+    pub async fn subscribe_channel(&self, user: &Arc<User>, channel: &Uuid) -> anyhow::Result<()> {
+        if !user.visible_channels.lock().unwrap().contains(channel) {
+            return Ok(());
+        }
+        let Some(target) = self.channels.read().await.get(channel).cloned() else {
+            return Ok(());
+        };
+        if !user.subscribed_channels.lock().unwrap().insert(*channel) {
+            // the user is already subscribed
+            return Ok(());
+        }
+        let clients = target.proto_clients.read().unwrap().clone();
+        let packet = ServerPacket::ChannelClients { channel: *channel, clients: Some(clients) }.encode()?;
+        user.connection.send_reliable(&packet).await
+    }
+
+    /// unsubscribes `user` from `channel`, users can't unsubscribe from their current channel
+    pub async fn unsubscribe_channel(&self, user: &Arc<User>, channel: &Uuid) -> anyhow::Result<()> {
+        if &user.channel.load().uuid == channel || !user.subscribed_channels.lock().unwrap().remove(channel) {
+            return Ok(());
+        }
+        let packet = ServerPacket::ChannelClients { channel: *channel, clients: None }.encode()?;
+        user.connection.send_reliable(&packet).await
+    }
+
+}
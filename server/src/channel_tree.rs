@@ -0,0 +1,578 @@
+// channels form a tree, every channel without a parent is a root channel and
+// siblings are ordered by their sort id
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU16, AtomicU64, Ordering};
+use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use bytes::BytesMut;
+use swap_arc::SwapArc;
+use uuid::Uuid;
+use crate::channel_db::{ChannelDbEntry, U256Container};
+use crate::packet::{Channel, ChannelGroup, ChannelGroupAssignment, ChannelLifetime, ChannelPerms, ChannelSubClientUpdate, ChannelSubUpdate, ChannelUpdate, ServerGroup, ServerPacket};
+use crate::{Server, User, UserUuid};
+use crate::perms;
+use crate::perms::Perms;
+use crate::utils::current_time_millis;
+
+/// returns the perms that apply to `channel`, channels that don't have perms of their own inherit the perms of their parent
+pub fn effective_perms(channels: &HashMap<Uuid, Arc<Channel>>, channel: &Uuid) -> ChannelPerms {
+    let mut curr = channels.get(channel);
+    while let Some(channel) = curr {
+        if let Some(perms) = channel.perms.load().as_ref() {
+            return perms.clone();
+        }
+        curr = channel.parent.load().as_ref().and_then(|parent| channels.get(parent));
+    }
+    ChannelPerms::default()
+}
+
+/// returns the perms `user` has in `channel`, if the user is a member of a channel group in the channel
+/// or one of its ancestors the nearest group's perms are granted on top of the user's server groups
+pub fn user_perms(channels: &HashMap<Uuid, Arc<Channel>>, server_groups: &HashMap<Uuid, Arc<ServerGroup>>, channel_groups: &HashMap<Uuid, Arc<ChannelGroup>>, channel: &Uuid, user: &User) -> Perms {
+    let mut channel_group = None;
+    let mut curr = channels.get(channel);
+    while let Some(channel) = curr {
+        if let Some(group) = channel.user_groups.load().iter()
+            .find(|assignment| assignment.user == user.uuid)
+            .and_then(|assignment| channel_groups.get(&assignment.group)) {
+            channel_group = Some(group);
+            break;
+        }
+        curr = channel.parent.load().as_ref().and_then(|parent| channels.get(parent));
+    }
+    let groups = user.groups.read().unwrap().clone();
+    perms::resolve(server_groups, &groups, channel_group.map(|group| group.as_ref()), &user.perms.load())
+}
+
+/// returns whether `user` has enough join power to join `channel`
+pub fn may_join(channels: &HashMap<Uuid, Arc<Channel>>, server_groups: &HashMap<Uuid, Arc<ServerGroup>>, channel_groups: &HashMap<Uuid, Arc<ChannelGroup>>, channel: &Uuid, user: &User) -> bool {
+    effective_perms(channels, channel).join <= user_perms(channels, server_groups, channel_groups, channel, user).granted(perms::CHANNEL_JOIN)
+}
+
+/// returns the direct children of `parent` ordered by their sort id
+pub fn children(channels: &HashMap<Uuid, Arc<Channel>>, parent: Option<Uuid>) -> Vec<Arc<Channel>> {
+    let mut children = channels.values().filter(|channel| *channel.parent.load().deref() == parent).cloned().collect::<Vec<_>>();
+    children.sort_by_key(|channel| channel.sort_id.load(Ordering::Acquire));
+    children
+}
+
+/// returns whether a child of `parent` other than `except` is already called `name`, names only have to be unique among siblings
+pub fn is_name_taken(channels: &HashMap<Uuid, Arc<Channel>>, parent: Option<Uuid>, name: &str, except: Option<&Uuid>) -> bool {
+    children(channels, parent).iter().any(|sibling| Some(&sibling.uuid) != except && sibling.name.load().eq_ignore_ascii_case(name))
+}
+
+/// returns all channels in the order in which they are displayed alongside their depth in the tree
+pub fn tree_order(channels: &HashMap<Uuid, Arc<Channel>>) -> Vec<(usize, Arc<Channel>)> {
+    fn visit(channels: &HashMap<Uuid, Arc<Channel>>, parent: Option<Uuid>, depth: usize, result: &mut Vec<(usize, Arc<Channel>)>) {
+        for channel in children(channels, parent) {
+            let uuid = channel.uuid;
+            result.push((depth, channel));
+            visit(channels, Some(uuid), depth + 1, result);
+        }
+    }
+
+    let mut result = vec![];
+    visit(channels, None, 0, &mut result);
+    result
+}
+
+/// returns `channel` and all of its descendants, children always come before their parents
+pub fn subtree(channels: &HashMap<Uuid, Arc<Channel>>, channel: &Uuid) -> Vec<Uuid> {
+    let mut result = vec![];
+    for child in children(channels, Some(*channel)) {
+        result.extend(subtree(channels, &child.uuid));
+    }
+    result.push(*channel);
+    result
+}
+
+/// checks whether `channel` can be moved below `parent`, a channel can't become a descendant of itself
+pub fn check_move(channels: &HashMap<Uuid, Arc<Channel>>, channel: &Uuid, parent: Option<&Uuid>) -> anyhow::Result<()> {
+    if !channels.contains_key(channel) {
+        return Err(anyhow::Error::from(ErrorUnknownChannel(*channel)));
+    }
+    if let Some(parent) = parent {
+        if !channels.contains_key(parent) {
+            return Err(anyhow::Error::from(ErrorUnknownChannel(*parent)));
+        }
+        if subtree(channels, channel).contains(parent) {
+            return Err(anyhow::Error::from(ErrorChannelCycle(*channel)));
+        }
+    }
+    Ok(())
+}
+
+/// returns the sort id a new channel needs to be placed after all of its siblings
+pub fn next_sort_id(channels: &HashMap<Uuid, Arc<Channel>>, parent: Option<Uuid>) -> u16 {
+    children(channels, parent).last().map_or(0, |channel| channel.sort_id.load(Ordering::Acquire) + 1)
+}
+
+impl Server {
+
+    pub async fn broadcast(&self, packet: &BytesMut) {
+        for user in self.online_users.iter() {
+            user.value().connection.send_reliable(packet).await.unwrap(); // FIXME: handle errors properly!
+        }
+    }
+
+    /// moves `user` from their current channel into `new_channel` and informs all other users about it
+    pub async fn move_user(&self, user: &Arc<User>, new_channel: &Arc<Channel>) {
+        let client_id = user.uuid;
+        let channel = user.channel.load().clone();
+        let idx = channel.clients.read().await.iter().enumerate().find(|user| user.1 == &client_id).unwrap().0;
+        channel.clients.write().await.remove(idx);
+        let idx = channel.proto_clients.read().unwrap().iter().enumerate().find(|user| &user.1.uuid == &client_id).unwrap().0;
+        let profile = RwLock::write(&channel.proto_clients).unwrap().remove(idx);
+        new_channel.clients.write().await.push(client_id);
+        RwLock::write(&new_channel.proto_clients).unwrap().push(profile);
+        user.channel.store(new_channel.clone());
+        let remove_packet = ServerPacket::ChannelUpdate(ChannelUpdate::SubUpdate { channel: channel.uuid, update: ChannelSubUpdate::Client(ChannelSubClientUpdate::Remove(client_id)) }).encode().unwrap();
+        let add_packet = ServerPacket::ChannelUpdate(ChannelUpdate::SubUpdate { channel: new_channel.uuid, update: ChannelSubUpdate::Client(ChannelSubClientUpdate::Add(client_id)) }).encode().unwrap();
+        self.send_to_subscribers(&channel.uuid, &remove_packet).await;
+        self.send_to_subscribers(&new_channel.uuid, &add_packet).await;
+        // the user's current channel is always visible to them and they are always subscribed to it
+        self.refresh_visibility(user).await.unwrap(); // FIXME: handle errors properly!
+        self.subscribe_channel(user, &new_channel.uuid).await.unwrap(); // FIXME: handle errors properly!
+    }
+
+    /// creates a new channel below `entry.parent` and informs all users about it
+    pub async fn create_channel(&self, entry: ChannelDbEntry<'_>) -> anyhow::Result<()> {
+        let uuid = Uuid::from_u128(entry.id);
+        let parent = entry.parent.map(Uuid::from_u128);
+        let channel = Arc::new(Channel {
+            uuid,
+            parent: Arc::new(SwapArc::new(Arc::new(parent))),
+            password: AtomicBool::new(entry.password.is_some()),
+            name: Arc::new(SwapArc::new(Arc::new(entry.name.to_string()))),
+            desc: Arc::new(SwapArc::new(Arc::new(entry.desc.to_string()))),
+            perms: Arc::new(SwapArc::new(Arc::new(entry.perms.clone()))),
+            clients: Arc::new(Default::default()),
+            proto_clients: Arc::new(Default::default()),
+            slots: AtomicI16::new(entry.slots),
+            sort_id: AtomicU16::new(entry.sort_id),
+            user_groups: Arc::new(SwapArc::new(Arc::new(entry.user_groups.iter().map(|(user, group)| ChannelGroupAssignment {
+                user: UserUuid::from_u256(user.unwrap()),
+                group: Uuid::from_u128(*group),
+            }).collect()))),
+            lifetime: entry.lifetime,
+            empty_since: AtomicU64::new(0),
+        });
+        {
+            // everything is checked under the write lock so concurrent creations can't invalidate the checks
+            let mut channels = self.channels.write().await;
+            if let Some(parent) = parent.as_ref() {
+                let Some(parent_channel) = channels.get(parent) else {
+                    return Err(anyhow::Error::from(ErrorUnknownChannel(*parent)));
+                };
+                if self.outlives(entry.lifetime, parent_channel.lifetime) {
+                    return Err(anyhow::Error::from(ErrorChannelOutlivesParent(*parent)));
+                }
+            }
+            if is_name_taken(&channels, parent, &entry.name, None) {
+                return Err(anyhow::Error::from(ErrorChannelNameTaken(entry.name.to_string())));
+            }
+            // the channel is only added once it was persisted, so a failed write doesn't leave a channel behind that vanishes on restart
+            if self.is_persisted(entry.lifetime) {
+                let mut db = self.read_channel_db()?;
+                db.push(entry);
+                self.channel_db.write(&db)?;
+            }
+            channels.insert(uuid, channel.clone());
+        }
+
+        self.refresh_all_visibility().await
+    }
+
+    /// moves `channel` and all of its descendants below `parent`
+    pub async fn move_channel(&self, channel: &Uuid, parent: Option<Uuid>, sort_id: Option<u16>) -> anyhow::Result<()> {
+        let sort_id = {
+            // the move is checked and applied under the write lock so concurrent moves can't create a cycle together
+            let channels = self.channels.write().await;
+            check_move(&channels, channel, parent.as_ref())?;
+            let target = &channels[channel];
+            if is_name_taken(&channels, parent, &target.name.load(), Some(channel)) {
+                return Err(anyhow::Error::from(ErrorChannelNameTaken(target.name.load().to_string())));
+            }
+            // the descendants can't outlive the moved channel, so only the moved channel has to be checked against its new parent
+            if let Some(parent) = parent.as_ref() {
+                if self.outlives(target.lifetime, channels[parent].lifetime) {
+                    return Err(anyhow::Error::from(ErrorChannelOutlivesParent(*parent)));
+                }
+            }
+            let sort_id = sort_id.unwrap_or_else(|| next_sort_id(&channels, parent));
+            let mut db = self.read_channel_db()?;
+            if let Some(entry) = db.iter_mut().find(|entry| entry.id == channel.as_u128()) {
+                entry.parent = parent.map(|parent| parent.as_u128());
+                entry.sort_id = sort_id;
+            }
+            self.channel_db.write(&db)?;
+            target.parent.store(Arc::new(parent));
+            target.sort_id.store(sort_id, Ordering::Release);
+            sort_id
+        };
+
+        let packet = ServerPacket::ChannelUpdate(ChannelUpdate::SubUpdate { channel: *channel, update: ChannelSubUpdate::Move { parent, sort_id } }).encode()?;
+        self.send_to_viewers(channel, &packet).await;
+        // the channel may have been moved below a channel some users can't see
+        self.refresh_all_visibility().await
+    }
+
+    /// deletes `channel` and all of its descendants, the users in them get moved into the default channel
+    pub async fn delete_channel(&self, channel: &Uuid) -> anyhow::Result<()> {
+        let default_channel = self.default_channel_id();
+        let (deleted, default_channel) = {
+            let channels = self.channels.read().await;
+            if !channels.contains_key(channel) {
+                return Err(anyhow::Error::from(ErrorUnknownChannel(*channel)));
+            }
+            let deleted = subtree(&channels, channel);
+            if deleted.contains(&default_channel) {
+                return Err(anyhow::Error::from(ErrorDefaultChannelInSubtree(*channel)));
+            }
+            (deleted, channels.get(&default_channel).unwrap().clone())
+        };
+        for user in self.online_users.iter().map(|user| user.value().clone()).collect::<Vec<_>>() {
+            if deleted.contains(&user.channel.load().uuid) {
+                self.move_user(&user, &default_channel).await;
+            }
+        }
+        {
+            let mut channels = self.channels.write().await;
+            for uuid in deleted.iter() {
+                channels.remove(uuid);
+            }
+        }
+        let mut db = self.read_channel_db()?;
+        db.retain(|entry| !deleted.contains(&Uuid::from_u128(entry.id)));
+        self.channel_db.write(&db)?;
+
+        for uuid in deleted.iter() {
+            let packet = ServerPacket::ChannelUpdate(ChannelUpdate::Delete(*uuid)).encode()?;
+            self.send_to_viewers(uuid, &packet).await;
+        }
+        for user in self.online_users.iter() {
+            user.visible_channels.lock().unwrap().retain(|channel| !deleted.contains(channel));
+            user.subscribed_channels.lock().unwrap().retain(|channel| !deleted.contains(channel));
+        }
+        Ok(())
+    }
+
+    /// returns whether `password` matches the password of `channel`, channels without a password accept any password
+    pub fn check_channel_password(&self, channel: &Channel, password: Option<&str>) -> anyhow::Result<bool> {
+        if !channel.password.load(Ordering::Acquire) {
+            return Ok(true);
+        }
+        let db = self.read_channel_db()?;
+        Ok(db.iter()
+            .find(|entry| entry.id == channel.uuid.as_u128())
+            .map_or(false, |entry| entry.password.as_deref() == password))
+    }
+
+    /// makes `channel` the default channel and stores it in the config so it stays the default channel after a restart
+    pub async fn set_default_channel(&self, channel: &Uuid) -> anyhow::Result<()> {
+        // the config update fails if the channel doesn't exist
+        self.update_config(|config| {
+            config.default_channel_id = channel.as_u128();
+            Ok(())
+        }).await
+    }
+
+    /// whether channels with the given lifetime are written to the channel db
+    fn is_persisted(&self, lifetime: ChannelLifetime) -> bool {
+        match lifetime {
+            ChannelLifetime::Permanent => true,
+            ChannelLifetime::SemiPermanent => self.config.load().persist_semi_permanent_channels,
+            ChannelLifetime::Temporary => false,
+        }
+    }
+
+    /// whether a channel with the lifetime `child` could outlive a parent with the lifetime `parent`,
+    /// which would leave it without a parent once the parent is deleted or isn't restored after a restart
+    fn outlives(&self, child: ChannelLifetime, parent: ChannelLifetime) -> bool {
+        (child != ChannelLifetime::Temporary && parent == ChannelLifetime::Temporary)
+            || (self.is_persisted(child) && !self.is_persisted(parent))
+    }
+
+    /// deletes all temporary channels whose subtree has been empty for longer than the configured delay
+    pub async fn purge_temporary_channels(&self) -> anyhow::Result<()> {
+        let delay = Duration::from_secs(self.config.load().temp_channel_delete_delay_secs).as_millis() as u64;
+        let now = current_time_millis().as_millis() as u64;
+        let mut expired = vec![];
+        {
+            let channels = self.channels.read().await;
+            for channel in channels.values().filter(|channel| channel.lifetime == ChannelLifetime::Temporary) {
+                let mut empty = true;
+                for uuid in subtree(&channels, &channel.uuid) {
+                    if !channels.get(&uuid).unwrap().clients.read().await.is_empty() {
+                        empty = false;
+                        break;
+                    }
+                }
+                if !empty {
+                    channel.empty_since.store(0, Ordering::Release);
+                    continue;
+                }
+                let empty_since = channel.empty_since.load(Ordering::Acquire);
+                if empty_since == 0 {
+                    channel.empty_since.store(now, Ordering::Release);
+                } else if now.saturating_sub(empty_since) >= delay {
+                    expired.push(channel.uuid);
+                }
+            }
+        }
+        for channel in expired {
+            // the channel may already be gone if one of its ancestors expired as well
+            if self.channels.read().await.contains_key(&channel) {
+                self.delete_channel(&channel).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// makes `user` a member of `group` in `channel` or removes the user's channel group if `group` is none
+    pub async fn assign_channel_group(&self, channel: &Uuid, user: &UserUuid, group: Option<Uuid>) -> anyhow::Result<()> {
+        if let Some(group) = group.as_ref() {
+            if !self.channel_groups.read().await.contains_key(group) {
+                return Err(anyhow::Error::from(ErrorUnknownChannelGroup(*group)));
+            }
+        }
+        {
+            let channels = self.channels.read().await;
+            let target = channels.get(channel).ok_or(anyhow::Error::from(ErrorUnknownChannel(*channel)))?;
+            let mut user_groups = target.user_groups.load().as_ref().clone();
+            user_groups.retain(|assignment| &assignment.user != user);
+            if let Some(group) = group {
+                user_groups.push(ChannelGroupAssignment {
+                    user: *user,
+                    group,
+                });
+            }
+            target.user_groups.store(Arc::new(user_groups));
+        }
+        let mut db = self.read_channel_db()?;
+        if let Some(entry) = db.iter_mut().find(|entry| entry.id == channel.as_u128()) {
+            entry.user_groups.retain(|(member, _)| UserUuid::from_u256(member.unwrap()) != *user);
+            if let Some(group) = group {
+                entry.user_groups.push((U256Container::new(user.into_u256()), group.as_u128()));
+            }
+        }
+        self.channel_db.write(&db)?;
+
+        let packet = ServerPacket::ChannelUpdate(ChannelUpdate::SubUpdate { channel: *channel, update: ChannelSubUpdate::Group { user: *user, group } }).encode()?;
+        self.send_to_viewers(channel, &packet).await;
+        // the group may change which channels the user can see
+        if let Some(online) = self.online_users.get(user).map(|user| user.value().clone()) {
+            self.refresh_visibility(&online).await?;
+        }
+        Ok(())
+    }
+
+}
+
+pub struct ErrorUnknownChannel(pub(crate) Uuid);
+
+impl Debug for ErrorUnknownChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there is no channel with the uuid ")?;
+        f.write_str(self.0.to_string().as_str())
+    }
+}
+
+impl Display for ErrorUnknownChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownChannel {}
+
+pub struct ErrorChannelOutlivesParent(pub(crate) Uuid);
+
+impl Debug for ErrorChannelOutlivesParent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the channel would outlive its parent ")?;
+        f.write_str(self.0.to_string().as_str())
+    }
+}
+
+impl Display for ErrorChannelOutlivesParent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorChannelOutlivesParent {}
+
+pub struct ErrorChannelNameTaken(pub(crate) String);
+
+impl Debug for ErrorChannelNameTaken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there already is a channel named ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorChannelNameTaken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorChannelNameTaken {}
+
+pub struct ErrorChannelCycle(Uuid);
+
+impl Debug for ErrorChannelCycle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the channel ")?;
+        f.write_str(self.0.to_string().as_str())?;
+        f.write_str(" can't be moved below one of its own subchannels")
+    }
+}
+
+impl Display for ErrorChannelCycle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorChannelCycle {}
+
+pub struct ErrorDefaultChannelInSubtree(Uuid);
+
+impl Debug for ErrorDefaultChannelInSubtree {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the channel ")?;
+        f.write_str(self.0.to_string().as_str())?;
+        f.write_str(" contains the default channel and may not be deleted")
+    }
+}
+
+impl Display for ErrorDefaultChannelInSubtree {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorDefaultChannelInSubtree {}
+
+pub struct ErrorUnknownChannelGroup(pub(crate) Uuid);
+
+impl Debug for ErrorUnknownChannelGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there is no channel group with the uuid ")?;
+        f.write_str(self.0.to_string().as_str())
+    }
+}
+
+impl Display for ErrorUnknownChannelGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownChannelGroup {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(parent: Option<Uuid>, sort_id: u16) -> Arc<Channel> {
+        Arc::new(Channel {
+            uuid: Uuid::new_v4(),
+            parent: Arc::new(SwapArc::new(Arc::new(parent))),
+            password: AtomicBool::new(false),
+            name: Arc::new(SwapArc::new(Arc::new(String::new()))),
+            desc: Arc::new(SwapArc::new(Arc::new(String::new()))),
+            perms: Arc::new(SwapArc::new(Arc::new(None))),
+            clients: Arc::new(Default::default()),
+            proto_clients: Arc::new(Default::default()),
+            slots: AtomicI16::new(-1),
+            sort_id: AtomicU16::new(sort_id),
+            user_groups: Arc::new(SwapArc::new(Arc::new(vec![]))),
+            lifetime: ChannelLifetime::Permanent,
+            empty_since: AtomicU64::new(0),
+        })
+    }
+
+    // root
+    // ├── a
+    // │   ├── a1
+    // │   └── a2
+    // │       └── a2x
+    // └── b
+    // other
+    fn tree() -> (HashMap<Uuid, Arc<Channel>>, [Uuid; 7]) {
+        let root = channel(None, 0);
+        let other = channel(None, 1);
+        let a = channel(Some(root.uuid), 0);
+        let b = channel(Some(root.uuid), 1);
+        let a1 = channel(Some(a.uuid), 0);
+        let a2 = channel(Some(a.uuid), 1);
+        let a2x = channel(Some(a2.uuid), 0);
+        let ids = [root.uuid, a.uuid, a1.uuid, a2.uuid, a2x.uuid, b.uuid, other.uuid];
+        let channels = [root, other, a, b, a1, a2, a2x].into_iter().map(|channel| (channel.uuid, channel)).collect();
+        (channels, ids)
+    }
+
+    #[test]
+    fn subtree_puts_children_before_their_parents() {
+        let (channels, [root, a, a1, a2, a2x, b, _other]) = tree();
+        assert_eq!(subtree(&channels, &root), vec![a1, a2x, a2, a, b, root]);
+        assert_eq!(subtree(&channels, &a2), vec![a2x, a2]);
+        assert_eq!(subtree(&channels, &a2x), vec![a2x]);
+    }
+
+    #[test]
+    fn tree_order_is_depth_first() {
+        let (channels, [root, a, a1, a2, a2x, b, other]) = tree();
+        let order = tree_order(&channels).into_iter().map(|(depth, channel)| (depth, channel.uuid)).collect::<Vec<_>>();
+        assert_eq!(order, vec![(0, root), (1, a), (2, a1), (2, a2), (3, a2x), (1, b), (0, other)]);
+        assert_eq!(next_sort_id(&channels, Some(a)), 2);
+        assert_eq!(next_sort_id(&channels, Some(b)), 0);
+    }
+
+    #[test]
+    fn channels_cant_be_moved_below_their_descendants() {
+        let (channels, [root, a, _a1, a2, a2x, b, other]) = tree();
+        assert!(check_move(&channels, &a, Some(&a)).unwrap_err().is::<ErrorChannelCycle>());
+        assert!(check_move(&channels, &a, Some(&a2x)).unwrap_err().is::<ErrorChannelCycle>());
+        assert!(check_move(&channels, &root, Some(&b)).unwrap_err().is::<ErrorChannelCycle>());
+        assert!(check_move(&channels, &a, Some(&Uuid::new_v4())).unwrap_err().is::<ErrorUnknownChannel>());
+        assert!(check_move(&channels, &Uuid::new_v4(), None).unwrap_err().is::<ErrorUnknownChannel>());
+        check_move(&channels, &a2, Some(&b)).unwrap();
+        check_move(&channels, &a, Some(&other)).unwrap();
+        check_move(&channels, &a2x, None).unwrap();
+    }
+
+    #[test]
+    fn names_only_have_to_be_unique_among_siblings() {
+        let (channels, [root, a, a1, a2, _a2x, _b, _other]) = tree();
+        channels[&a1].name.store(Arc::new("Lobby".to_string()));
+        assert!(is_name_taken(&channels, Some(a), "lobby", None));
+        assert!(!is_name_taken(&channels, Some(a), "lobby", Some(&a1)));
+        assert!(!is_name_taken(&channels, Some(a2), "lobby", None));
+        assert!(!is_name_taken(&channels, Some(root), "lobby", None));
+    }
+
+    #[test]
+    fn perms_are_inherited_from_the_nearest_ancestor() {
+        let (channels, [root, a, _a1, a2, a2x, b, _other]) = tree();
+        channels[&a].perms.store(Arc::new(Some(ChannelPerms {
+            join: 20,
+            ..ChannelPerms::default()
+        })));
+        channels[&a2].perms.store(Arc::new(Some(ChannelPerms {
+            join: 50,
+            ..ChannelPerms::default()
+        })));
+        assert_eq!(effective_perms(&channels, &a2x).join, 50);
+        assert_eq!(effective_perms(&channels, &a).join, 20);
+        assert_eq!(effective_perms(&channels, &b).join, ChannelPerms::default().join);
+        assert_eq!(effective_perms(&channels, &root).join, ChannelPerms::default().join);
+    }
+}
@@ -0,0 +1,90 @@
+// users only learn about the channels they can see, a channel is visible if the user's see power
+// reaches the channel's see requirement and its parent is visible as well. the default channel and
+// the user's current channel (alongside its ancestors) are always visible
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use bytes::BytesMut;
+use uuid::Uuid;
+use crate::channel_subscriptions::channel_for;
+use crate::channel_tree::{effective_perms, tree_order, user_perms};
+use crate::packet::{Channel, ChannelGroup, ChannelUpdate, ServerGroup, ServerPacket};
+use crate::perms;
+use crate::{Server, User};
+
+/// returns the channels `user` can see
+pub fn visible_channels(channels: &HashMap<Uuid, Arc<Channel>>, server_groups: &HashMap<Uuid, Arc<ServerGroup>>, channel_groups: &HashMap<Uuid, Arc<ChannelGroup>>, user: &User, default_channel: &Uuid) -> HashSet<Uuid> {
+    let mut visible = HashSet::new();
+    // parents are visited before their children
+    for (_, channel) in tree_order(channels) {
+        let parent_visible = channel.parent.load().as_ref().map_or(true, |parent| visible.contains(parent));
+        if parent_visible && effective_perms(channels, &channel.uuid).see <= user_perms(channels, server_groups, channel_groups, &channel.uuid, user).granted(perms::CHANNEL_SEE) {
+            visible.insert(channel.uuid);
+        }
+    }
+    for forced in [*default_channel, user.channel.load().uuid] {
+        let mut curr = channels.get(&forced);
+        while let Some(channel) = curr {
+            visible.insert(channel.uuid);
+            curr = channel.parent.load().as_ref().and_then(|parent| channels.get(parent));
+        }
+    }
+    visible
+}
+
+impl Server {
+
+    /// sends `packet` to all users that can see `channel`
+    pub async fn send_to_viewers(&self, channel: &Uuid, packet: &BytesMut) {
+        let viewers = self.online_users.iter()
+            .filter(|user| user.visible_channels.lock().unwrap().contains(channel))
+            .map(|user| user.connection.clone())
+            .collect::<Vec<_>>();
+        for viewer in viewers {
+            viewer.send_reliable(packet).await.unwrap(); // FIXME: handle errors properly!
+        }
+    }
+
+    /// recalculates which channels `user` can see and sends them the channels that became visible or hidden
+    pub async fn refresh_visibility(&self, user: &Arc<User>) -> anyhow::Result<()> {
+        let default_channel = self.default_channel_id();
+        let (shown, hidden) = {
+            let channels = self.channels.read().await;
+            let server_groups = self.server_groups.read().await;
+            let channel_groups = self.channel_groups.read().await;
+            let visible = visible_channels(&channels, &server_groups, &channel_groups, user, &default_channel);
+            let prev = std::mem::replace(&mut *user.visible_channels.lock().unwrap(), visible.clone());
+            // users can't stay subscribed to channels they can no longer see
+            user.subscribed_channels.lock().unwrap().retain(|channel| visible.contains(channel));
+            // parents have to be shown before and hidden after their children
+            let order = tree_order(&channels);
+            let shown = order.iter()
+                .filter(|(_, channel)| visible.contains(&channel.uuid) && !prev.contains(&channel.uuid))
+                .map(|(_, channel)| channel_for(user, channel))
+                .collect::<Vec<_>>();
+            let mut hidden = order.iter()
+                .map(|(_, channel)| channel.uuid)
+                .filter(|channel| prev.contains(channel) && !visible.contains(channel))
+                .collect::<Vec<_>>();
+            hidden.reverse();
+            (shown, hidden)
+        };
+        for channel in shown {
+            let packet = ServerPacket::ChannelUpdate(ChannelUpdate::Create(channel)).encode()?;
+            user.connection.send_reliable(&packet).await?;
+        }
+        for channel in hidden {
+            let packet = ServerPacket::ChannelUpdate(ChannelUpdate::Delete(channel)).encode()?;
+            user.connection.send_reliable(&packet).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn refresh_all_visibility(&self) -> anyhow::Result<()> {
+        for user in self.online_users.iter().map(|user| user.value().clone()).collect::<Vec<_>>() {
+            self.refresh_visibility(&user).await?;
+        }
+        Ok(())
+    }
+
+}
@@ -1,16 +1,114 @@
 use crate::network::AddressMode;
 use serde_derive::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use crate::DEFAULT_CHANNEL_UUID;
+use crate::mailbox_db::MailboxLimits;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     pub address_mode: AddressMode,
+    #[serde(default)]
+    pub bind_address: Option<IpAddr>, // binds to the wildcard address of the address mode if none
     pub port: u16,
     pub req_security_level: u8,
     pub default_channel_id: u128,
+    #[serde(default = "default_mailbox_max_messages")]
+    pub mailbox_max_messages: u64,
+    #[serde(default = "default_mailbox_max_bytes")]
+    pub mailbox_max_bytes: u64,
+    #[serde(default = "default_mailbox_expiry_secs")]
+    pub mailbox_expiry_secs: u64,
+    #[serde(default = "default_mailbox_sender_max_messages")]
+    pub mailbox_sender_max_messages: u64, // the number of messages a single sender may store in a receiver's mailbox per expiry period
+    #[serde(default = "default_poke_cooldown_millis")]
+    pub poke_cooldown_millis: u64, // the minimum time between two pokes of the same sender
+    #[serde(default = "default_poke_max_len")]
+    pub poke_max_len: usize,
+    #[serde(default = "default_temp_channel_delete_delay_secs")]
+    pub temp_channel_delete_delay_secs: u64, // the time a temporary channel stays after the last user left
+    #[serde(default = "default_persist_semi_permanent_channels")]
+    pub persist_semi_permanent_channels: bool, // whether semi-permanent channels are kept across restarts
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default)]
+    pub welcome_message: String,
+    #[serde(default = "default_max_clients")]
+    pub max_clients: u32, // the number of slots that is shown to clients querying our info
+    #[serde(default)]
+    pub icon_path: Option<PathBuf>, // a png image clients show in their server list
+    #[serde(default = "default_info_query_cooldown_millis")]
+    pub info_query_cooldown_millis: u64, // the minimum time between two info queries from the same address
+    #[serde(default = "default_sealed_message_cooldown_millis")]
+    pub sealed_message_cooldown_millis: u64, // the minimum time between two sealed messages from the same address
+    #[serde(default = "default_sealed_keys_max")]
+    pub sealed_keys_max: u64, // the number of sealed sender access keys a user may have registered at the same time
+}
+
+/// the keys that can be read and changed while the server is running
+pub const CONFIG_KEYS: [&str; 20] = ["address_mode", "bind_address", "port", "req_security_level", "default_channel_id", "mailbox_max_messages",
+    "mailbox_max_bytes", "mailbox_expiry_secs", "mailbox_sender_max_messages", "poke_cooldown_millis", "poke_max_len", "temp_channel_delete_delay_secs",
+    "persist_semi_permanent_channels", "name", "welcome_message", "max_clients", "icon_path", "info_query_cooldown_millis",
+    "sealed_message_cooldown_millis", "sealed_keys_max"];
+
+/// the keys whose new values only take effect after a restart as the network server can't be rebound at runtime
+pub const RESTART_KEYS: [&str; 3] = ["address_mode", "bind_address", "port"];
+
+fn default_mailbox_max_messages() -> u64 {
+    256
+}
+
+fn default_mailbox_max_bytes() -> u64 {
+    4 * 1024 * 1024
+}
+
+fn default_mailbox_expiry_secs() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_mailbox_sender_max_messages() -> u64 {
+    32
+}
+
+fn default_poke_cooldown_millis() -> u64 {
+    1000
+}
+
+fn default_poke_max_len() -> usize {
+    100
+}
+
+fn default_temp_channel_delete_delay_secs() -> u64 {
+    30
+}
+
+fn default_persist_semi_permanent_channels() -> bool {
+    true
+}
+
+fn default_name() -> String {
+    String::from("RustSpeak Server")
+}
+
+fn default_max_clients() -> u32 {
+    100
+}
+
+fn default_sealed_message_cooldown_millis() -> u64 {
+    1000
+}
+
+fn default_info_query_cooldown_millis() -> u64 {
+    1000
+}
+
+fn default_sealed_keys_max() -> u64 {
+    64
 }
 
 impl Config {
@@ -31,15 +129,168 @@ impl Config {
             def
         })
     }
+
+    /// reads the config at `src` as it is, unlike `load_or_create` a missing file is an error and invalid values aren't corrected
+    pub fn load(src: &Path) -> anyhow::Result<Self> {
+        let mut content = String::new();
+        File::open(src)?.read_to_string(&mut content)?;
+        Ok(serde_json::from_str(content.as_str())?)
+    }
+
+    pub fn save(&self, dst: &Path) -> anyhow::Result<()> {
+        let mut file = File::create(dst)?;
+        file.write(serde_json::to_string(self)?.as_ref())?;
+        Ok(())
+    }
+
+    /// returns the value of `key` in the same format `set` accepts
+    pub fn get(&self, key: &str) -> anyhow::Result<String> {
+        Ok(match key {
+            "address_mode" => format!("{:?}", self.address_mode),
+            "bind_address" => self.bind_address.map_or(String::from("none"), |addr| addr.to_string()),
+            "port" => self.port.to_string(),
+            "req_security_level" => self.req_security_level.to_string(),
+            "default_channel_id" => format!("{:x}", self.default_channel_id),
+            "mailbox_max_messages" => self.mailbox_max_messages.to_string(),
+            "mailbox_max_bytes" => self.mailbox_max_bytes.to_string(),
+            "mailbox_expiry_secs" => self.mailbox_expiry_secs.to_string(),
+            "mailbox_sender_max_messages" => self.mailbox_sender_max_messages.to_string(),
+            "poke_cooldown_millis" => self.poke_cooldown_millis.to_string(),
+            "poke_max_len" => self.poke_max_len.to_string(),
+            "temp_channel_delete_delay_secs" => self.temp_channel_delete_delay_secs.to_string(),
+            "persist_semi_permanent_channels" => self.persist_semi_permanent_channels.to_string(),
+            "name" => self.name.clone(),
+            "welcome_message" => self.welcome_message.clone(),
+            "max_clients" => self.max_clients.to_string(),
+            "icon_path" => self.icon_path.as_ref().map_or(String::from("none"), |path| path.display().to_string()),
+            "info_query_cooldown_millis" => self.info_query_cooldown_millis.to_string(),
+            "sealed_message_cooldown_millis" => self.sealed_message_cooldown_millis.to_string(),
+            "sealed_keys_max" => self.sealed_keys_max.to_string(),
+            _ => return Err(anyhow::Error::from(ErrorUnknownConfigKey(key.to_string()))),
+        })
+    }
+
+    /// parses `value` and stores it in `key`, this doesn't check whether the resulting config is valid
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let invalid = || anyhow::Error::from(ErrorInvalidConfigValue(key.to_string(), value.to_string()));
+        match key {
+            "address_mode" => self.address_mode = if value.eq_ignore_ascii_case("v4") {
+                AddressMode::V4
+            } else if value.eq_ignore_ascii_case("v6") {
+                AddressMode::V6
+            } else if value.eq_ignore_ascii_case("dualstack") {
+                AddressMode::DualStack
+            } else {
+                return Err(invalid());
+            },
+            "bind_address" => self.bind_address = if value.eq_ignore_ascii_case("none") {
+                None
+            } else {
+                Some(value.parse().map_err(|_| invalid())?)
+            },
+            "port" => self.port = value.parse().map_err(|_| invalid())?,
+            "req_security_level" => self.req_security_level = value.parse().map_err(|_| invalid())?,
+            "default_channel_id" => self.default_channel_id = u128::from_str_radix(value, 16).map_err(|_| invalid())?,
+            "mailbox_max_messages" => self.mailbox_max_messages = value.parse().map_err(|_| invalid())?,
+            "mailbox_max_bytes" => self.mailbox_max_bytes = value.parse().map_err(|_| invalid())?,
+            "mailbox_expiry_secs" => self.mailbox_expiry_secs = value.parse().map_err(|_| invalid())?,
+            "mailbox_sender_max_messages" => self.mailbox_sender_max_messages = value.parse().map_err(|_| invalid())?,
+            "poke_cooldown_millis" => self.poke_cooldown_millis = value.parse().map_err(|_| invalid())?,
+            "poke_max_len" => self.poke_max_len = value.parse().map_err(|_| invalid())?,
+            "temp_channel_delete_delay_secs" => self.temp_channel_delete_delay_secs = value.parse().map_err(|_| invalid())?,
+            "persist_semi_permanent_channels" => self.persist_semi_permanent_channels = value.parse().map_err(|_| invalid())?,
+            "name" => self.name = value.to_string(),
+            "welcome_message" => self.welcome_message = value.to_string(),
+            "max_clients" => self.max_clients = value.parse().map_err(|_| invalid())?,
+            "icon_path" => self.icon_path = if value.eq_ignore_ascii_case("none") {
+                None
+            } else {
+                Some(PathBuf::from(value))
+            },
+            "info_query_cooldown_millis" => self.info_query_cooldown_millis = value.parse().map_err(|_| invalid())?,
+            "sealed_message_cooldown_millis" => self.sealed_message_cooldown_millis = value.parse().map_err(|_| invalid())?,
+            "sealed_keys_max" => self.sealed_keys_max = value.parse().map_err(|_| invalid())?,
+            _ => return Err(anyhow::Error::from(ErrorUnknownConfigKey(key.to_string()))),
+        }
+        Ok(())
+    }
+
+    /// checks the values that don't depend on the server's state
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.req_security_level < 1 {
+            return Err(anyhow::Error::from(ErrorInvalidConfigValue(String::from("req_security_level"), self.req_security_level.to_string())));
+        }
+        Ok(())
+    }
+
+    pub fn mailbox_limits(&self) -> MailboxLimits {
+        MailboxLimits {
+            max_messages: self.mailbox_max_messages,
+            max_bytes: self.mailbox_max_bytes,
+            expiry: Duration::from_secs(self.mailbox_expiry_secs),
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             address_mode: AddressMode::V4,
+            bind_address: None,
             port: 20354,
             req_security_level: 12,
             default_channel_id: DEFAULT_CHANNEL_UUID.as_u128(),
+            mailbox_max_messages: default_mailbox_max_messages(),
+            mailbox_max_bytes: default_mailbox_max_bytes(),
+            mailbox_expiry_secs: default_mailbox_expiry_secs(),
+            mailbox_sender_max_messages: default_mailbox_sender_max_messages(),
+            poke_cooldown_millis: default_poke_cooldown_millis(),
+            poke_max_len: default_poke_max_len(),
+            temp_channel_delete_delay_secs: default_temp_channel_delete_delay_secs(),
+            persist_semi_permanent_channels: default_persist_semi_permanent_channels(),
+            name: default_name(),
+            welcome_message: String::new(),
+            max_clients: default_max_clients(),
+            icon_path: None,
+            info_query_cooldown_millis: default_info_query_cooldown_millis(),
+            sealed_message_cooldown_millis: default_sealed_message_cooldown_millis(),
+            sealed_keys_max: default_sealed_keys_max(),
         }
     }
 }
+
+pub struct ErrorUnknownConfigKey(String);
+
+impl Debug for ErrorUnknownConfigKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("There is no config key named ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorUnknownConfigKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownConfigKey {}
+
+pub struct ErrorInvalidConfigValue(String, String);
+
+impl Debug for ErrorInvalidConfigValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("\"")?;
+        f.write_str(self.1.as_str())?;
+        f.write_str("\" is not a valid value for ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorInvalidConfigValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorInvalidConfigValue {}
@@ -0,0 +1,52 @@
+// the config can be changed while the server is running, subsystems load the current config whenever
+// they need one of its values so new values are picked up without dropping any connections
+
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::channel_tree::ErrorUnknownChannel;
+use crate::config::Config;
+use crate::packet::ServerPacket;
+use crate::Server;
+
+impl Server {
+
+    /// applies `update` to a copy of the current config, validates the result and persists it to config.json
+    pub async fn update_config<F: FnOnce(&mut Config) -> anyhow::Result<()>>(&self, update: F) -> anyhow::Result<()> {
+        let _guard = self.config_update.lock().await;
+        let mut config = Config::clone(&self.config.load());
+        update(&mut config)?;
+        self.validate_config(&config).await?;
+        config.save(&self.config_path)?;
+        self.apply_config(config).await
+    }
+
+    /// reads config.json again and applies it, the config stays unchanged if the file is missing or invalid
+    pub async fn reload_config(&self) -> anyhow::Result<()> {
+        let _guard = self.config_update.lock().await;
+        let config = Config::load(&self.config_path)?;
+        self.validate_config(&config).await?;
+        self.apply_config(config).await
+    }
+
+    async fn validate_config(&self, config: &Config) -> anyhow::Result<()> {
+        config.validate()?;
+        let default_channel = Uuid::from_u128(config.default_channel_id);
+        if !self.channels.read().await.contains_key(&default_channel) {
+            return Err(anyhow::Error::from(ErrorUnknownChannel(default_channel)));
+        }
+        Ok(())
+    }
+
+    async fn apply_config(&self, config: Config) -> anyhow::Result<()> {
+        let prev = self.config.swap(Arc::new(config));
+        let default_channel = self.default_channel_id();
+        if prev.default_channel_id != default_channel.as_u128() {
+            // the default channel is visible to everyone
+            self.refresh_all_visibility().await?;
+            let packet = ServerPacket::DefaultChannel(default_channel).encode()?;
+            self.broadcast(&packet).await;
+        }
+        Ok(())
+    }
+
+}
@@ -0,0 +1,84 @@
+// clients can query a server's info without logging in so they can show it in their server list,
+// the queries are answered on anonymous connections
+
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use crate::packet::ServerInfo;
+use crate::protocol::VersionRange;
+use crate::Server;
+
+// the icon is sent along with every answer, so it has to stay small
+const ICON_SIZE_LIMIT: u64 = 64 * 1024;
+// the addresses that sent us requests are only cleaned up once there are this many of them
+const QUERY_CLEANUP_THRESHOLD: usize = 1024;
+
+/// returns whether `ip` may send another request, `requests` holds the time of the last request of each address
+pub(crate) fn is_request_allowed(requests: &DashMap<IpAddr, Instant>, ip: IpAddr, cooldown: Duration) -> bool {
+    let now = Instant::now();
+    if requests.len() >= QUERY_CLEANUP_THRESHOLD {
+        requests.retain(|_, last| now.duration_since(*last) < cooldown);
+    }
+    match requests.entry(ip) {
+        Entry::Occupied(mut entry) => {
+            if now.duration_since(*entry.get()) < cooldown {
+                return false;
+            }
+            entry.insert(now);
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(now);
+        }
+    }
+    true
+}
+
+impl Server {
+
+    /// returns whether `ip` may query our info, addresses that query too often get no answer
+    pub fn is_info_query_allowed(&self, ip: IpAddr) -> bool {
+        is_request_allowed(&self.info_queries, ip, Duration::from_millis(self.config.load().info_query_cooldown_millis))
+    }
+
+    pub async fn server_info(&self) -> ServerInfo {
+        // the icon is read before loading the config, so the config isn't held across the read
+        let icon_path = self.config.load().icon_path.clone();
+        let icon = match icon_path {
+            Some(path) => self.read_icon(&path).await,
+            None => None,
+        };
+        let config = self.config.load();
+        ServerInfo {
+            name: config.name.clone(),
+            welcome_message: config.welcome_message.clone(),
+            online: self.online_users.len() as u32,
+            slots: config.max_clients,
+            req_security_level: config.req_security_level,
+            protocol_versions: VersionRange::SUPPORTED,
+            icon,
+        }
+    }
+
+    /// icons that can't be read or are too large are left out of the info
+    async fn read_icon(&self, path: &Path) -> Option<Vec<u8>> {
+        let icon = match tokio::fs::metadata(path).await {
+            Ok(metadata) if metadata.len() > ICON_SIZE_LIMIT => Ok(None),
+            Ok(_) => tokio::fs::read(path).await.map(Some),
+            Err(err) => Err(err),
+        };
+        match icon {
+            Ok(None) => {
+                self.println(format!("The server icon is larger than {} bytes", ICON_SIZE_LIMIT).as_str());
+                None
+            }
+            Ok(icon) => icon,
+            Err(err) => {
+                self.println(format!("Couldn't read the server icon: {}", err).as_str());
+                None
+            }
+        }
+    }
+
+}
@@ -0,0 +1,385 @@
+use crate::packet::{DirectMessageContent, Encrypted, MailMessage, SealedAccessKey, Signed};
+use crate::utils::current_time_millis;
+use crate::{RWBytes, UserUuid};
+use bytes::{Bytes, BytesMut};
+use openssl::pkey::PKey;
+use sled::{Db, IVec, Tree};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// stores end-to-end encrypted messages for users whose home server this is
+/// while they are offline, the server never sees the plaintext of these messages
+pub struct MailboxDb {
+    db: Db,
+    sealed_keys: Tree, // the access keys users handed out to their contacts for sending sealed messages
+}
+
+impl MailboxDb {
+    pub fn new(path: String) -> anyhow::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            sealed_keys: db.open_tree("sealed_keys")?,
+            db,
+        })
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        // this flushes all trees as they share their buffers
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// queues a message for `receiver`, the returned message is the one that was stored
+    pub fn insert(&self, receiver: &UserUuid, sealed_key: Option<Uuid>, payload: Encrypted<DirectMessageContent>, limits: &MailboxLimits) -> anyhow::Result<Result<MailMessage, MailboxError>> {
+        if payload.is_empty() || payload.len() as u64 > limits.max_bytes {
+            return Ok(Err(MailboxError::InvalidSize));
+        }
+        let now = current_time_millis();
+        let mut msg_count = 0;
+        let mut byte_count = payload.len() as u64;
+        for entry in self.db.scan_prefix(receiver) {
+            let (key, val) = entry?;
+            let msg = MailMessage::read(&mut Bytes::from(val.to_vec()))?;
+            if limits.is_expired(&msg, now) {
+                self.db.remove(key)?;
+                continue;
+            }
+            msg_count += 1;
+            byte_count += msg.payload.len() as u64;
+        }
+        if msg_count >= limits.max_messages || byte_count > limits.max_bytes {
+            return Ok(Err(MailboxError::QuotaExceeded));
+        }
+
+        let msg = MailMessage {
+            id: self.db.generate_id()?,
+            received: now,
+            sealed_key,
+            payload,
+        };
+        let mut buf = BytesMut::new();
+        msg.write(&mut buf)?;
+        self.db.insert(key(receiver, msg.id), IVec::from(buf.to_vec()))?;
+        Ok(Ok(msg))
+    }
+
+    /// returns all messages that are queued for `receiver` and weren't acknowledged yet
+    pub fn get_all(&self, receiver: &UserUuid, limits: &MailboxLimits) -> anyhow::Result<Vec<MailMessage>> {
+        let now = current_time_millis();
+        let mut result = vec![];
+        for entry in self.db.scan_prefix(receiver) {
+            let (key, val) = entry?;
+            let msg = MailMessage::read(&mut Bytes::from(val.to_vec()))?;
+            if limits.is_expired(&msg, now) {
+                self.db.remove(key)?;
+                continue;
+            }
+            result.push(msg);
+        }
+        Ok(result)
+    }
+
+    /// removes acknowledged messages, ids that don't belong to `receiver` are ignored
+    pub fn remove(&self, receiver: &UserUuid, ids: &[u64]) -> anyhow::Result<()> {
+        for id in ids {
+            self.db.remove(key(receiver, *id))?;
+        }
+        Ok(())
+    }
+
+    /// removes all expired messages of all users
+    pub fn purge_expired(&self, limits: &MailboxLimits) -> anyhow::Result<usize> {
+        let now = current_time_millis();
+        let mut purged = 0;
+        for entry in self.db.iter() {
+            let (key, val) = entry?;
+            let msg = MailMessage::read(&mut Bytes::from(val.to_vec()))?;
+            if limits.is_expired(&msg, now) {
+                self.db.remove(key)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// stores access keys `receiver` handed out to their contacts, the server doesn't know which
+    /// contact got which key. the keys are refused if `receiver` would end up with more than `max_keys` keys
+    pub fn register_sealed_keys(&self, receiver: &UserUuid, keys: &[SealedAccessKey], max_keys: u64) -> anyhow::Result<Result<(), MailboxError>> {
+        let now = current_time_millis();
+        let mut key_count = keys.len() as u64;
+        for entry in self.sealed_keys.scan_prefix(receiver) {
+            let (key, val) = entry?;
+            let access_key = SealedAccessKey::read(&mut Bytes::from(val.to_vec()))?;
+            if access_key.expiry < now {
+                self.sealed_keys.remove(key)?;
+                continue;
+            }
+            key_count += 1;
+        }
+        if key_count > max_keys {
+            return Ok(Err(MailboxError::QuotaExceeded));
+        }
+        for access_key in keys {
+            let mut buf = BytesMut::new();
+            access_key.write(&mut buf)?;
+            self.sealed_keys.insert(sealed_key(receiver, &access_key.id), IVec::from(buf.to_vec()))?;
+        }
+        Ok(Ok(()))
+    }
+
+    /// returns the access key with the given id if it was registered by `receiver` and hasn't expired yet
+    pub fn get_sealed_key(&self, receiver: &UserUuid, id: &Uuid) -> anyhow::Result<Option<SealedAccessKey>> {
+        let key = sealed_key(receiver, id);
+        Ok(match self.sealed_keys.get(&key)? {
+            None => None,
+            Some(raw) => {
+                let access_key = SealedAccessKey::read(&mut Bytes::from(raw.to_vec()))?;
+                if access_key.expiry < current_time_millis() {
+                    self.sealed_keys.remove(key)?;
+                    None
+                } else {
+                    Some(access_key)
+                }
+            }
+        })
+    }
+
+    /// verifies that `signed_payload` was signed with the access key `key_id` of `receiver` and returns the
+    /// payload if it was, nothing but the signature is checked as we mustn't learn who sent the message
+    pub fn open_sealed(&self, receiver: &UserUuid, key_id: &Uuid, signed_payload: Vec<u8>) -> anyhow::Result<Option<Encrypted<DirectMessageContent>>> {
+        Ok(self.get_sealed_key(receiver, key_id)?.and_then(|access_key| {
+            let pub_key = PKey::public_key_from_der(&access_key.pub_key).ok()?;
+            Signed::<Encrypted<DirectMessageContent>>::read(&mut Bytes::from(signed_payload), &pub_key).ok()
+        }))
+    }
+
+    /// removes all expired access keys of all users
+    pub fn purge_expired_sealed_keys(&self) -> anyhow::Result<usize> {
+        let now = current_time_millis();
+        let mut purged = 0;
+        for entry in self.sealed_keys.iter() {
+            let (key, val) = entry?;
+            let access_key = SealedAccessKey::read(&mut Bytes::from(val.to_vec()))?;
+            if access_key.expiry < now {
+                self.sealed_keys.remove(key)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+}
+
+// the user's uuid is used as a prefix so all messages of a user can be found via a prefix scan,
+// the id is stored in big endian so messages are iterated in the order they were received
+fn key(receiver: &UserUuid, id: u64) -> Vec<u8> {
+    let mut key = receiver.as_ref().to_vec();
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn sealed_key(receiver: &UserUuid, id: &Uuid) -> Vec<u8> {
+    let mut key = receiver.as_ref().to_vec();
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+pub struct MailboxLimits {
+    pub max_messages: u64,
+    pub max_bytes: u64,
+    pub expiry: Duration,
+}
+
+impl MailboxLimits {
+    fn is_expired(&self, msg: &MailMessage, now: Duration) -> bool {
+        now.saturating_sub(msg.received) > self.expiry
+    }
+}
+
+/// who stored a message, the senders of sealed messages are only known by the access key they used
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum MessageSender {
+    User(UserUuid),
+    Sealed(Uuid),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum MailboxError {
+    InvalidSize,
+    QuotaExceeded,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::Private;
+    use openssl::rsa::Rsa;
+    use openssl::sha::sha256;
+    use openssl::sign::Signer;
+    use ruint::aliases::U256;
+
+    fn temp_db() -> (MailboxDb, String) {
+        let path = std::env::temp_dir().join(format!("rustspeak_mailbox_test_{}", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        (MailboxDb::new(path.clone()).unwrap(), path)
+    }
+
+    fn limits() -> MailboxLimits {
+        MailboxLimits {
+            max_messages: 16,
+            max_bytes: 64 * 1024,
+            expiry: Duration::from_secs(3600),
+        }
+    }
+
+    fn access_key(key: &PKey<Private>, expiry: Duration) -> SealedAccessKey {
+        SealedAccessKey {
+            id: Uuid::new_v4(),
+            pub_key: key.public_key_to_der().unwrap(),
+            expiry,
+        }
+    }
+
+    // signs the payload the same way clients do, the signature followed by the encoded payload
+    fn sign(key: &PKey<Private>, payload: Vec<u8>) -> Vec<u8> {
+        let mut encoded = BytesMut::new();
+        payload.write(&mut encoded).unwrap();
+        let signature = Signer::new(MessageDigest::sha256(), key).unwrap().sign_oneshot_to_vec(&encoded).unwrap();
+        let mut signed = BytesMut::new();
+        signature.write(&mut signed).unwrap();
+        signed.extend_from_slice(&encoded);
+        signed.to_vec()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn sealed_messages_dont_store_the_sender() {
+        let (db, path) = temp_db();
+        let receiver = UserUuid::from_u256(U256::from(1));
+        let sender_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let sender_pub_key = sender_key.public_key_to_der().unwrap();
+        let sender = UserUuid::from_u256(U256::from_le_bytes(sha256(&sender_pub_key)));
+
+        // the receiver hands the access key to the sender who signs every sealed message with it
+        let access = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let registered = access_key(&access, current_time_millis() + Duration::from_secs(3600));
+        let key_id = registered.id;
+        db.register_sealed_keys(&receiver, &[registered], 8).unwrap().unwrap();
+        let payload = db.open_sealed(&receiver, &key_id, sign(&access, vec![42; 128])).unwrap().unwrap();
+        db.insert(&receiver, Some(key_id), payload, &limits()).unwrap().unwrap();
+        db.flush().unwrap();
+
+        for name in db.db.tree_names() {
+            for entry in db.db.open_tree(name).unwrap().iter() {
+                let (key, val) = entry.unwrap();
+                for raw in [&key, &val] {
+                    assert!(!contains(raw, sender.as_ref()));
+                    assert!(!contains(raw, &sender_pub_key));
+                }
+            }
+        }
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn sealed_messages_need_a_valid_signature() {
+        let (db, path) = temp_db();
+        let receiver = UserUuid::from_u256(U256::from(1));
+        let access = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let other = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let registered = access_key(&access, current_time_millis() + Duration::from_secs(3600));
+        let key_id = registered.id;
+        db.register_sealed_keys(&receiver, &[registered], 8).unwrap().unwrap();
+
+        assert!(db.open_sealed(&receiver, &key_id, sign(&other, vec![42; 16])).unwrap().is_none());
+        assert!(db.open_sealed(&receiver, &Uuid::new_v4(), sign(&access, vec![42; 16])).unwrap().is_none());
+        assert!(db.open_sealed(&UserUuid::from_u256(U256::from(2)), &key_id, sign(&access, vec![42; 16])).unwrap().is_none());
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn sealed_keys_are_limited() {
+        let (db, path) = temp_db();
+        let receiver = UserUuid::from_u256(U256::from(1));
+        let access = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let valid = current_time_millis() + Duration::from_secs(3600);
+
+        // expired keys don't count towards the limit
+        db.register_sealed_keys(&receiver, &[access_key(&access, Duration::ZERO)], 2).unwrap().unwrap();
+        db.register_sealed_keys(&receiver, &[access_key(&access, valid), access_key(&access, valid)], 2).unwrap().unwrap();
+        assert!(matches!(db.register_sealed_keys(&receiver, &[access_key(&access, valid)], 2).unwrap(), Err(MailboxError::QuotaExceeded)));
+        // other users have limits of their own
+        db.register_sealed_keys(&UserUuid::from_u256(U256::from(2)), &[access_key(&access, valid)], 2).unwrap().unwrap();
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    fn payload(len: usize) -> Encrypted<DirectMessageContent> {
+        Encrypted::new(vec![42; len])
+    }
+
+    #[test]
+    fn mailboxes_are_limited() {
+        let (db, path) = temp_db();
+        let receiver = UserUuid::from_u256(U256::from(1));
+        let limits = MailboxLimits {
+            max_messages: 2,
+            max_bytes: 100,
+            expiry: Duration::from_secs(3600),
+        };
+
+        assert!(matches!(db.insert(&receiver, None, payload(0), &limits).unwrap(), Err(MailboxError::InvalidSize)));
+        assert!(matches!(db.insert(&receiver, None, payload(101), &limits).unwrap(), Err(MailboxError::InvalidSize)));
+        db.insert(&receiver, None, payload(60), &limits).unwrap().unwrap();
+        // the messages of a user may not exceed the byte limit together
+        assert!(matches!(db.insert(&receiver, None, payload(41), &limits).unwrap(), Err(MailboxError::QuotaExceeded)));
+        db.insert(&receiver, None, payload(40), &limits).unwrap().unwrap();
+        assert!(matches!(db.insert(&receiver, None, payload(1), &limits).unwrap(), Err(MailboxError::QuotaExceeded)));
+        // other users have limits of their own
+        db.insert(&UserUuid::from_u256(U256::from(2)), None, payload(60), &limits).unwrap().unwrap();
+
+        // acknowledged messages free up space again
+        let ids = db.get_all(&receiver, &limits).unwrap().iter().map(|msg| msg.id).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 2);
+        db.remove(&receiver, &ids[..1]).unwrap();
+        db.insert(&receiver, None, payload(1), &limits).unwrap().unwrap();
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn expired_messages_are_dropped() {
+        let (db, path) = temp_db();
+        let receiver = UserUuid::from_u256(U256::from(1));
+        let other = UserUuid::from_u256(U256::from(2));
+        let limits = MailboxLimits {
+            max_messages: 1,
+            ..limits()
+        };
+        // messages can't be backdated through the api, so the expired ones are written directly
+        for receiver in [&receiver, &other] {
+            let msg = MailMessage {
+                id: db.db.generate_id().unwrap(),
+                received: current_time_millis() - limits.expiry - Duration::from_secs(1),
+                sealed_key: None,
+                payload: payload(16),
+            };
+            let mut buf = BytesMut::new();
+            msg.write(&mut buf).unwrap();
+            db.db.insert(key(receiver, msg.id), IVec::from(buf.to_vec())).unwrap();
+        }
+
+        assert!(db.get_all(&receiver, &limits).unwrap().is_empty());
+        // expired messages don't count towards the quota either
+        db.insert(&receiver, None, payload(16), &limits).unwrap().unwrap();
+        assert_eq!(db.purge_expired(&limits).unwrap(), 1);
+        assert_eq!(db.get_all(&receiver, &limits).unwrap().len(), 1);
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -7,12 +7,18 @@
 #![feature(lazy_cell)]
 
 use crate::channel_db::{ChannelDb, ChannelDbEntry};
-use crate::config::Config;
+use crate::channel_subscriptions::channel_for;
+use crate::channel_visibility::visible_channels;
+use crate::config::{Config, CONFIG_KEYS, RESTART_KEYS};
 use crate::network::{ClientConnection, handle_packet, NetworkServer};
-use crate::packet::{AuthFailure, AuthResponse, Channel, ChannelCreatePerms, ChannelPerms, ClientPacket, RemoteProfile, ServerGroup, PermsSnapshot, ServerPacket, DisconnectReason};
-use crate::protocol::{RWBytes, UserUuid, PROTOCOL_VERSION};
+use crate::packet::{AuthFailure, AuthResponse, Channel, ChannelGroup, ChannelGroupAssignment, ChannelLifetime, ChannelPerms, ClientPacket, RemoteProfile, ServerGroup, ServerPacket, DisconnectReason};
+use crate::perms::{PermValue, Perms};
+use crate::protocol::{Features, RWBytes, UserUuid, VersionRange, FEATURES};
+use crate::mailbox_db::{MailboxDb, MessageSender};
 use crate::server_group_db::{ServerGroupDb, ServerGroupEntry};
-use crate::user_db::{DbUser, UserDb};
+use crate::shutdown::{ErrorShutdownScheduled, ERROR_EXIT_CODE};
+use crate::channel_group_db::{ChannelGroupDb, ChannelGroupEntry};
+use crate::user_db::{DbUser, TokenGrant, UserDb};
 use crate::utils::{LIGHT_GRAY, parse_bool};
 use bytes::Buf;
 use clitty::core::{CmdParamEnumConstraints, CmdParamNumConstraints, CmdParamStrConstraints, CommandBuilder, CommandImpl, CommandParam, CommandParamTy, EnumVal, UsageBuilder};
@@ -24,20 +30,21 @@ use ruint::aliases::U256;
 use sled::Db;
 use std::borrow::Cow;
 use std::cell::{LazyCell, RefCell};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter, Write};
 use std::fs::File;
 use std::net::IpAddr;
 use std::num::NonZeroUsize;
 use std::ops::Deref;
-use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU16, Ordering};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicI16, AtomicI32, AtomicU16, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::{fs, thread};
+use std::{fs, process, thread};
 use std::future::Future;
 use std::str::FromStr;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use arc_swap::ArcSwap;
 use crossbeam_utils::Backoff;
 use futures::StreamExt;
@@ -48,27 +55,49 @@ use tokio::{join, select};
 use uuid::Uuid;
 use crate::conc_once_cell::ConcurrentOnceCell;
 
+mod anonymous;
 mod certificate;
 mod channel_db;
+mod channel_group_db;
+mod channel_subscriptions;
+mod channel_tree;
+mod channel_visibility;
 mod config;
+mod config_reload;
+mod info_query;
 mod network;
 mod packet;
+mod perms;
+mod privilege_tokens;
 mod protocol;
 mod security_level;
+mod shutdown;
 mod server_group_db;
+mod server_groups;
 mod user_db;
 mod utils;
 mod conc_once_cell;
 mod sized_box;
 mod conc_vec;
+mod mailbox_db;
 
 // FIXME: review all the endianness related shit!
 
 const RELATIVE_USER_DB_PATH: &str = "user_db";
 const RELATIVE_CHANNEL_DB_PATH: &str = "channel_db.json";
 const RELATIVE_SERVER_GROUP_DB_PATH: &str = "server_group_db.json";
+const RELATIVE_CHANNEL_GROUP_DB_PATH: &str = "channel_group_db.json";
+const RELATIVE_MAILBOX_DB_PATH: &str = "mailbox_db";
+const MAILBOX_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const TEMP_CHANNEL_PURGE_INTERVAL: Duration = Duration::from_secs(1);
 const ADMIN_GROUP_UUID: Uuid = Uuid::from_u128(0x1);
 const DEFAULT_GROUP_UUID: Uuid = Uuid::from_u128(0x0);
+const ADMIN_POWER: u64 = 100; // the power and priority of the admin group in a freshly created server group db
+// the channel groups that are created along with a fresh channel group db, channel groups are stored apart from
+// server groups so their uuids don't collide with the server groups' uuids even though both start at 0
+const CHANNEL_GUEST_GROUP_UUID: Uuid = Uuid::from_u128(0x0);
+const CHANNEL_ADMIN_GROUP_UUID: Uuid = Uuid::from_u128(0x1);
+const CHANNEL_OPERATOR_GROUP_UUID: Uuid = Uuid::from_u128(0x2);
 const DEFAULT_CHANNEL_UUID: Uuid = Uuid::from_u128(0x0);
 
 // FIXME: take a look at: https://www.nist.gov/news-events/news/2022/07/nist-announces-first-four-quantum-resistant-cryptographic-algorithms
@@ -86,6 +115,14 @@ fn main() -> anyhow::Result<()> {
             .unwrap()
             .to_string(),
     )?;
+    let mailbox_db = MailboxDb::new(
+        data_dir
+            .clone()
+            .join(RELATIVE_MAILBOX_DB_PATH)
+            .to_str()
+            .unwrap()
+            .to_string(),
+    )?;
     let channel_db = ChannelDb::new(
         data_dir
             .clone()
@@ -97,25 +134,28 @@ fn main() -> anyhow::Result<()> {
         .read_or_create(|| {
             Ok(vec![ChannelDbEntry {
                 id: DEFAULT_CHANNEL_UUID.as_u128(),
+                parent: None,
                 sort_id: 0,
                 name: Cow::Borrowed("Lobby"),
                 desc: Default::default(),
                 password: None,
                 user_groups: vec![],
-                perms: ChannelPerms {
+                perms: Some(ChannelPerms {
                     see: 0,
                     join: 0,
                     modify: 100,
                     talk: 0,
                     assign_talk: 100,
                     delete: 100,
-                },
+                }),
                 slots: -1,
+                lifetime: ChannelLifetime::Permanent,
             }])
         })?
         .into_iter()
         .map(|entry| Channel {
             uuid: Uuid::from_u128(entry.id),
+            parent: Arc::new(SwapArc::new(Arc::new(entry.parent.map(Uuid::from_u128)))),
             password: AtomicBool::new(entry.password.is_some()),
             name: Arc::new(SwapArc::new(Arc::new(entry.name.to_string()))),
             desc: Arc::new(SwapArc::new(Arc::new(entry.desc.to_string()))),
@@ -124,6 +164,12 @@ fn main() -> anyhow::Result<()> {
             proto_clients: Arc::new(Default::default()),
             slots: AtomicI16::new(entry.slots),
             sort_id: AtomicU16::new(entry.sort_id),
+            user_groups: Arc::new(SwapArc::new(Arc::new(entry.user_groups.iter().map(|(user, group)| ChannelGroupAssignment {
+                user: UserUuid::from_u256(user.unwrap()),
+                group: Uuid::from_u128(*group),
+            }).collect()))),
+            lifetime: entry.lifetime,
+            empty_since: AtomicU64::new(0),
         })
         .collect::<Vec<_>>();
     let channels = {
@@ -143,48 +189,27 @@ fn main() -> anyhow::Result<()> {
             .to_string(),
     );
     let server_groups = server_group_db.read_or_create(|| {
+        let mut admin_perms = Perms::default();
+        for key in perms::PERM_KEYS {
+            admin_perms.set_granted(key, ADMIN_POWER);
+        }
+        let mut default_perms = Perms::default();
+        default_perms.set_granted(perms::CHANNEL_CREATE_TEMPORARY, 1);
+        default_perms.set_granted(perms::POKE, 1);
         Ok(vec![
             ServerGroupEntry {
                 uuid: ADMIN_GROUP_UUID.as_u128(),
                 name: Cow::Borrowed("admin"),
-                perms: PermsSnapshot {
-                    server_group_assign: 0,
-                    server_group_unassign: 0,
-                    channel_see: 0,
-                    channel_join: 0,
-                    channel_modify: 0,
-                    channel_talk: 0,
-                    channel_assign_talk: 0,
-                    channel_delete: 0,
-                    can_send: false,
-                    channel_create: ChannelCreatePerms {
-                        power: 0,
-                        set_desc: false,
-                        set_password: false,
-                        resort_channel: false,
-                    },
-                },
+                perms: admin_perms,
+                priority: ADMIN_POWER,
+                negate: false,
             },
             ServerGroupEntry {
                 uuid: DEFAULT_GROUP_UUID.as_u128(),
                 name: Cow::Borrowed("default"),
-                perms: PermsSnapshot {
-                    server_group_assign: 0,
-                    server_group_unassign: 0,
-                    channel_see: 0,
-                    channel_join: 0,
-                    channel_modify: 0,
-                    channel_talk: 0,
-                    channel_assign_talk: 0,
-                    channel_delete: 0,
-                    can_send: false,
-                    channel_create: ChannelCreatePerms {
-                        power: 0,
-                        set_desc: false,
-                        set_password: false,
-                        resort_channel: false,
-                    },
-                },
+                perms: default_perms,
+                priority: 0,
+                negate: false,
             },
         ])
     })?;
@@ -193,24 +218,9 @@ fn main() -> anyhow::Result<()> {
         .map(|server_group| ServerGroup {
             uuid: Uuid::from_u128(server_group.uuid),
             name: server_group.name.to_string(),
-            priority: 0,
-            perms: PermsSnapshot {
-                server_group_assign: 0,
-                server_group_unassign: 0,
-                channel_see: 0,
-                channel_join: 0,
-                channel_modify: 0,
-                channel_talk: 0,
-                channel_assign_talk: 0,
-                channel_delete: 0,
-                can_send: false,
-                channel_create: ChannelCreatePerms {
-                    power: 0,
-                    set_desc: false,
-                    set_password: false,
-                    resort_channel: false,
-                },
-            },
+            priority: server_group.priority,
+            negate: server_group.negate,
+            perms: server_group.perms,
         })
         .collect::<Vec<_>>();
     let server_groups = {
@@ -222,7 +232,60 @@ fn main() -> anyhow::Result<()> {
 
         result
     };
-    let config = Config::load_or_create(data_dir.join("config.json"))?;
+    let channel_group_db = ChannelGroupDb::new(
+        data_dir
+            .clone()
+            .join(RELATIVE_CHANNEL_GROUP_DB_PATH)
+            .to_string_lossy()
+            .to_string(),
+    );
+    let channel_groups = channel_group_db.read_or_create(|| {
+        let channel_keys = [perms::CHANNEL_SEE, perms::CHANNEL_JOIN, perms::CHANNEL_MODIFY, perms::CHANNEL_TALK, perms::CHANNEL_ASSIGN_TALK, perms::CHANNEL_DELETE];
+        let mut admin_perms = Perms::default();
+        for key in channel_keys {
+            admin_perms.set_granted(key, ADMIN_POWER);
+        }
+        // operators can do everything channel admins can do except for deleting the channel
+        let mut operator_perms = admin_perms.clone();
+        operator_perms.set_granted(perms::CHANNEL_DELETE, 0);
+        Ok(vec![
+            ChannelGroupEntry {
+                uuid: CHANNEL_ADMIN_GROUP_UUID.as_u128(),
+                name: Cow::Borrowed("channel admin"),
+                perms: admin_perms,
+            },
+            ChannelGroupEntry {
+                uuid: CHANNEL_OPERATOR_GROUP_UUID.as_u128(),
+                name: Cow::Borrowed("operator"),
+                perms: operator_perms,
+            },
+            ChannelGroupEntry {
+                uuid: CHANNEL_GUEST_GROUP_UUID.as_u128(),
+                name: Cow::Borrowed("guest"),
+                perms: Perms::default(),
+            },
+        ])
+    })?;
+    let channel_groups = {
+        let mut result = HashMap::new();
+
+        for channel_group in channel_groups.into_iter() {
+            let uuid = Uuid::from_u128(channel_group.uuid);
+            result.insert(uuid, Arc::new(ChannelGroup {
+                uuid,
+                name: channel_group.name.to_string(),
+                perms: channel_group.perms,
+            }));
+        }
+
+        result
+    };
+    let config_path = data_dir.join("config.json");
+    let config = Config::load_or_create(config_path.clone())?;
+    let default_channel = Uuid::from_u128(config.default_channel_id);
+    if !channels.contains_key(&default_channel) {
+        return Err(anyhow::Error::from(ErrorUnknownDefaultChannel(default_channel)));
+    }
 
     let cli = CLIBuilder::new()
         .prompt(format!("{}{}", "RustSpeak".red().to_string(), ": ".color(LIGHT_GRAY).to_string()))
@@ -235,7 +298,25 @@ fn main() -> anyhow::Result<()> {
         .command(
             CommandBuilder::new("stop", CommandShutdown())
                 .desc("shuts down the server gracefully")
-                .aliases(&["shutdown", "end", "kill", "off"]),
+                .aliases(&["shutdown", "end", "kill", "off"])
+                .params(UsageBuilder::new().optional(CommandParam {
+                    name: "delay_secs",
+                    ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                }).optional(CommandParam {
+                    name: "message", // shown to the users when they get disconnected
+                    ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                })),
+        )
+        .command(
+            CommandBuilder::new("restart", CommandRestart())
+                .desc("shuts down the server gracefully and tells clients to reconnect, the process exits with the restart exit code")
+                .params(UsageBuilder::new().optional(CommandParam {
+                    name: "delay_secs",
+                    ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                }).optional(CommandParam {
+                    name: "message", // shown to the users when they get disconnected
+                    ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                })),
         )
         .command(
             CommandBuilder::new("user", CommandUser())
@@ -244,7 +325,16 @@ fn main() -> anyhow::Result<()> {
                     ty: CommandParamTy::String(CmdParamStrConstraints::None),
                 }).optional(CommandParam {
                     name: "action",
-                    ty: CommandParamTy::Enum(CmdParamEnumConstraints::IgnoreCase(vec![("delete", EnumVal::None), ("group", EnumVal::None), ("perms", EnumVal::None)])),
+                    ty: CommandParamTy::Enum(CmdParamEnumConstraints::IgnoreCase(vec![("delete", EnumVal::None), ("group", EnumVal::None), ("perms", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "perm",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).required(CommandParam {
+                        name: "granted", // a power, true/false for perms that are flags or reset to remove the override
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).optional(CommandParam {
+                        name: "needed",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    })))])),
                 })),
         )
         .command(
@@ -253,6 +343,112 @@ fn main() -> anyhow::Result<()> {
         .command(
             CommandBuilder::new("channels", CommandChannels()),
     )
+        .command(
+            CommandBuilder::new("channelgroups", CommandChannelGroups()),
+        )
+        .command(
+            CommandBuilder::new("channelgroup", CommandChannelGroup())
+                .desc("assigns a channel group to an online user in a channel")
+                .params(UsageBuilder::new().required(CommandParam {
+                    name: "channel",
+                    ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                }).required(CommandParam {
+                    name: "user",
+                    ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                }).required(CommandParam {
+                    name: "group", // the name of the channel group or "none"
+                    ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                })),
+        )
+        .command(
+            CommandBuilder::new("servergroups", CommandServerGroups()),
+        )
+        .command(
+            CommandBuilder::new("servergroup", CommandServerGroup())
+                .desc("creates, edits, deletes and assigns server groups")
+                .params(UsageBuilder::new().required(CommandParam {
+                    name: "name",
+                    ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                }).required(CommandParam {
+                    name: "action",
+                    ty: CommandParamTy::Enum(CmdParamEnumConstraints::IgnoreCase(vec![("create", EnumVal::None), ("delete", EnumVal::None),
+                                                                                      ("rename", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "new_name",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }))), ("perms", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "perm",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).required(CommandParam {
+                        name: "granted", // a power or true/false for perms that are flags
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).optional(CommandParam {
+                        name: "needed",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }))), ("priority", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "priority",
+                        ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                    }).optional(CommandParam {
+                        name: "negate",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }))), ("assign", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "user",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }))), ("unassign", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "user",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    })))])),
+                })),
+        )
+        .command(
+            CommandBuilder::new("token", CommandToken())
+                .desc("creates, lists and deletes privilege tokens which grant a group to whoever redeems them")
+                .params(UsageBuilder::new().required(CommandParam {
+                    name: "action",
+                    ty: CommandParamTy::Enum(CmdParamEnumConstraints::IgnoreCase(vec![("list", EnumVal::None),
+                                                                                      ("delete", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "token",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }))), ("servergroup", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "group",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).optional(CommandParam {
+                        name: "uses", // 0 allows any number of uses
+                        ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                    }).optional(CommandParam {
+                        name: "valid_secs",
+                        ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                    }))), ("channelgroup", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "channel",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).required(CommandParam {
+                        name: "group",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).optional(CommandParam {
+                        name: "uses", // 0 allows any number of uses
+                        ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                    }).optional(CommandParam {
+                        name: "valid_secs",
+                        ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                    })))])),
+                })),
+        )
+        .command(
+            CommandBuilder::new("config", CommandConfig())
+                .desc("prints, changes and reloads the server config without restarting the server")
+                .params(UsageBuilder::new().required(CommandParam {
+                    name: "action",
+                    ty: CommandParamTy::Enum(CmdParamEnumConstraints::IgnoreCase(vec![("get", EnumVal::Complex(UsageBuilder::new().optional(CommandParam {
+                        name: "key",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }))), ("set", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "key",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).required(CommandParam {
+                        name: "value",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }))), ("reload", EnumVal::None)])),
+                })),
+        )
         .command(
             CommandBuilder::new("channel", CommandChannel())
                 .params(UsageBuilder::new().required(CommandParam {
@@ -265,7 +461,19 @@ fn main() -> anyhow::Result<()> {
                         ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
                     }).optional(CommandParam { name: "sort_id", ty: CommandParamTy::UInt(CmdParamNumConstraints::None) })
                 .optional(CommandParam { name: "password", ty: CommandParamTy::String(CmdParamStrConstraints::None) }).optional(CommandParam { name: "description", ty: CommandParamTy::Unbound { minimum: NonZeroUsize::new(1).unwrap(), param: Box::new(CommandParamTy::String(CmdParamStrConstraints::None)) } }))),
-                                                  ("delete", EnumVal::None), ("edit", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                                                  ("createsub", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "parent",
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).required(CommandParam {
+                        name: "slots",
+                        ty: CommandParamTy::UInt(CmdParamNumConstraints::None),
+                    }).optional(CommandParam { name: "sort_id", ty: CommandParamTy::UInt(CmdParamNumConstraints::None) })
+                .optional(CommandParam { name: "password", ty: CommandParamTy::String(CmdParamStrConstraints::None) }).optional(CommandParam { name: "description", ty: CommandParamTy::Unbound { minimum: NonZeroUsize::new(1).unwrap(), param: Box::new(CommandParamTy::String(CmdParamStrConstraints::None)) } }))),
+                                                  ("move", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
+                        name: "parent", // the name of the new parent or "root"
+                        ty: CommandParamTy::String(CmdParamStrConstraints::None),
+                    }).optional(CommandParam { name: "sort_id", ty: CommandParamTy::UInt(CmdParamNumConstraints::None) }))),
+                                                  ("delete", EnumVal::None), ("default", EnumVal::None), ("edit", EnumVal::Complex(UsageBuilder::new().required(CommandParam {
                         name: "property",
                         ty: CommandParamTy::Enum(CmdParamEnumConstraints::IgnoreCase(vec![("name", EnumVal::Simple(CommandParamTy::String(CmdParamStrConstraints::None))), ("slots", EnumVal::Simple(CommandParamTy::Int(CmdParamNumConstraints::None)))])), // FIXME: expand this!
                     })))])),
@@ -277,7 +485,7 @@ fn main() -> anyhow::Result<()> {
     let cli = CmdLineInterface::new(cli.build());
 
     let main_server_ref = main_server.clone();
-    thread::spawn(move || {
+    let runtime = thread::spawn(move || {
         tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
@@ -286,19 +494,53 @@ fn main() -> anyhow::Result<()> {
                 let network_server = setup_network_server(&config).unwrap(); // FIXME: handle panics by gracefully shutting down using a panic hook!
                 let server = Arc::new(Server {
                     server_groups: tokio::sync::RwLock::new(server_groups),
+                    channel_groups: tokio::sync::RwLock::new(channel_groups),
                     channels: tokio::sync::RwLock::new(channels),
                     online_users: Default::default(),
                     network_server,
-                    config,
+                    config: ArcSwap::new(Arc::new(config)),
                     user_db,
                     channel_db,
                     server_group_db,
+                    channel_group_db,
+                    mailbox_db,
                     cli,
                     shutting_down: Default::default(),
-                    shut_down: Default::default(),
+                    exit_code: Default::default(),
+                    runtime: tokio::runtime::Handle::current(),
+                    config_path,
+                    config_update: tokio::sync::Mutex::new(()),
+                    info_queries: DashMap::new(),
+                    sealed_messages: DashMap::new(),
+                    message_quotas: DashMap::new(),
                 });
                 main_server_ref.try_init(server.clone()).expect("server already init, this can't happen!");
                 let tmp = server.clone();
+                tokio::spawn(async move {
+                    let server = tmp;
+                    let mut interval = tokio::time::interval(MAILBOX_PURGE_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        if let Err(err) = server.mailbox_db.purge_expired(&server.config.load().mailbox_limits()) {
+                            server.println(format!("An error occurred while purging expired messages: {}", err).as_str());
+                        }
+                        if let Err(err) = server.mailbox_db.purge_expired_sealed_keys() {
+                            server.println(format!("An error occurred while purging expired access keys: {}", err).as_str());
+                        }
+                    }
+                });
+                let tmp = server.clone();
+                tokio::spawn(async move {
+                    let server = tmp;
+                    let mut interval = tokio::time::interval(TEMP_CHANNEL_PURGE_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        if let Err(err) = server.purge_temporary_channels().await {
+                            server.println(format!("An error occurred while deleting empty temporary channels: {}", err).as_str());
+                        }
+                    }
+                });
+                let tmp = server.clone();
                 thread::spawn(move || {
                     let server = tmp.clone();
                     loop {
@@ -321,21 +563,22 @@ fn main() -> anyhow::Result<()> {
     };
 
     server.println(
-        format!("Server started up successfully, waiting for inbound connections on port {}...",
-        server.config.port).as_str()
+        format!("Server started up successfully, waiting for inbound connections on {}...",
+        server.network_server.endpoint.local_addr()?).as_str()
     );
 
-    while !server.shut_down.load(Ordering::Acquire) {
-        thread::sleep(Duration::from_millis(250));
+    // the runtime exits once the server was shut down
+    if runtime.join().is_err() {
+        process::exit(ERROR_EXIT_CODE);
     }
-
-    Ok(())
+    process::exit(server.exit_code.load(Ordering::Acquire));
 }
 
 fn setup_network_server(config: &Config) -> anyhow::Result<NetworkServer> {
     let (local_cert, private_key) = certificate::insecure_local::generate_self_signed_cert()?;
     NetworkServer::new(
         config.port,
+        config.bind_address,
         /*1000*/ u32::MAX,
         config.address_mode,
         certificate::create_config(local_cert, private_key)?,
@@ -373,32 +616,40 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
                     // println!("read packet!");
                     let server = server.clone();
                     if let ClientPacket::AuthRequest {
-                        protocol_version,
+                        protocol_versions,
+                        features,
                         pub_key,
                         name,
                         security_proofs,
                         signed_data,
+                        channel: requested_channel,
+                        channel_password,
                     } = packet
                     {
                         server.println(format!("{} tried to connect!", name).as_str());
-                        if protocol_version != PROTOCOL_VERSION {
-                            let failure = ServerPacket::AuthResponse(AuthResponse::Failure(
-                                AuthFailure::OutOfDate(PROTOCOL_VERSION),
-                            ));
-                            let encoded = failure.encode()?;
-                            new_conn.send_reliable(&encoded).await?;
-                            new_conn.close().await?;
-                            return Err(anyhow::Error::from(ErrorAuthProtoVer {
-                                ip: new_conn
-                                    .conn
-                                    .remote_address()
-                                    .ip(),
-                                uuid: UserUuid::from_u256(U256::from_le_bytes(sha256(
-                                    &signed_data,
-                                ))),
-                                recv_proto_ver: protocol_version,
-                            }));
-                        }
+                        // we speak the highest version both of us support, so clients don't have to upgrade together with the server
+                        let protocol_version = match VersionRange::SUPPORTED.negotiate(&protocol_versions) {
+                            Some(version) => version,
+                            None => {
+                                let failure = ServerPacket::AuthResponse(AuthResponse::Failure(
+                                    AuthFailure::OutOfDate(VersionRange::SUPPORTED),
+                                ));
+                                let encoded = failure.encode()?;
+                                new_conn.send_reliable(&encoded).await?;
+                                new_conn.close().await?;
+                                return Err(anyhow::Error::from(ErrorAuthProtoVer {
+                                    ip: new_conn
+                                        .conn
+                                        .remote_address()
+                                        .ip(),
+                                    uuid: UserUuid::from_u256(U256::from_le_bytes(sha256(
+                                        &signed_data,
+                                    ))),
+                                    recv_proto_vers: protocol_versions,
+                                }));
+                            }
+                        };
+                        let features = FEATURES.intersect(features);
                         let uuid = UserUuid::from_u256(U256::from_le_bytes(sha256(&pub_key)));
                         let last_security_proof = security_proofs.last().copied();
                         let security_proof_result = if let Some(level) =
@@ -422,9 +673,10 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
                                 uuid,
                             }));
                         };
-                        if server.config.req_security_level > security_proof_result {
+                        let req_security_level = server.config.load().req_security_level;
+                        if req_security_level > security_proof_result {
                             let failure = ServerPacket::AuthResponse(AuthResponse::Failure(
-                                AuthFailure::ReqSec(server.config.req_security_level),
+                                AuthFailure::ReqSec(req_security_level),
                             ));
                             let encoded = failure.encode()?;
                             new_conn.send_reliable(&encoded).await?;
@@ -438,6 +690,21 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
                                 provided_lvl: security_proof_result,
                             }));
                         }
+                        if server.shutting_down.load(Ordering::Acquire) {
+                            let failure = ServerPacket::AuthResponse(AuthResponse::Failure(
+                                AuthFailure::Invalid(Cow::from("The server is shutting down")),
+                            ));
+                            let encoded = failure.encode()?;
+                            new_conn.send_reliable(&encoded).await?;
+                            new_conn.close().await?;
+                            return Err(anyhow::Error::from(ErrorShuttingDown {
+                                ip: new_conn
+                                    .conn
+                                    .remote_address()
+                                    .ip(),
+                                uuid,
+                            }));
+                        }
                         if server.online_users.contains_key(&uuid) {
                             let failure = ServerPacket::AuthResponse(AuthResponse::Failure(
                                 AuthFailure::AlreadyOnline,
@@ -455,8 +722,9 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
                         }
                         // FIXME: compare auth_id with the auth_id in our data base if this isn't the first login!
                         // FIXME: insert data send the proper data back!
+                        // channels are always locked before the groups
+                        let channels = server.channels.read().await;
                         let server_groups = server.server_groups.read().await;
-                        let server_groups = server_groups.values();
                         let user = if let Some(user) = server.user_db.get(&uuid)? {
                             user
                         } else {
@@ -466,17 +734,18 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
                                 last_security_proof: last_security_proof.unwrap(),
                                 last_verified_security_level: security_proof_result,
                                 groups: vec![],
-                                perms: PermsSnapshot::default(),
+                                perms: Perms::default(),
+                                last_channel: None,
                             };
                             server.user_db.insert(user.clone())?;
                             user
                         };
 
-                        let channels = server.channels.read().await;
-                        let channel = channels.get(&DEFAULT_CHANNEL_UUID).unwrap(); // FIXME: add possibility to allow privileged users to login into other channels than the default channel!
+                        let default_channel = channels.get(&server.default_channel_id()).unwrap();
+                        let last_channel = user.last_channel;
 
                         let groups = user.groups.clone();
-                        let active_perms = calculate_active_perms(&server, &user.perms, &groups);
+                        let active_perms = calculate_active_perms(&server_groups, &user.perms, &groups);
 
                         let user = Arc::new(User {
                             uuid,
@@ -487,8 +756,32 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
                             connection: new_conn.clone(),
                             perms: SwapArc::new(Arc::new(user.perms)),
                             active_perms: SwapArc::new(Arc::new(active_perms)),
-                            channel: ArcSwap::new(channel.clone()),
+                            channel: ArcSwap::new(default_channel.clone()),
+                            last_poke: AtomicU64::new(0),
+                            visible_channels: Mutex::new(HashSet::new()),
+                            subscribed_channels: Mutex::new(HashSet::new()),
+                            protocol_version,
+                            features,
                         });
+                        // the channel the client asked for takes precedence over the channel the user was in when they left,
+                        // users who may not join either of them end up in the default channel
+                        let channel = {
+                            let channel_groups = server.channel_groups.read().await;
+                            let mut channel = default_channel.clone();
+                            for candidate in [requested_channel, last_channel].into_iter().flatten() {
+                                if let Some(candidate) = channels.get(&candidate) {
+                                    if channel_tree::may_join(&channels, &server_groups, &channel_groups, &candidate.uuid, &user) && server.check_channel_password(candidate, channel_password.as_deref())? {
+                                        channel = candidate.clone();
+                                        break;
+                                    }
+                                }
+                            }
+                            channel
+                        };
+                        user.channel.store(channel.clone());
+                        user.subscribed_channels.lock().unwrap().insert(channel.uuid);
+                        let visible_channels = visible_channels(&channels, &server_groups, server.channel_groups.read().await.deref(), &user, &server.default_channel_id());
+                        *user.visible_channels.lock().unwrap() = visible_channels.clone();
 
                         new_conn.user.try_init_silent(user.clone()).unwrap();
                         server.println(format!("{} ({:?}) successfully connected", name, uuid).as_str());
@@ -499,28 +792,41 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
                             server_groups: groups.clone(),
                         };
 
-                        // broadcast user join to other users
-                        let packet = ServerPacket::ClientConnected(profile.clone());
+                        // broadcast user join to the other users who can see the channel, the others mustn't learn about it
+                        let packet = ServerPacket::ClientConnected { profile: profile.clone(), channel: channel.uuid };
                         let encoded = packet.encode()?;
                         for user in server.online_users.iter() {
-                            user.connection.send_reliable(&encoded).await?;
+                            if user.visible_channels.lock().unwrap().contains(&channel.uuid) {
+                                user.connection.send_reliable(&encoded).await?;
+                            }
                         }
 
-                        server.online_users.insert(uuid, user);
+                        server.online_users.insert(uuid, user.clone());
                         channel.clients.write().await.push(uuid);
                         RwLock::write(&channel.proto_clients).unwrap().push(profile.clone());
                         // println!("channels: {}", channels.len());
-                        let channels = channels.values();
-                        let channels = channels.map(|val| val.deref().clone()).collect::<Vec<_>>();
+                        let channels = channels.values().filter(|channel| visible_channels.contains(&channel.uuid));
+                        let channels = channels.map(|val| channel_for(&user, val)).collect::<Vec<_>>();
 
                         let auth = ServerPacket::AuthResponse(AuthResponse::Success {
-                            default_channel_id: Uuid::from_u128(server.config.default_channel_id),
-                            server_groups: server_groups.cloned().collect::<Vec<_>>(), // FIXME: try getting rid of this clone!
+                            protocol_version,
+                            features,
+                            default_channel_id: server.default_channel_id(),
+                            server_groups: server_groups.values().cloned().collect::<Vec<_>>(), // FIXME: try getting rid of this clone!
+                            channel_groups: server.channel_groups.read().await.values().cloned().collect::<Vec<_>>(),
                             own_groups: groups,
                             channels,
                         });
                         let encoded = auth.encode()?;
                         new_conn.send_reliable(&encoded).await?;
+                        let active_perms = ServerPacket::ActivePerms(Perms::clone(&user.active_perms.load())).encode()?;
+                        new_conn.send_reliable(&active_perms).await?;
+                        // deliver all messages which were sent to the user while they were offline
+                        let messages = server.mailbox_db.get_all(&uuid, &server.config.load().mailbox_limits())?;
+                        if !messages.is_empty() {
+                            let delivery = ServerPacket::MailboxDelivery { messages }.encode()?;
+                            new_conn.send_reliable(&delivery).await?;
+                        }
                         let keep_alive_stream = new_conn.conn.accept_bi().await?;
                         let _ = new_conn.keep_alive_stream.try_init((tokio::sync::Mutex::new(keep_alive_stream.0), tokio::sync::Mutex::new(keep_alive_stream.1)));
                         new_conn.start_read().await;
@@ -544,16 +850,25 @@ async fn start_server<F: Fn(anyhow::Error)>(server: Arc<Server>, error_handler:
 
 pub struct Server {
     pub server_groups: tokio::sync::RwLock<HashMap<Uuid, Arc<ServerGroup>>>,
+    pub channel_groups: tokio::sync::RwLock<HashMap<Uuid, Arc<ChannelGroup>>>,
     pub channels: tokio::sync::RwLock<HashMap<Uuid, Arc<Channel>>>,
     pub online_users: DashMap<UserUuid, Arc<User>>, // FIXME: add a timed cache for offline users
     pub network_server: NetworkServer,
-    pub config: Config, // FIXME: make this mutable somehow
+    pub config: ArcSwap<Config>, // swapped when the config gets changed or reloaded at runtime
     pub user_db: UserDb,
     pub channel_db: ChannelDb,
     pub server_group_db: ServerGroupDb,
+    pub channel_group_db: ChannelGroupDb,
+    pub mailbox_db: MailboxDb,
     pub cli: CmdLineInterface<Arc<Server>>,
     pub shutting_down: AtomicBool,
-    pub shut_down: AtomicBool,
+    pub exit_code: AtomicI32, // the exit code of the process once the server was shut down
+    pub runtime: tokio::runtime::Handle, // allows the cli thread to run async tasks
+    pub config_path: PathBuf,
+    pub config_update: tokio::sync::Mutex<()>, // held while the config gets changed so concurrent changes don't get lost
+    pub info_queries: DashMap<IpAddr, Instant>, // the time of the last info query of each address, used for rate limiting
+    pub sealed_messages: DashMap<IpAddr, Instant>, // the time of the last sealed message of each address, used for rate limiting
+    pub message_quotas: DashMap<(MessageSender, UserUuid), (Instant, u64)>, // the start of the current period and the messages each sender stored for each receiver in it
 }
 
 // A pseudo debug impl
@@ -566,6 +881,11 @@ impl Debug for Server {
 
 impl Server {
 
+    /// the channel users log into and get moved into when their channel gets deleted
+    pub fn default_channel_id(&self) -> Uuid {
+        Uuid::from_u128(self.config.load().default_channel_id)
+    }
+
     pub fn println(&self, msg: &str) {
         self.cli.println(msg);
     }
@@ -575,20 +895,22 @@ impl Server {
             .read_or_create(|| {
                 Ok(vec![ChannelDbEntry {
                     id: DEFAULT_CHANNEL_UUID.as_u128(),
+                    parent: None,
                     sort_id: 0,
                     name: Cow::Borrowed("Lobby"),
                     desc: Default::default(),
                     password: None,
                     user_groups: vec![],
-                    perms: ChannelPerms {
+                    perms: Some(ChannelPerms {
                         see: 0,
                         join: 0,
                         modify: 100,
                         talk: 0,
                         assign_talk: 100,
                         delete: 100,
-                    },
+                    }),
                     slots: 100,
+                    lifetime: ChannelLifetime::Permanent,
                 }])
             })?;
         Ok(channels)
@@ -603,119 +925,26 @@ pub struct User {
     pub last_verified_security_level: u8,
     pub groups: RwLock<Vec<Uuid>>,
     pub connection: Arc<ClientConnection>,
-    pub perms: SwapArc<PermsSnapshot>,
-    pub active_perms: SwapArc<ActivePerms>,
+    pub perms: SwapArc<Perms>, // overrides the perms granted by the user's groups
+    pub active_perms: SwapArc<Perms>,
     pub channel: ArcSwap<Channel>,
+    pub last_poke: AtomicU64, // the time of the last poke this user sent in millis, used for rate limiting
+    pub visible_channels: Mutex<HashSet<Uuid>>, // the channels this user was informed about
+    pub subscribed_channels: Mutex<HashSet<Uuid>>, // the channels whose clients this user is informed about
+    pub protocol_version: u64, // the protocol version that was negotiated with the user's client
+    pub features: Features, // the optional features both the user's client and we support
 }
 
-pub struct ActivePerms {
-    pub server_group_assign: u64,
-    pub server_group_unassign: u64,
-    pub channel_see: u64,
-    pub channel_join: u64,
-    pub channel_modify: u64,
-    pub channel_talk: u64,
-    pub channel_assign_talk: u64,
-    pub channel_delete: u64,
-    pub send: u64,
-    pub channel_create: ActiveChannelCreatePerms,
-}
-
-pub struct ActiveChannelCreatePerms {
-    pub power: u64,
-    pub set_desc: u64,
-    pub set_password: u64,
-    pub resort_channel: u64,
-    // FIXME: add other features that channels have
-}
-
-fn calculate_active_perms(server: &Arc<Server>, perms: &PermsSnapshot, groups: &Vec<Uuid>) -> ActivePerms {
-    let mut active = ActivePerms {
-        server_group_assign: perms.server_group_assign,
-        server_group_unassign: perms.server_group_unassign,
-        channel_see: perms.channel_see,
-        channel_join: perms.channel_join,
-        channel_modify: perms.channel_modify,
-        channel_talk: perms.channel_talk,
-        channel_assign_talk: perms.channel_assign_talk,
-        channel_delete: perms.channel_delete,
-        send: if perms.can_send {
-            perms.channel_join
-        } else {
-            0
-        },
-        channel_create: ActiveChannelCreatePerms {
-            power: perms.channel_create.power,
-            set_desc: if perms.channel_create.set_desc {
-                perms.channel_create.power
-            } else {
-                0
-            },
-            set_password: if perms.channel_create.set_password {
-                perms.channel_create.power
-            } else {
-                0
-            },
-            resort_channel: if perms.channel_create.resort_channel {
-                perms.channel_create.power
-            } else {
-                0
-            },
-        },
-    };
-
-    for group in groups.iter() {
-        let groups = server.server_groups.read().block_on();
-        let group = groups.get(group).unwrap();
-        if group.perms.server_group_assign > active.server_group_assign {
-            active.server_group_assign = group.perms.server_group_assign;
-        }
-        if group.perms.server_group_unassign > active.server_group_unassign {
-            active.server_group_unassign = group.perms.server_group_unassign;
-        }
-        if group.perms.channel_join > active.channel_join {
-            active.channel_join = group.perms.channel_join;
-        }
-        if group.perms.can_send && group.perms.channel_join > active.send {
-            active.send = group.perms.channel_join;
-        }
-        if group.perms.channel_talk > active.channel_talk {
-            active.channel_talk = group.perms.channel_talk;
-        }
-        if group.perms.channel_see > active.channel_see {
-            active.channel_see = group.perms.channel_see;
-        }
-        if group.perms.channel_delete > active.channel_delete {
-            active.channel_delete = group.perms.channel_delete;
-        }
-        if group.perms.channel_assign_talk > active.channel_assign_talk {
-            active.channel_assign_talk = group.perms.channel_assign_talk;
-        }
-        if group.perms.channel_modify > active.channel_modify {
-            active.channel_modify = group.perms.channel_modify;
-        }
-        if group.perms.channel_create.power > active.channel_create.power {
-            active.channel_create.power = group.perms.channel_create.power;
-        }
-        if group.perms.channel_create.resort_channel && group.perms.channel_create.power > active.channel_create.resort_channel {
-            active.channel_create.resort_channel = group.perms.channel_create.power;
-        }
-        if group.perms.channel_create.set_password && group.perms.channel_create.power > active.channel_create.set_password {
-            active.channel_create.set_password = group.perms.channel_create.power;
-        }
-        if group.perms.channel_create.set_desc && group.perms.channel_create.power > active.channel_create.set_desc {
-            active.channel_create.set_desc = group.perms.channel_create.power;
-        }
-        // FIXME: extend this once there are more perms!
-    }
-
-    active
+/// resolves the perms a user has on the whole server, their server groups get applied in order of their priority and
+/// their own perms override all of them, channel groups aren't included as they only apply within their channel
+fn calculate_active_perms(server_groups: &HashMap<Uuid, Arc<ServerGroup>>, overrides: &Perms, groups: &Vec<Uuid>) -> Perms {
+    perms::resolve(server_groups, groups, None, overrides)
 }
 
 struct ErrorAuthProtoVer {
     ip: IpAddr,
     uuid: UserUuid,
-    recv_proto_ver: u64,
+    recv_proto_vers: VersionRange,
 }
 
 impl Debug for ErrorAuthProtoVer {
@@ -724,8 +953,10 @@ impl Debug for ErrorAuthProtoVer {
         f.write_str(self.ip.to_string().as_str())?;
         f.write_str(" with uuid ")?;
         f.write_str(&*format!("{:?}", self.uuid))?;
-        f.write_str(" tried to login with an incompatible protocol version ")?;
-        f.write_str(self.recv_proto_ver.to_string().as_str())
+        f.write_str(" tried to login with the protocol versions ")?;
+        f.write_str(self.recv_proto_vers.to_string().as_str())?;
+        f.write_str(" but we only support ")?;
+        f.write_str(VersionRange::SUPPORTED.to_string().as_str())
     }
 }
 
@@ -735,8 +966,10 @@ impl Display for ErrorAuthProtoVer {
         f.write_str(self.ip.to_string().as_str())?;
         f.write_str(" with uuid ")?;
         f.write_str(&*format!("{:?}", self.uuid))?;
-        f.write_str(" tried to login with an incompatible protocol version ")?;
-        f.write_str(self.recv_proto_ver.to_string().as_str())
+        f.write_str(" tried to login with the protocol versions ")?;
+        f.write_str(self.recv_proto_vers.to_string().as_str())?;
+        f.write_str(" but we only support ")?;
+        f.write_str(VersionRange::SUPPORTED.to_string().as_str())
     }
 }
 
@@ -826,6 +1059,47 @@ impl Display for ErrorAlreadyOnline {
 
 impl Error for ErrorAlreadyOnline {}
 
+struct ErrorShuttingDown {
+    ip: IpAddr,
+    uuid: UserUuid,
+}
+
+impl Debug for ErrorShuttingDown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("client from ")?;
+        f.write_str(self.ip.to_string().as_str())?;
+        f.write_str(" with uuid ")?;
+        f.write_str(&*format!("{:?}", self.uuid))?;
+        f.write_str(" tried to login while the server was shutting down")
+    }
+}
+
+impl Display for ErrorShuttingDown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorShuttingDown {}
+
+struct ErrorUnknownDefaultChannel(Uuid);
+
+impl Debug for ErrorUnknownDefaultChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the configured default channel ")?;
+        f.write_str(self.0.to_string().as_str())?;
+        f.write_str(" doesn't exist, change default_channel_id in the config to the uuid of an existing channel")
+    }
+}
+
+impl Display for ErrorUnknownDefaultChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownDefaultChannel {}
+
 struct ErrorInvalidPacketSize(u64);
 
 impl Debug for ErrorInvalidPacketSize {
@@ -914,17 +1188,48 @@ struct CommandShutdown();
 impl CommandImpl for CommandShutdown {
     type CTX = Arc<Server>;
 
-    fn execute(&self, server: &Arc<Server>, _input: &[&str]) -> anyhow::Result<()> {
+    fn execute(&self, server: &Arc<Server>, input: &[&str]) -> anyhow::Result<()> {
+        schedule_shutdown(server, input, false)
+    }
+}
+
+struct CommandRestart();
+
+impl CommandImpl for CommandRestart {
+    type CTX = Arc<Server>;
+
+    fn execute(&self, server: &Arc<Server>, input: &[&str]) -> anyhow::Result<()> {
+        schedule_shutdown(server, input, true)
+    }
+}
+
+fn schedule_shutdown(server: &Arc<Server>, input: &[&str], restart: bool) -> anyhow::Result<()> {
+    let delay = match input.first() {
+        Some(secs) => Duration::from_secs(secs.parse::<u64>()?),
+        None => Duration::ZERO,
+    };
+    let message = if input.len() > 1 {
+        Some(input[1..].join(" "))
+    } else {
+        None
+    };
+    if server.shutting_down.load(Ordering::Acquire) {
+        return Err(anyhow::Error::from(ErrorShutdownScheduled));
+    }
+    if delay.is_zero() {
         server.println("Shutting down...");
-        server.shutting_down.store(true, Ordering::Release);
-        for user in server.online_users.iter() {
-            user.value().connection.close();
-        }
-        server.online_users.clear();
-        server.println("Shutdown successfully!");
-        server.shut_down.store(true, Ordering::Release);
-        Ok(())
+    } else {
+        server.println(format!("Shutting down in {} seconds...", delay.as_secs()).as_str());
     }
+    let server = server.clone();
+    // the shutdown has to run on the runtime as it relies on tokio's timers
+    server.runtime.clone().spawn(async move {
+        match server.shutdown(delay, message, restart).await {
+            Ok(_) => server.println("Shutdown successfully!"),
+            Err(err) => server.println(format!("An error occurred while shutting down: {}", err).as_str()),
+        }
+    });
+    Ok(())
 }
 
 struct CommandUser();
@@ -933,10 +1238,57 @@ impl CommandImpl for CommandUser {
     type CTX = Arc<Server>;
 
     fn execute(&self, server: &Arc<Server>, input: &[&str]) -> anyhow::Result<()> {
-        todo!()
+        let user = find_user(server, input[0])?;
+        match input.get(1).map(|action| action.to_lowercase()).as_deref() {
+            None => {
+                let db_user = server.user_db.get(&user)?.ok_or(anyhow::Error::from(UserInexistentError(input[0].to_string())))?;
+                server.println(format!("{} ({:?})", db_user.name, db_user.uuid).as_str());
+                server.println("Perm   Granted   Needed   Overridden");
+                // the db is kept up to date for online users, so their perms are resolved the same way
+                let active_perms = calculate_active_perms(&server.server_groups.read().block_on(), &db_user.perms, &db_user.groups);
+                for key in perms::PERM_KEYS {
+                    let value = active_perms.get(key);
+                    server.println(format!("{} | {} | {} | {}", key, value.granted, value.needed, db_user.perms.contains(key)).as_str());
+                }
+            }
+            Some("perms") => {
+                if input[3].eq_ignore_ascii_case("reset") {
+                    server.set_perm_override(&user, input[2], None).block_on()?;
+                    server.println(format!("Removed the override of {} for {}", input[2], input[0]).as_str());
+                    return Ok(());
+                }
+                let db_user = server.user_db.get(&user)?.ok_or(anyhow::Error::from(UserInexistentError(input[0].to_string())))?;
+                let granted = server_groups::parse_power(input[3])?;
+                let needed = match input.get(4) {
+                    Some(needed) => server_groups::parse_power(needed)?,
+                    None => db_user.perms.needed(&input[2].to_lowercase()),
+                };
+                server.set_perm_override(&user, input[2], Some(PermValue { granted, needed })).block_on()?;
+                server.println(format!("Overrode {} for {} with granted {} needed {}", input[2], input[0], granted, needed).as_str());
+            }
+            Some(action) => {
+                // deleting users and managing their groups from here isn't implemented yet
+                return Err(anyhow::Error::from(UnsupportedUserActionError(action.to_string())));
+            }
+        }
+        Ok(())
     }
 }
 
+/// finds an online user by their name or any known user by their uuid in hex
+fn find_user(server: &Arc<Server>, name: &str) -> anyhow::Result<UserUuid> {
+    if let Some(user) = server.online_users.iter().find(|user| user.name.load().as_str().eq_ignore_ascii_case(name)) {
+        return Ok(user.uuid);
+    }
+    if let Ok(raw) = U256::from_str_radix(name, 16) {
+        let uuid = UserUuid::from_u256(raw);
+        if server.user_db.get(&uuid)?.is_some() {
+            return Ok(uuid);
+        }
+    }
+    Err(anyhow::Error::from(UserInexistentError(name.to_string())))
+}
+
 struct CommandOnlineUsers();
 
 impl CommandImpl for CommandOnlineUsers {
@@ -979,7 +1331,10 @@ impl CommandImpl for CommandChannel {
                         Cow::Owned(val.to_string())
                     }
                 }).as_str());
+                server.println(format!("  Parent: {}", channel.1.parent.load().as_ref().and_then(|parent| channels.get(parent)).map(|parent| parent.name.load().to_string()).unwrap_or_else(|| String::from("none"))).as_str());
                 server.println(format!("  SortId: {}", channel.1.sort_id.load(Ordering::Acquire)).as_str());
+                server.println(format!("  InheritsPerms: {}", channel.1.perms.load().is_none()).as_str());
+                server.println(format!("  Lifetime: {:?}", channel.1.lifetime).as_str());
                 server.println(format!("  Description: \"{}\"", channel.1.desc.load().as_str()).as_str());
                 server.println(format!("  HasPassword: {}", channel.1.password.load(Ordering::Acquire)).as_str()); // FIXME: print pw (only if requested)
                 server.println(format!("  Clients: {}", channel.1.clients.blocking_read().len()).as_str()); // FIXME: print names!
@@ -988,88 +1343,94 @@ impl CommandImpl for CommandChannel {
         }
         match input[1] {
             "create" => {
-                let mut id = rand::random::<u128>();
-                let mut db = server.read_channel_db()?;
-                while db.iter().any(|channel| channel.id == id) {
-                    id = rand::random::<u128>();
-                }
-                let desc = if input.len() >= 6 {
-                    input[5..].join(" ").to_string()
+                create_channel(server, input[0], None, &input[2..])?;
+            },
+            "createsub" => {
+                let parent = find_channel(server, input[2])?;
+                create_channel(server, input[0], Some(parent), &input[3..])?;
+            },
+            "move" => {
+                let channel = find_channel(server, input[0])?;
+                let parent = if input[2].eq_ignore_ascii_case("root") {
+                    None
                 } else {
-                    String::new()
-                };
-                let pw = input.get(4).map(|raw| Some(Cow::Owned(raw.to_string()))).unwrap_or(None);
-                let has_pw = pw.is_some();
-                let slots = isize::from_str(input[2]).unwrap() as i16;
-                if slots < -1 {
-                    panic!("A slot count below -1 is illegal");
-                }
-                let sort_id = input.get(3).map(|raw| usize::from_str(raw).unwrap() as u16).unwrap_or_else(|| {
-                    let mut last_id = 0;
-                    for channel in db.iter() {
-                        if channel.sort_id > last_id {
-                            last_id = channel.sort_id;
-                        }
-                    }
-                    last_id + 1
-                });
-                let channel = ChannelDbEntry {
-                    id,
-                    sort_id,
-                    name: Cow::Owned(input[0].to_string()),
-                    desc: Cow::Owned(desc.clone()),
-                    password: pw,
-                    user_groups: vec![],
-                    perms: ChannelPerms::default(), // FIXME: make this configurable via cmd params!
-                    slots,
+                    Some(find_channel(server, input[2])?)
                 };
-                db.push(channel);
-                server.channel_db.write(&db).expect("An error occurred while writing the new database!");
-                server.channels.write().block_on().insert(Uuid::from_u128(id), Arc::new(Channel {
-                    uuid: Uuid::from_u128(id),
-                    password: AtomicBool::new(has_pw),
-                    name: Arc::new(SwapArc::new(Arc::new(input[0].to_string()))),
-                    desc: Arc::new(SwapArc::new(Arc::new(desc))),
-                    perms: Arc::new(SwapArc::new(Arc::new(ChannelPerms::default()))), // FIXME: make this configurable via cmd params!
-                    clients: Arc::new(Default::default()),
-                    proto_clients: Arc::new(Default::default()),
-                    slots: AtomicI16::new(slots),
-                    sort_id: AtomicU16::new(sort_id),
-                }));
-
-                server.println(format!("Created channel {}", input[0]).as_str());
+                let sort_id = input.get(3).map(|raw| usize::from_str(raw).unwrap() as u16);
+                server.move_channel(&channel, parent, sort_id).block_on()?;
+                server.println(format!("Moved channel {}", input[0]).as_str());
             },
             "edit" => {},
             "delete" => {
-                let channel = server.channels.read().block_on().values().find(|channel| channel.name.load().deref().as_str() == input[0]).map(|channel| channel.uuid.clone());
-                if channel.is_none() {
-                    return Err(anyhow::Error::from(ChannelInexistentError(input[0].to_string())));
-                }
-                if server.config.default_channel_id == channel.as_ref().unwrap().as_u128() {
+                let channel = find_channel(server, input[0])?;
+                if server.default_channel_id() == channel {
                     return Err(anyhow::Error::from(DefaultChannelNotDeletableError(input[0].to_string())));
                 }
-                // FIXME: move all clients from the to-be-deleted channel into the default channel!
-                server.channels.write().block_on().remove(&channel.unwrap());
-                let mut db = server.read_channel_db()?;
-                let entry = db.iter().enumerate().find(|entry| entry.1.id == channel.as_ref().unwrap().as_u128());
-                match entry {
-                    None => {
-                        return Err(anyhow::Error::from(ChannelInexistentError(input[0].to_string())));
-                    }
-                    Some(entry) => {
-                        let id = entry.0;
-                        db.remove(id);
-                    }
-                }
-                server.channel_db.write(&db).expect("An error occurred while writing the new database!");
+                // this deletes all subchannels as well and moves all clients into the default channel
+                server.delete_channel(&channel).block_on()?;
                 server.println(format!("Deleted channel {}", input[0]).as_str());
             },
+            "default" => {
+                let channel = find_channel(server, input[0])?;
+                server.set_default_channel(&channel).block_on()?;
+                server.println(format!("Made {} the default channel", input[0]).as_str());
+            },
             _ => unreachable!(),
         }
         Ok(())
     }
 }
 
+fn find_channel(server: &Arc<Server>, name: &str) -> anyhow::Result<Uuid> {
+    server.channels.read().block_on().values()
+        .find(|channel| channel.name.load().deref().as_str().eq_ignore_ascii_case(name))
+        .map(|channel| channel.uuid)
+        .ok_or(anyhow::Error::from(ChannelInexistentError(name.to_string())))
+}
+
+// args: slots [sort_id] [password] [description]
+fn create_channel(server: &Arc<Server>, name: &str, parent: Option<Uuid>, args: &[&str]) -> anyhow::Result<()> {
+    let mut id = rand::random::<u128>();
+    let db = server.read_channel_db()?;
+    while db.iter().any(|channel| channel.id == id) {
+        id = rand::random::<u128>();
+    }
+    let desc = if args.len() >= 4 {
+        args[3..].join(" ").to_string()
+    } else {
+        String::new()
+    };
+    let pw = args.get(2).map(|raw| Some(Cow::Owned(raw.to_string()))).unwrap_or(None);
+    let slots = isize::from_str(args[0]).unwrap() as i16;
+    if slots < -1 {
+        panic!("A slot count below -1 is illegal");
+    }
+    let sort_id = args.get(1).map(|raw| usize::from_str(raw).unwrap() as u16).unwrap_or_else(|| {
+        channel_tree::next_sort_id(&server.channels.read().block_on(), parent)
+    });
+    let channel = ChannelDbEntry {
+        id,
+        parent: parent.map(|parent| parent.as_u128()),
+        sort_id,
+        name: Cow::Owned(name.to_string()),
+        desc: Cow::Owned(desc),
+        password: pw,
+        user_groups: vec![],
+        // root channels get the default perms, subchannels inherit the perms of their parent
+        perms: if parent.is_some() {
+            None
+        } else {
+            Some(ChannelPerms::default())
+        }, // FIXME: make this configurable via cmd params!
+        slots,
+        lifetime: ChannelLifetime::Permanent,
+    };
+    server.create_channel(channel).block_on()?;
+
+    server.println(format!("Created channel {}", name).as_str());
+    Ok(())
+}
+
 struct ChannelInexistentError(String);
 
 impl Debug for ChannelInexistentError {
@@ -1117,9 +1478,9 @@ impl CommandImpl for CommandChannels {
             server.println(format!("There are {} channels:", channels.len()).as_str());
         }
         server.println("Name   UUID   Users/Slots");
-        for channel in channels.values() {
+        for (depth, channel) in channel_tree::tree_order(&channels) {
             let slots = channel.slots.load(Ordering::Acquire);
-            server.println(format!("{} | {:?} | {}/{}", channel.name.load(), channel.uuid, channel.clients.read().block_on().len(), if slots == -1 {
+            server.println(format!("{}{} | {:?} | {}/{}", "  ".repeat(depth), channel.name.load(), channel.uuid, channel.clients.read().block_on().len(), if slots == -1 {
                 String::from("unlimited")
             } else {
                 slots.to_string()
@@ -1128,3 +1489,303 @@ impl CommandImpl for CommandChannels {
         Ok(())
     }
 }
+
+struct CommandChannelGroups();
+
+impl CommandImpl for CommandChannelGroups {
+    type CTX = Arc<Server>;
+
+    fn execute(&self, server: &Arc<Server>, _input: &[&str]) -> anyhow::Result<()> {
+        let channel_groups = server.channel_groups.read().block_on();
+        if channel_groups.len() == 1 {
+            server.println("There is 1 channel group:");
+        } else {
+            server.println(format!("There are {} channel groups:", channel_groups.len()).as_str());
+        }
+        server.println("Name   UUID");
+        for channel_group in channel_groups.values() {
+            server.println(format!("{} | {:?}", channel_group.name, channel_group.uuid).as_str());
+        }
+        Ok(())
+    }
+}
+
+struct CommandChannelGroup();
+
+impl CommandImpl for CommandChannelGroup {
+    type CTX = Arc<Server>;
+
+    fn execute(&self, server: &Arc<Server>, input: &[&str]) -> anyhow::Result<()> {
+        let channel = find_channel(server, input[0])?;
+        let user = server.online_users.iter()
+            .find(|user| user.name.load().as_str().eq_ignore_ascii_case(input[1]))
+            .map(|user| user.uuid)
+            .ok_or(anyhow::Error::from(UserInexistentError(input[1].to_string())))?;
+        let group = if input[2].eq_ignore_ascii_case("none") {
+            None
+        } else {
+            Some(server.channel_groups.read().block_on().values()
+                .find(|group| group.name.eq_ignore_ascii_case(input[2]))
+                .map(|group| group.uuid)
+                .ok_or(anyhow::Error::from(ChannelGroupInexistentError(input[2].to_string())))?)
+        };
+        server.assign_channel_group(&channel, &user, group).block_on()?;
+        match group {
+            None => server.println(format!("Removed the channel group of {} in {}", input[1], input[0]).as_str()),
+            Some(_) => server.println(format!("Assigned {} the channel group {} in {}", input[1], input[2], input[0]).as_str()),
+        }
+        Ok(())
+    }
+}
+
+struct CommandServerGroups();
+
+impl CommandImpl for CommandServerGroups {
+    type CTX = Arc<Server>;
+
+    fn execute(&self, server: &Arc<Server>, _input: &[&str]) -> anyhow::Result<()> {
+        let server_groups = server.server_groups.read().block_on();
+        if server_groups.len() == 1 {
+            server.println("There is 1 server group:");
+        } else {
+            server.println(format!("There are {} server groups:", server_groups.len()).as_str());
+        }
+        server.println("Name   UUID   Priority   Negate");
+        let mut server_groups = server_groups.values().collect::<Vec<_>>();
+        server_groups.sort_by_key(|server_group| server_group.priority);
+        for server_group in server_groups {
+            server.println(format!("{} | {:?} | {} | {}", server_group.name, server_group.uuid, server_group.priority, server_group.negate).as_str());
+        }
+        Ok(())
+    }
+}
+
+struct CommandServerGroup();
+
+impl CommandImpl for CommandServerGroup {
+    type CTX = Arc<Server>;
+
+    fn execute(&self, server: &Arc<Server>, input: &[&str]) -> anyhow::Result<()> {
+        if input[1].eq_ignore_ascii_case("create") {
+            // new groups don't grant any perms until they get edited
+            server.create_server_group(input[0].to_string(), Perms::default()).block_on()?;
+            server.println(format!("Created the server group {}", input[0]).as_str());
+            return Ok(());
+        }
+        let group = server.server_groups.read().block_on().values()
+            .find(|group| group.name.eq_ignore_ascii_case(input[0]))
+            .cloned()
+            .ok_or(anyhow::Error::from(ServerGroupInexistentError(input[0].to_string())))?;
+        match input[1].to_lowercase().as_str() {
+            "delete" => {
+                server.delete_server_group(&group.uuid).block_on()?;
+                server.println(format!("Deleted the server group {}", input[0]).as_str());
+            }
+            "rename" => {
+                server.rename_server_group(&group.uuid, input[2].to_string()).block_on()?;
+                server.println(format!("Renamed the server group {} to {}", input[0], input[2]).as_str());
+            }
+            "perms" => {
+                let mut perms = group.perms.clone();
+                server_groups::set_perm(&mut perms, input[2], input[3], input.get(4).copied())?;
+                let value = perms.get(&input[2].to_lowercase());
+                server.set_server_group_perms(&group.uuid, perms).block_on()?;
+                server.println(format!("Set {} of the server group {} to granted {} needed {}", input[2], input[0], value.granted, value.needed).as_str());
+            }
+            "priority" => {
+                let priority = input[2].parse::<u64>()?;
+                let negate = match input.get(3) {
+                    Some(negate) => server_groups::parse_flag(negate)?,
+                    None => group.negate,
+                };
+                server.set_server_group_priority(&group.uuid, priority, negate).block_on()?;
+                server.println(format!("Set the priority of the server group {} to {} (negate: {})", input[0], priority, negate).as_str());
+            }
+            "assign" | "unassign" => {
+                let user = server.online_users.iter()
+                    .find(|user| user.name.load().as_str().eq_ignore_ascii_case(input[2]))
+                    .map(|user| user.uuid)
+                    .ok_or(anyhow::Error::from(UserInexistentError(input[2].to_string())))?;
+                if input[1].eq_ignore_ascii_case("assign") {
+                    server.assign_server_group(&user, &group.uuid).block_on()?;
+                    server.println(format!("Assigned {} the server group {}", input[2], input[0]).as_str());
+                } else {
+                    server.unassign_server_group(&user, &group.uuid).block_on()?;
+                    server.println(format!("Removed the server group {} from {}", input[0], input[2]).as_str());
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+struct CommandToken();
+
+impl CommandImpl for CommandToken {
+    type CTX = Arc<Server>;
+
+    fn execute(&self, server: &Arc<Server>, input: &[&str]) -> anyhow::Result<()> {
+        let (grant, rest) = match input[0].to_lowercase().as_str() {
+            "list" => {
+                let tokens = server.user_db.tokens()?;
+                if tokens.len() == 1 {
+                    server.println("There is 1 privilege token:");
+                } else {
+                    server.println(format!("There are {} privilege tokens:", tokens.len()).as_str());
+                }
+                server.println("Token   Grant   Uses   Expiry");
+                for token in tokens {
+                    let grant = match token.grant {
+                        TokenGrant::ServerGroup(group) => format!("server group {:?}", group),
+                        TokenGrant::ChannelGroup { channel, group } => format!("channel group {:?} in {:?}", group, channel),
+                    };
+                    let uses = token.uses.map_or(String::from("unlimited"), |uses| uses.to_string());
+                    let expiry = token.expiry.map_or(String::from("never"), |expiry| format!("{}", expiry.as_secs()));
+                    server.println(format!("{} | {} | {} | {}", token.token, grant, uses, expiry).as_str());
+                }
+                return Ok(());
+            }
+            "delete" => {
+                if server.user_db.remove_token(input[1])? {
+                    server.println(format!("Deleted the privilege token {}", input[1]).as_str());
+                } else {
+                    server.println(format!("There is no privilege token {}", input[1]).as_str());
+                }
+                return Ok(());
+            }
+            "servergroup" => {
+                let group = server.server_groups.read().block_on().values()
+                    .find(|group| group.name.eq_ignore_ascii_case(input[1]))
+                    .map(|group| group.uuid)
+                    .ok_or(anyhow::Error::from(ServerGroupInexistentError(input[1].to_string())))?;
+                (TokenGrant::ServerGroup(group), &input[2..])
+            }
+            "channelgroup" => {
+                let channel = find_channel(server, input[1])?;
+                let group = server.channel_groups.read().block_on().values()
+                    .find(|group| group.name.eq_ignore_ascii_case(input[2]))
+                    .map(|group| group.uuid)
+                    .ok_or(anyhow::Error::from(ChannelGroupInexistentError(input[2].to_string())))?;
+                (TokenGrant::ChannelGroup { channel, group }, &input[3..])
+            }
+            _ => unreachable!(),
+        };
+        let uses = match rest.first() {
+            Some(uses) => Some(uses.parse::<u64>()?),
+            None => Some(1),
+        };
+        let valid_for = match rest.get(1) {
+            Some(secs) => Some(Duration::from_secs(secs.parse::<u64>()?)),
+            None => None,
+        };
+        let token = server.create_privilege_token(grant, uses, valid_for).block_on()?;
+        server.println(format!("Created the privilege token {}", token).as_str());
+        Ok(())
+    }
+}
+
+struct CommandConfig();
+
+impl CommandImpl for CommandConfig {
+    type CTX = Arc<Server>;
+
+    fn execute(&self, server: &Arc<Server>, input: &[&str]) -> anyhow::Result<()> {
+        match input[0].to_lowercase().as_str() {
+            "get" => {
+                let config = server.config.load();
+                if let Some(key) = input.get(1) {
+                    server.println(format!("{}: {}", key, config.get(key)?).as_str());
+                } else {
+                    for key in CONFIG_KEYS {
+                        server.println(format!("{}: {}", key, config.get(key)?).as_str());
+                    }
+                }
+            }
+            "set" => {
+                // values like the welcome message may contain spaces
+                let value = input[2..].join(" ");
+                server.update_config(|config| config.set(input[1], value.as_str())).block_on()?;
+                server.println(format!("Set {} to {}", input[1], value).as_str());
+                if RESTART_KEYS.contains(&input[1]) {
+                    server.println(format!("The new value of {} only takes effect after a restart", input[1]).as_str());
+                }
+            }
+            "reload" => {
+                server.reload_config().block_on()?;
+                server.println("Reloaded the config");
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+struct UserInexistentError(String);
+
+impl Debug for UserInexistentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("There is no online user named ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for UserInexistentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for UserInexistentError {}
+
+struct UnsupportedUserActionError(String);
+
+impl Debug for UnsupportedUserActionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("The user action ")?;
+        f.write_str(self.0.as_str())?;
+        f.write_str(" isn't supported yet")
+    }
+}
+
+impl Display for UnsupportedUserActionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for UnsupportedUserActionError {}
+
+struct ChannelGroupInexistentError(String);
+
+impl Debug for ChannelGroupInexistentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("There is no channel group named ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ChannelGroupInexistentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ChannelGroupInexistentError {}
+
+struct ServerGroupInexistentError(String);
+
+impl Debug for ServerGroupInexistentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("There is no server group named ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ServerGroupInexistentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ServerGroupInexistentError {}
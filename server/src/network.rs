@@ -1,20 +1,29 @@
 use bytes::{Buf, Bytes, BytesMut};
-use quinn::{Connection, ConnectionError, Endpoint, IdleTimeout, RecvStream, SendStream, ServerConfig, TransportConfig, VarInt};
+use quinn::crypto::rustls::HandshakeData;
+use quinn::{Connection, ConnectionError, Endpoint, EndpointConfig, IdleTimeout, RecvStream, SendStream, ServerConfig, TokioRuntime, TransportConfig, VarInt};
 use serde_derive::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Write};
 use std::future::Future;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::borrow::Cow;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use arc_swap::ArcSwap;
 use pollster::FutureExt;
+use socket2::{Domain, Protocol, Socket, Type};
 use swap_arc::SwapArc;
 use tokio::sync::Mutex;
 use uuid::Uuid;
-use crate::{ClientPacket, DEFAULT_CHANNEL_UUID, RWBytes, Server, User, UserUuid};
+use crate::{CHANNEL_ADMIN_GROUP_UUID, ClientPacket, RWBytes, Server, User, UserUuid};
+use crate::server_groups::{ErrorEmptyServerGroupName, ErrorProtectedServerGroup, ErrorServerGroupNameTaken, ErrorServerGroupNoPermission, ErrorUnknownServerGroup, ErrorUnknownUser};
+use crate::channel_db::{ChannelDbEntry, U256Container};
+use crate::channel_tree::{self, ErrorChannelNameTaken, ErrorChannelOutlivesParent, ErrorUnknownChannel};
+use crate::perms::{self, Perms};
 use crate::conc_once_cell::ConcurrentOnceCell;
-use crate::packet::{Channel, ChannelSubClientUpdate, ChannelSubUpdate, ChannelUpdate, RemoteProfile, ServerPacket, SwitchChannelResponse};
+use crate::certificate::ALPN_ANONYMOUS;
+use crate::mailbox_db::{MailboxError, MessageSender};
+use crate::packet::{AssignChannelGroupResponse, Channel, ChannelGroup, ChannelLifetime, ChannelPerms, ClientUpdateServerGroups, CreateChannelResponse, DirectMessageContent, Encrypted, PokeResponse, RedeemTokenResponse, RegisterSealedKeysResponse, RemoteProfile, ServerGroup, ServerGroupEdit, ServerGroupResponse, ServerPacket, StoreMessageResponse, SwitchChannelResponse};
 use crate::utils::current_time_millis;
 
 // FIXME: look at: https://gitlab.com/veloren/veloren/-/issues/749 and https://gitlab.com/veloren/veloren/-/issues/1728
@@ -28,6 +37,7 @@ pub struct NetworkServer {
 impl NetworkServer {
     pub fn new(
         port: u16,
+        bind_address: Option<IpAddr>, // none binds to the wildcard address of `address_mode`
         idle_timeout_millis: u32,
         address_mode: AddressMode,
         mut config: ServerConfig,
@@ -37,7 +47,8 @@ impl NetworkServer {
             idle_timeout_millis,
         ))));
         config.transport = Arc::new(transport_cfg);
-        let endpoint = Endpoint::server(config, address_mode.local(port))?;
+        let addr = SocketAddr::new(bind_address.unwrap_or(address_mode.unspecified()), port);
+        let endpoint = Endpoint::new(EndpointConfig::default(), Some(config), bind_socket(addr, address_mode)?, Arc::new(TokioRuntime))?;
 
         Ok(Self {
             endpoint,
@@ -64,6 +75,10 @@ impl NetworkServer {
                     continue 'server;
                 }
             };
+            if is_anonymous(&connection) {
+                tokio::spawn(server.clone().handle_anonymous(connection));
+                continue 'server;
+            }
             let initial_stream = {
                 match connection.accept_bi().await {
                     Ok(stream) => stream,
@@ -88,6 +103,12 @@ impl NetworkServer {
     }
 }
 
+fn is_anonymous(connection: &Connection) -> bool {
+    connection.handshake_data()
+        .and_then(|data| data.downcast::<HandshakeData>().ok())
+        .map_or(false, |data| data.protocol.as_deref() == Some(ALPN_ANONYMOUS))
+}
+
 pub struct ClientConnection {
     pub user: ConcurrentOnceCell<Arc<User>>,
     pub conn: Connection,
@@ -317,10 +338,18 @@ impl ClientConnection {
             let mut clients = RwLock::write(&channel.proto_clients).unwrap();
             let idx = clients.iter().enumerate().find(|client| &client.1.uuid == &user.uuid).unwrap().0; // FIXME: should we make this a hashmap?
             let profile = clients.remove(idx);
+            // remember the channel so the user rejoins it on their next login
+            if let Some(mut db_user) = self.server.user_db.get(&user.uuid).unwrap() { // FIXME: handle errors properly!
+                db_user.last_channel = Some(channel.uuid);
+                self.server.user_db.insert(db_user).unwrap(); // FIXME: handle errors properly!
+            }
             if send_packet {
+                // only the users who can see the channel were told about the user in the first place
                 let disconnect_packet = ServerPacket::ClientDisconnected(profile).encode().unwrap();
                 for client in self.server.online_users.iter() {
-                    client.value().connection.send_reliable(&disconnect_packet).block_on().unwrap(); // FIXME: handle errors properly!
+                    if client.visible_channels.lock().unwrap().contains(&channel.uuid) {
+                        client.value().connection.send_reliable(&disconnect_packet).block_on().unwrap(); // FIXME: handle errors properly!
+                    }
                 }
             }
         }
@@ -331,17 +360,28 @@ impl ClientConnection {
 pub enum AddressMode {
     V4,
     V6,
+    DualStack, // an ipv6 socket that accepts ipv4 connections as well
 }
 
 impl AddressMode {
-    fn local(self, port: u16) -> SocketAddr {
+    fn unspecified(self) -> IpAddr {
         match self {
-            AddressMode::V4 => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
-            AddressMode::V6 => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), port),
+            AddressMode::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            AddressMode::V6 | AddressMode::DualStack => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
         }
     }
 }
 
+fn bind_socket(addr: SocketAddr, address_mode: AddressMode) -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        // set this explicitly as the os default differs between platforms
+        socket.set_only_v6(!matches!(address_mode, AddressMode::DualStack))?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
 pub struct KeepAlive {
     pub id: u64,
     pub send_time: Duration,
@@ -356,15 +396,21 @@ pub async fn handle_packet(packet: ClientPacket, server: &Arc<Server>, client: &
         ClientPacket::KeepAlive { .. } => {
             // FIXME: store the keep alive value somewhere in the client
         }
-        ClientPacket::UpdateClientServerGroups { .. } => {}
+        ClientPacket::UpdateClientServerGroups { client: target, update } => {
+            let response = update_server_groups(server, client.user.get().unwrap(), target, update).await;
+            let response = ServerPacket::ServerGroupResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
         ClientPacket::ChallengeResponse { .. } => {
             todo!()
         }
         ClientPacket::SwitchChannel { channel } => {
             let new_channel_id = channel;
-            if let Some(new_channel) = server.channels.read().await.get(&channel) {
-                let user = client.user.get().unwrap();
-                let client_id = user.uuid.clone();
+            let channels = server.channels.read().await;
+            let user = client.user.get().unwrap();
+            // channels the user can't see are treated as if they didn't exist so their existence isn't revealed
+            let visible = user.visible_channels.lock().unwrap().contains(&new_channel_id);
+            if let Some(new_channel) = channels.get(&channel).filter(|_| visible) {
                 let channel = user.channel.load().clone();
                 // check if it's the same channel
                 if channel.uuid == new_channel_id {
@@ -372,33 +418,331 @@ pub async fn handle_packet(packet: ClientPacket, server: &Arc<Server>, client: &
                     client.send_reliable(&response).await.unwrap();
                     return;
                 }
-                // check join perms
-                if channel.perms.load().join > client.user.get().unwrap().perms.load().channel_join {
+                // check join perms, channels without perms of their own inherit them from their parent
+                // and the user's channel group may grant additional power
+                if !channel_tree::may_join(&channels, &server.server_groups.read().await, &server.channel_groups.read().await, &new_channel_id, user) {
                     let response = ServerPacket::SwitchChannelResponse(SwitchChannelResponse::NoPermissions).encode().unwrap();
                     client.send_reliable(&response).await.unwrap();
                     return;
                 }
-                let idx = channel.clients.read().await.iter().enumerate().find(|user| user.1 == &client_id).unwrap().0;
-                channel.clients.write().await.remove(idx);
-                let idx = channel.proto_clients.read().unwrap().iter().enumerate().find(|user| &user.1.uuid == &client_id).unwrap().0;
-                let profile = RwLock::write(&channel.proto_clients).unwrap().remove(idx);
-                new_channel.clients.write().await.push(client_id);
-                RwLock::write(&new_channel.proto_clients).unwrap().push(profile);
-                user.channel.store(new_channel.clone());
+                let new_channel = new_channel.clone();
+                drop(channels);
                 // inform the sender about its success
                 let response = ServerPacket::SwitchChannelResponse(SwitchChannelResponse::Success).encode().unwrap();
                 client.send_reliable(&response).await.unwrap();
                 // inform all other clients about the update
-                let remove_packet = ServerPacket::ChannelUpdate(ChannelUpdate::SubUpdate { channel: channel.uuid, update: ChannelSubUpdate::Client(ChannelSubClientUpdate::Remove(client_id)) }).encode().unwrap();
-                let add_packet = ServerPacket::ChannelUpdate(ChannelUpdate::SubUpdate { channel: new_channel_id, update: ChannelSubUpdate::Client(ChannelSubClientUpdate::Add(client_id)) }).encode().unwrap();
-                for client in server.online_users.iter() {
-                    client.value().connection.send_reliable(&remove_packet).await.unwrap(); // FIXME: handle errors properly!
-                    client.value().connection.send_reliable(&add_packet).await.unwrap(); // FIXME: handle errors properly!
-                }
+                server.move_user(user, &new_channel).await;
             } else {
                 let response = ServerPacket::SwitchChannelResponse(SwitchChannelResponse::InvalidChannel).encode().unwrap();
                 client.send_reliable(&response).await.unwrap();
             }
         }
+        ClientPacket::StoreMessage { receiver, payload } => {
+            let user = client.user.get().unwrap();
+            let response = store_message(server, receiver, MessageSender::User(user.uuid), payload).await;
+            let response = ServerPacket::StoreMessageResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
+        ClientPacket::AckMessages { ids } => {
+            let user = client.user.get().unwrap();
+            server.mailbox_db.remove(&user.uuid, &ids).unwrap(); // FIXME: handle errors properly!
+        }
+        ClientPacket::RegisterSealedKeys { keys } => {
+            let user = client.user.get().unwrap();
+            let response = match server.mailbox_db.register_sealed_keys(&user.uuid, &keys, server.config.load().sealed_keys_max) {
+                Ok(Ok(())) => RegisterSealedKeysResponse::Success,
+                Ok(Err(_)) => RegisterSealedKeysResponse::TooManyKeys,
+                Err(err) => {
+                    server.println(format!("Couldn't register the sealed keys of {:?}: {}", user.uuid, err).as_str());
+                    return;
+                }
+            };
+            let response = ServerPacket::RegisterSealedKeysResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
+        ClientPacket::StoreSealedMessage { .. } => {
+            // sealed messages are only accepted on anonymous connections, on this one we know who sent them
+            let response = ServerPacket::StoreMessageResponse(StoreMessageResponse::NotAnonymous).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
+        ClientPacket::Poke { receiver, text } => {
+            let user = client.user.get().unwrap();
+            let poke_power = user.active_perms.load().granted(perms::POKE);
+            let response = if poke_power == 0 {
+                PokeResponse::NoPermission
+            } else if text.as_ref().map_or(false, |text| text.chars().count() > server.config.load().poke_max_len) {
+                PokeResponse::TextTooLong
+            } else {
+                let now = current_time_millis().as_millis() as u64;
+                let last_poke = user.last_poke.load(Ordering::Acquire);
+                if now.saturating_sub(last_poke) < server.config.load().poke_cooldown_millis
+                    || user.last_poke.compare_exchange(last_poke, now, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                    PokeResponse::RateLimited
+                } else if let Some(receiver) = server.online_users.get(&receiver) {
+                    // users can require a minimum power from everybody who wants to poke them
+                    if receiver.active_perms.load().needed(perms::POKE) > poke_power {
+                        PokeResponse::NoPermission
+                    } else {
+                        let poke = ServerPacket::Poke { sender: user.uuid, text }.encode().unwrap();
+                        receiver.connection.send_reliable(&poke).await.unwrap(); // FIXME: handle errors properly!
+                        PokeResponse::Success
+                    }
+                } else {
+                    PokeResponse::UnknownReceiver
+                }
+            };
+            let response = ServerPacket::PokeResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
+        ClientPacket::AssignChannelGroup { channel, user, group } => {
+            let sender = client.user.get().unwrap();
+            let response = {
+                let channels = server.channels.read().await;
+                let server_groups = server.server_groups.read().await;
+                let channel_groups = server.channel_groups.read().await;
+                if !channels.contains_key(&channel) {
+                    Some(AssignChannelGroupResponse::UnknownChannel)
+                } else if !server.online_users.contains_key(&user) {
+                    Some(AssignChannelGroupResponse::UnknownUser)
+                } else if group.as_ref().map_or(false, |group| !channel_groups.contains_key(group)) {
+                    Some(AssignChannelGroupResponse::UnknownGroup)
+                } else {
+                    let sender_perms = channel_tree::user_perms(&channels, &server_groups, &channel_groups, &channel, sender);
+                    // the group the user currently has in the channel gets replaced, so the sender has to be able to manage it as well
+                    let current = channels[&channel].user_groups.load().iter()
+                        .find(|assignment| assignment.user == user)
+                        .and_then(|assignment| channel_groups.get(&assignment.group).cloned());
+                    let manageable = [group.as_ref().and_then(|group| channel_groups.get(group)), current.as_ref()].into_iter()
+                        .flatten()
+                        .all(|group| can_manage_channel_group(&sender_perms, group));
+                    // assigning channel groups requires the permission to modify the channel
+                    if channel_tree::effective_perms(&channels, &channel).modify > sender_perms.granted(perms::CHANNEL_MODIFY) || !manageable {
+                        Some(AssignChannelGroupResponse::NoPermission)
+                    } else {
+                        None
+                    }
+                }
+            };
+            let response = match response {
+                Some(response) => response,
+                None => {
+                    server.assign_channel_group(&channel, &user, group).await.unwrap(); // FIXME: handle errors properly!
+                    AssignChannelGroupResponse::Success
+                }
+            };
+            let response = ServerPacket::AssignChannelGroupResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
+        ClientPacket::CreateChannel { parent, name, lifetime } => {
+            let user = client.user.get().unwrap();
+            let response = {
+                let channels = server.channels.read().await;
+                let server_groups = server.server_groups.read().await;
+                let channel_groups = server.channel_groups.read().await;
+                let allowed = user.active_perms.load().has(match lifetime {
+                    ChannelLifetime::Permanent => perms::CHANNEL_CREATE_PERMANENT,
+                    ChannelLifetime::SemiPermanent => perms::CHANNEL_CREATE_SEMI_PERMANENT,
+                    ChannelLifetime::Temporary => perms::CHANNEL_CREATE_TEMPORARY,
+                });
+                if name.trim().is_empty() || channel_tree::is_name_taken(&channels, parent, &name, None) {
+                    Some(CreateChannelResponse::InvalidName)
+                } else if parent.as_ref().map_or(false, |parent| !channels.contains_key(parent)) {
+                    Some(CreateChannelResponse::UnknownParent)
+                } else if !allowed {
+                    Some(CreateChannelResponse::NoPermission)
+                } else if parent.as_ref().map_or(false, |parent| {
+                    // creating subchannels requires the permission to modify the parent
+                    channel_tree::effective_perms(&channels, parent).modify > channel_tree::user_perms(&channels, &server_groups, &channel_groups, parent, user).granted(perms::CHANNEL_MODIFY)
+                }) {
+                    Some(CreateChannelResponse::NoPermission)
+                } else {
+                    None
+                }
+            };
+            if let Some(response) = response {
+                let response = ServerPacket::CreateChannelResponse(response).encode().unwrap();
+                client.send_reliable(&response).await.unwrap();
+                return;
+            }
+            let id = Uuid::new_v4().as_u128();
+            let entry = ChannelDbEntry {
+                id,
+                parent: parent.map(|parent| parent.as_u128()),
+                sort_id: channel_tree::next_sort_id(&server.channels.read().await, parent),
+                name: Cow::Owned(name),
+                desc: Default::default(),
+                password: None,
+                // the creator administers the new channel
+                user_groups: vec![(U256Container::new(user.uuid.into_u256()), CHANNEL_ADMIN_GROUP_UUID.as_u128())],
+                perms: if parent.is_some() {
+                    None
+                } else {
+                    Some(ChannelPerms::default())
+                },
+                slots: -1,
+                lifetime,
+            };
+            // the checks above were made without holding the channels' write lock, so they may be outdated by now
+            let response = match server.create_channel(entry).await {
+                Ok(()) => CreateChannelResponse::Success,
+                Err(err) if err.is::<ErrorUnknownChannel>() => CreateChannelResponse::UnknownParent,
+                Err(err) if err.is::<ErrorChannelNameTaken>() => CreateChannelResponse::InvalidName,
+                Err(err) if err.is::<ErrorChannelOutlivesParent>() => CreateChannelResponse::InvalidLifetime,
+                Err(err) => {
+                    server.println(format!("Failed creating a channel: {}", err).as_str());
+                    CreateChannelResponse::Failed
+                }
+            };
+            let response = ServerPacket::CreateChannelResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+            // move the creator into the new channel, this also keeps temporary channels from being deleted right away
+            let new_channel = server.channels.read().await.get(&Uuid::from_u128(id)).cloned();
+            if let Some(new_channel) = new_channel {
+                server.move_user(user, &new_channel).await;
+            }
+        }
+        ClientPacket::EditServerGroup(edit) => {
+            let response = edit_server_group(server, client.user.get().unwrap(), edit).await;
+            let response = ServerPacket::ServerGroupResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
+        ClientPacket::SubscribeChannel { channel } => {
+            server.subscribe_channel(client.user.get().unwrap(), &channel).await.unwrap(); // FIXME: handle errors properly!
+        }
+        ClientPacket::UnsubscribeChannel { channel } => {
+            server.unsubscribe_channel(client.user.get().unwrap(), &channel).await.unwrap(); // FIXME: handle errors properly!
+        }
+        ClientPacket::RedeemToken { token } => {
+            let user = client.user.get().unwrap();
+            let response = match server.redeem_privilege_token(&user.uuid, token.trim()).await {
+                Ok(true) => RedeemTokenResponse::Success,
+                Ok(false) => RedeemTokenResponse::InvalidToken,
+                Err(err) => {
+                    // the group the token grants may have been deleted in the meantime
+                    server.println(format!("An error occurred while {:?} redeemed a privilege token: {}", user.uuid, err).as_str());
+                    RedeemTokenResponse::InvalidToken
+                }
+            };
+            let response = ServerPacket::RedeemTokenResponse(response).encode().unwrap();
+            client.send_reliable(&response).await.unwrap();
+        }
+        ClientPacket::InfoRequest => {
+            // info is only handed out on anonymous connections
+        }
+    }
+}
+
+/// returns whether a user with `perms` in a channel may assign (or unassign) the channel group `group` there,
+/// users can't hand out more power for any perm than they have themselves
+fn can_manage_channel_group(perms: &Perms, group: &ChannelGroup) -> bool {
+    group.perms.iter().all(|(key, value)| perms.granted(key) >= value.granted)
+}
+
+/// returns whether a user with `power` may assign (or unassign) `group`, users can't hand out groups
+/// whose priority or assign power exceeds their own power or which need more power than they have
+fn can_manage_group(power: u64, key: &str, group: &ServerGroup) -> bool {
+    power > 0 && power >= group.priority && power >= group.perms.granted(perms::SERVER_GROUP_ASSIGN) && power >= group.perms.needed(key)
+}
+
+async fn update_server_groups(server: &Arc<Server>, user: &Arc<User>, target: UserUuid, update: ClientUpdateServerGroups) -> ServerGroupResponse {
+    let (group, key) = match &update {
+        ClientUpdateServerGroups::Add(group) => (*group, perms::SERVER_GROUP_ASSIGN),
+        ClientUpdateServerGroups::Remove(group) => (*group, perms::SERVER_GROUP_UNASSIGN),
+    };
+    let power = user.active_perms.load().granted(key);
+    let Some(server_group) = server.server_groups.read().await.get(&group).cloned() else {
+        return ServerGroupResponse::UnknownGroup;
+    };
+    if !can_manage_group(power, key, &server_group) {
+        return ServerGroupResponse::NoPermission;
+    }
+    let result = match update {
+        ClientUpdateServerGroups::Add(group) => server.assign_server_group(&target, &group).await,
+        ClientUpdateServerGroups::Remove(group) => server.unassign_server_group(&target, &group).await,
+    };
+    server_group_response(server, result)
+}
+
+async fn edit_server_group(server: &Arc<Server>, user: &Arc<User>, edit: ServerGroupEdit) -> ServerGroupResponse {
+    let active_perms = user.active_perms.load();
+    // editing a group requires the power to both assign and unassign it
+    let power = active_perms.granted(perms::SERVER_GROUP_ASSIGN).min(active_perms.granted(perms::SERVER_GROUP_UNASSIGN));
+    match &edit {
+        ServerGroupEdit::Create { perms, .. } | ServerGroupEdit::Perms { perms, .. } => {
+            // groups may not grant more power than their editor has
+            if perms.iter().any(|(key, value)| value.granted > active_perms.granted(key)) {
+                return ServerGroupResponse::NoPermission;
+            }
+        }
+        _ => {}
+    }
+    // the group is checked by the server while it holds the lock the edit is made under, so it can't change in between
+    let result = server.edit_server_group(edit, |group| {
+        group.map_or(power > 0, |group| can_manage_group(power, perms::SERVER_GROUP_ASSIGN, group) && can_manage_group(power, perms::SERVER_GROUP_UNASSIGN, group))
+    }).await;
+    server_group_response(server, result.map(|_| ()))
+}
+
+fn server_group_response(server: &Arc<Server>, result: anyhow::Result<()>) -> ServerGroupResponse {
+    match result {
+        Ok(()) => ServerGroupResponse::Success,
+        Err(err) if err.is::<ErrorUnknownServerGroup>() => ServerGroupResponse::UnknownGroup,
+        Err(err) if err.is::<ErrorUnknownUser>() => ServerGroupResponse::UnknownUser,
+        Err(err) if err.is::<ErrorServerGroupNoPermission>() => ServerGroupResponse::NoPermission,
+        Err(err) if err.is::<ErrorEmptyServerGroupName>() || err.is::<ErrorServerGroupNameTaken>() => ServerGroupResponse::InvalidName,
+        Err(err) if err.is::<ErrorProtectedServerGroup>() => ServerGroupResponse::ProtectedGroup,
+        Err(err) => {
+            server.println(format!("Failed changing server groups: {}", err).as_str());
+            ServerGroupResponse::Failed
+        }
+    }
+}
+
+// the quotas of the senders are only cleaned up once there are this many of them
+const MESSAGE_QUOTA_CLEANUP_THRESHOLD: usize = 1024;
+
+pub(crate) async fn store_message(server: &Arc<Server>, receiver: UserUuid, sender: MessageSender, payload: Encrypted<DirectMessageContent>) -> StoreMessageResponse {
+    // only users that have this server as their home server can receive messages
+    if server.user_db.get(&receiver).unwrap().is_none() { // FIXME: handle errors properly!
+        return StoreMessageResponse::UnknownReceiver;
+    }
+    let config = server.config.load();
+    let period = Duration::from_secs(config.mailbox_expiry_secs);
+    let now = Instant::now();
+    if server.message_quotas.len() >= MESSAGE_QUOTA_CLEANUP_THRESHOLD {
+        server.message_quotas.retain(|_, (start, _)| now.duration_since(*start) < period);
+    }
+    let sealed_key = match sender {
+        MessageSender::User(_) => None,
+        MessageSender::Sealed(key) => Some(key),
+    };
+    // every sender only gets a share of the receiver's mailbox, so a single sender can't fill it up for everyone else.
+    // the quota stays locked until the message is stored, so concurrent messages of the same sender can't exceed it
+    let inserted = {
+        let mut quota = server.message_quotas.entry((sender, receiver)).or_insert((now, 0));
+        if now.duration_since(quota.0) >= period {
+            *quota = (now, 0);
+        }
+        if quota.1 >= config.mailbox_sender_max_messages {
+            return StoreMessageResponse::TooManyMessages;
+        }
+        let inserted = server.mailbox_db.insert(&receiver, sealed_key, payload, &config.mailbox_limits()).unwrap(); // FIXME: handle errors properly!
+        if inserted.is_ok() {
+            quota.1 += 1;
+        }
+        inserted
+    };
+    match inserted {
+        Ok(msg) => {
+            // if the receiver is online we can deliver the message right away, it will
+            // only get removed from the mailbox once the receiver acknowledged it
+            if let Some(user) = server.online_users.get(&receiver) {
+                let delivery = ServerPacket::MailboxDelivery { messages: vec![msg] }.encode().unwrap();
+                user.connection.send_reliable(&delivery).await.unwrap(); // FIXME: handle errors properly!
+            }
+            StoreMessageResponse::Success
+        }
+        Err(MailboxError::InvalidSize) => StoreMessageResponse::InvalidSize,
+        Err(MailboxError::QuotaExceeded) => StoreMessageResponse::QuotaExceeded,
     }
 }
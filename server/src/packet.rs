@@ -1,23 +1,22 @@
-use crate::protocol::{ErrorEnumVariantNotFound, RWBytes, RWBytesMut, UserUuid};
-use bytemuck::Pod;
+use crate::perms::Perms;
+use crate::protocol::{ErrorEnumVariantNotFound, Features, RWBytes, RWBytesMut, UserUuid, VersionRange};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use openssl::hash::MessageDigest;
 use openssl::pkey::{PKeyRef, Public};
-use openssl::rsa::{Padding, Rsa};
 use openssl::sign::Verifier;
 use ordinalizer::Ordinal;
 use ruint::aliases::U256;
 use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::fmt::{Debug, Display};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
 use std::sync::atomic::{AtomicBool, AtomicI16, AtomicU16, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use swap_arc::SwapArc;
 use uuid::Uuid;
 
-const PRIVATE_KEY_LEN_BITS: u32 = 4096;
-
 /// packets the server sends to the client
 /// size: u64
 /// id: u8
@@ -26,7 +25,10 @@ const PRIVATE_KEY_LEN_BITS: u32 = 4096;
 pub enum ServerPacket<'a> {
     AuthResponse(AuthResponse<'a>) = 0,
     ChannelUpdate(ChannelUpdate<'a>) = 1,
-    ClientConnected(RemoteProfile) = 2,
+    ClientConnected {
+        profile: RemoteProfile,
+        channel: Uuid, // the channel the client logged into
+    } = 2,
     ClientDisconnected(RemoteProfile) = 3,
     ClientUpdateServerGroups {
         client: UserUuid,
@@ -43,6 +45,33 @@ pub enum ServerPacket<'a> {
         reason: DisconnectReason,
     } = 7,
     SwitchChannelResponse(SwitchChannelResponse) = 8,
+    MailboxDelivery {
+        messages: Vec<MailMessage>,
+    } = 9,
+    StoreMessageResponse(StoreMessageResponse) = 10,
+    Poke {
+        sender: UserUuid,
+        text: Option<String>,
+    } = 11,
+    PokeResponse(PokeResponse) = 12,
+    AssignChannelGroupResponse(AssignChannelGroupResponse) = 13,
+    CreateChannelResponse(CreateChannelResponse) = 14,
+    ServerGroupUpdate(ServerGroupUpdate) = 15,
+    ServerGroupResponse(ServerGroupResponse) = 16,
+    ChannelClients {
+        channel: Uuid,
+        clients: Option<Vec<RemoteProfile>>, // none if the user is no longer subscribed to the channel
+    } = 17,
+    RedeemTokenResponse(RedeemTokenResponse) = 18,
+    ActivePerms(Perms) = 19, // the perms the receiver currently has on the server
+    DefaultChannel(Uuid) = 20, // the default channel was changed
+    ShutdownScheduled {
+        delay: Duration, // the time until the server shuts down
+        message: Option<String>,
+        restart: bool, // whether the server comes back up right after shutting down
+    } = 21,
+    ServerInfo(ServerInfo) = 22, // the answer to an `InfoRequest`, sent without authentication
+    RegisterSealedKeysResponse(RegisterSealedKeysResponse) = 23,
 }
 
 /// packets the client sends to the server
@@ -53,12 +82,15 @@ pub enum ServerPacket<'a> {
 #[derive(Ordinal)]
 pub enum ClientPacket {
     AuthRequest {
-        protocol_version: u64,
+        protocol_versions: VersionRange, // the versions the client speaks, the server picks the highest one it speaks as well
+        features: Features, // the optional features the client supports
         // auth_kind: ,
         pub_key: Vec<u8>, // the public key of the client which gets later hashed to get it's id
         name: String,
         security_proofs: Vec<U256>, // TODO: add comment
         signed_data: Vec<u8>,       // contains a signed send time
+        channel: Option<Uuid>, // the channel the client wants to log into, none for their last or the default channel
+        channel_password: Option<String>,
     } = 0,
     Disconnect = 1,
     KeepAlive {
@@ -75,6 +107,46 @@ pub enum ClientPacket {
     SwitchChannel {
         channel: Uuid,
     } = 5,
+    StoreMessage {
+        receiver: UserUuid,
+        payload: Encrypted<DirectMessageContent>,
+    } = 6,
+    AckMessages {
+        ids: Vec<u64>,
+    } = 7,
+    RegisterSealedKeys {
+        keys: Vec<SealedAccessKey>,
+    } = 8,
+    StoreSealedMessage {
+        receiver: UserUuid,
+        key_id: Uuid,
+        signed_payload: Vec<u8>, // the payload signed with the private half of the access key, the server never learns who sent it
+    } = 9,
+    Poke {
+        receiver: UserUuid,
+        text: Option<String>,
+    } = 10,
+    AssignChannelGroup {
+        channel: Uuid,
+        user: UserUuid,
+        group: Option<Uuid>, // none removes the user's channel group in this channel
+    } = 11,
+    CreateChannel {
+        parent: Option<Uuid>,
+        name: String,
+        lifetime: ChannelLifetime,
+    } = 12,
+    EditServerGroup(ServerGroupEdit) = 13,
+    SubscribeChannel {
+        channel: Uuid,
+    } = 14,
+    UnsubscribeChannel {
+        channel: Uuid,
+    } = 15,
+    RedeemToken {
+        token: String,
+    } = 16,
+    InfoRequest = 17, // queries the server's info without logging in, only valid as the first packet of a connection
 }
 
 impl ClientPacket {
@@ -105,7 +177,11 @@ impl RWBytes for ServerPacket<'_> {
         match id {
             0 => Ok(Self::AuthResponse(AuthResponse::read(src)?)),
             1 => Ok(Self::ChannelUpdate(ChannelUpdate::read(src)?)),
-            2 => Ok(Self::ClientConnected(RemoteProfile::read(src)?)),
+            2 => {
+                let profile = RemoteProfile::read(src)?;
+                let channel = Uuid::read(src)?;
+                Ok(Self::ClientConnected { profile, channel })
+            }
             3 => Ok(Self::ClientDisconnected(RemoteProfile::read(
                 src,
             )?)),
@@ -127,6 +203,37 @@ impl RWBytes for ServerPacket<'_> {
                 Ok(Self::ForceDisconnect { reason })
             }
             8 => Ok(Self::SwitchChannelResponse(SwitchChannelResponse::read(src)?)),
+            9 => {
+                let messages = Vec::<MailMessage>::read(src)?;
+                Ok(Self::MailboxDelivery { messages })
+            }
+            10 => Ok(Self::StoreMessageResponse(StoreMessageResponse::read(src)?)),
+            11 => {
+                let sender = UserUuid::read(src)?;
+                let text = Option::<String>::read(src)?;
+                Ok(Self::Poke { sender, text })
+            }
+            12 => Ok(Self::PokeResponse(PokeResponse::read(src)?)),
+            13 => Ok(Self::AssignChannelGroupResponse(AssignChannelGroupResponse::read(src)?)),
+            14 => Ok(Self::CreateChannelResponse(CreateChannelResponse::read(src)?)),
+            15 => Ok(Self::ServerGroupUpdate(ServerGroupUpdate::read(src)?)),
+            16 => Ok(Self::ServerGroupResponse(ServerGroupResponse::read(src)?)),
+            17 => {
+                let channel = Uuid::read(src)?;
+                let clients = Option::<Vec<RemoteProfile>>::read(src)?;
+                Ok(Self::ChannelClients { channel, clients })
+            }
+            18 => Ok(Self::RedeemTokenResponse(RedeemTokenResponse::read(src)?)),
+            19 => Ok(Self::ActivePerms(Perms::read(src)?)),
+            20 => Ok(Self::DefaultChannel(Uuid::read(src)?)),
+            21 => {
+                let delay = Duration::read(src)?;
+                let message = Option::<String>::read(src)?;
+                let restart = bool::read(src)?;
+                Ok(Self::ShutdownScheduled { delay, message, restart })
+            }
+            22 => Ok(Self::ServerInfo(ServerInfo::read(src)?)),
+            23 => Ok(Self::RegisterSealedKeysResponse(RegisterSealedKeysResponse::read(src)?)),
             _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
                 "ServerPacket",
                 id,
@@ -143,8 +250,9 @@ impl RWBytes for ServerPacket<'_> {
             ServerPacket::ChannelUpdate(update) => {
                 update.write(dst)?;
             }
-            ServerPacket::ClientConnected(connected) => {
-                connected.write(dst)?;
+            ServerPacket::ClientConnected { profile, channel } => {
+                profile.write(dst)?;
+                channel.write(dst)?;
             }
             ServerPacket::ClientDisconnected(disconnected) => {
                 disconnected.write(dst)?;
@@ -166,6 +274,55 @@ impl RWBytes for ServerPacket<'_> {
             ServerPacket::SwitchChannelResponse(response) => {
                 response.write(dst)?;
             },
+            ServerPacket::MailboxDelivery { messages } => {
+                messages.write(dst)?;
+            }
+            ServerPacket::StoreMessageResponse(response) => {
+                response.write(dst)?;
+            }
+            ServerPacket::Poke { sender, text } => {
+                sender.write(dst)?;
+                text.write(dst)?;
+            }
+            ServerPacket::PokeResponse(response) => {
+                response.write(dst)?;
+            }
+            ServerPacket::AssignChannelGroupResponse(response) => {
+                response.write(dst)?;
+            }
+            ServerPacket::CreateChannelResponse(response) => {
+                response.write(dst)?;
+            }
+            ServerPacket::ServerGroupUpdate(update) => {
+                update.write(dst)?;
+            }
+            ServerPacket::ServerGroupResponse(response) => {
+                response.write(dst)?;
+            }
+            ServerPacket::ChannelClients { channel, clients } => {
+                channel.write(dst)?;
+                clients.write(dst)?;
+            }
+            ServerPacket::RedeemTokenResponse(response) => {
+                response.write(dst)?;
+            }
+            ServerPacket::ActivePerms(perms) => {
+                perms.write(dst)?;
+            }
+            ServerPacket::DefaultChannel(channel) => {
+                channel.write(dst)?;
+            }
+            ServerPacket::ShutdownScheduled { delay, message, restart } => {
+                delay.write(dst)?;
+                message.write(dst)?;
+                restart.write(dst)?;
+            }
+            ServerPacket::ServerInfo(info) => {
+                info.write(dst)?;
+            }
+            ServerPacket::RegisterSealedKeysResponse(response) => {
+                response.write(dst)?;
+            }
         }
         Ok(())
     }
@@ -178,19 +335,35 @@ impl RWBytes for ClientPacket {
         let id = src.get_u8();
         match id {
             0 => {
-                let protocol_version = u64::read(src)?;
+                // the highest version comes first as older clients only sent a single version there
+                let max_version = u64::read(src)?;
                 let pub_key = Vec::<u8>::read(src)?;
                 let name = String::read(src)?;
                 println!("got name: {}", name);
                 let security_proofs = Vec::<U256>::read(src)?;
                 let signed_data = Vec::<u8>::read(src)?;
                 println!("got signed data: {:?}", signed_data);
+                let channel = Option::<Uuid>::read(src)?;
+                let channel_password = Option::<String>::read(src)?;
+                // older clients don't send anything beyond this, they only speak a single version and no features
+                let (min_version, features) = if src.has_remaining() {
+                    (u64::read(src)?, Features::read(src)?)
+                } else {
+                    (max_version, Features::NONE)
+                };
+                let protocol_versions = VersionRange {
+                    min: min_version,
+                    max: max_version,
+                };
                 Ok(Self::AuthRequest {
-                    protocol_version,
+                    protocol_versions,
+                    features,
                     pub_key,
                     name,
                     security_proofs,
                     signed_data,
+                    channel,
+                    channel_password,
                 })
             }
             1 => Ok(Self::Disconnect),
@@ -211,6 +384,56 @@ impl RWBytes for ClientPacket {
                 let channel = Uuid::read(src)?;
                 Ok(Self::SwitchChannel { channel })
             }
+            6 => {
+                let receiver = UserUuid::read(src)?;
+                let payload = Encrypted::read(src)?;
+                Ok(Self::StoreMessage { receiver, payload })
+            }
+            7 => {
+                let ids = Vec::<u64>::read(src)?;
+                Ok(Self::AckMessages { ids })
+            }
+            8 => {
+                let keys = Vec::<SealedAccessKey>::read(src)?;
+                Ok(Self::RegisterSealedKeys { keys })
+            }
+            9 => {
+                let receiver = UserUuid::read(src)?;
+                let key_id = Uuid::read(src)?;
+                let signed_payload = Vec::<u8>::read(src)?;
+                Ok(Self::StoreSealedMessage { receiver, key_id, signed_payload })
+            }
+            10 => {
+                let receiver = UserUuid::read(src)?;
+                let text = Option::<String>::read(src)?;
+                Ok(Self::Poke { receiver, text })
+            }
+            11 => {
+                let channel = Uuid::read(src)?;
+                let user = UserUuid::read(src)?;
+                let group = Option::<Uuid>::read(src)?;
+                Ok(Self::AssignChannelGroup { channel, user, group })
+            }
+            12 => {
+                let parent = Option::<Uuid>::read(src)?;
+                let name = String::read(src)?;
+                let lifetime = ChannelLifetime::read(src)?;
+                Ok(Self::CreateChannel { parent, name, lifetime })
+            }
+            13 => Ok(Self::EditServerGroup(ServerGroupEdit::read(src)?)),
+            14 => {
+                let channel = Uuid::read(src)?;
+                Ok(Self::SubscribeChannel { channel })
+            }
+            15 => {
+                let channel = Uuid::read(src)?;
+                Ok(Self::UnsubscribeChannel { channel })
+            }
+            16 => {
+                let token = String::read(src)?;
+                Ok(Self::RedeemToken { token })
+            }
+            17 => Ok(Self::InfoRequest),
             _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
                 "ClientPacket",
                 id,
@@ -222,17 +445,24 @@ impl RWBytes for ClientPacket {
         dst.put_u8(self.ordinal() as u8);
         match self {
             ClientPacket::AuthRequest {
-                protocol_version,
+                protocol_versions,
+                features,
                 name,
                 pub_key,
                 security_proofs,
                 signed_data,
+                channel,
+                channel_password,
             } => {
-                dst.put_u64_le(*protocol_version);
+                protocol_versions.max.write(dst)?;
                 name.write(dst)?;
                 pub_key.write(dst)?;
                 security_proofs.write(dst)?;
                 signed_data.write(dst)?;
+                channel.write(dst)?;
+                channel_password.write(dst)?;
+                protocol_versions.min.write(dst)?;
+                features.write(dst)?;
             }
             ClientPacket::Disconnect => {}
             ClientPacket::KeepAlive { id, send_time } => {
@@ -249,61 +479,132 @@ impl RWBytes for ClientPacket {
             ClientPacket::SwitchChannel { channel } => {
                 channel.write(dst)?;
             }
+            ClientPacket::StoreMessage { receiver, payload } => {
+                receiver.write(dst)?;
+                payload.write(dst)?;
+            }
+            ClientPacket::AckMessages { ids } => {
+                ids.write(dst)?;
+            }
+            ClientPacket::RegisterSealedKeys { keys } => {
+                keys.write(dst)?;
+            }
+            ClientPacket::StoreSealedMessage { receiver, key_id, signed_payload } => {
+                receiver.write(dst)?;
+                key_id.write(dst)?;
+                signed_payload.write(dst)?;
+            }
+            ClientPacket::Poke { receiver, text } => {
+                receiver.write(dst)?;
+                text.write(dst)?;
+            }
+            ClientPacket::AssignChannelGroup { channel, user, group } => {
+                channel.write(dst)?;
+                user.write(dst)?;
+                group.write(dst)?;
+            }
+            ClientPacket::CreateChannel { parent, name, lifetime } => {
+                parent.write(dst)?;
+                name.write(dst)?;
+                lifetime.write(dst)?;
+            }
+            ClientPacket::EditServerGroup(edit) => {
+                edit.write(dst)?;
+            }
+            ClientPacket::SubscribeChannel { channel } => {
+                channel.write(dst)?;
+            }
+            ClientPacket::UnsubscribeChannel { channel } => {
+                channel.write(dst)?;
+            }
+            ClientPacket::RedeemToken { token } => {
+                token.write(dst)?;
+            }
+            ClientPacket::InfoRequest => {}
         }
         Ok(())
     }
 }
 
-pub(crate) struct Encrypted<T: RWBytes + Pod> {
-    pub data: T,
-    pub pub_key: Rsa<Public>,
+/// data of type `T` that a client end-to-end encrypted for another client, the server
+/// can't decrypt it and only ever passes the ciphertext on as it is
+pub struct Encrypted<T> {
+    pub ciphertext: Vec<u8>,
+    content: PhantomData<T>,
+}
+
+impl<T> Encrypted<T> {
+    pub fn new(ciphertext: Vec<u8>) -> Self {
+        Self {
+            ciphertext,
+            content: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ciphertext.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ciphertext.is_empty()
+    }
 }
 
-impl<T: RWBytes + Pod> RWBytes for Encrypted<T> {
-    type Ty = T;
+impl<T> RWBytes for Encrypted<T> {
+    type Ty = Self;
 
-    fn read(_src: &mut Bytes) -> anyhow::Result<Self::Ty> {
-        todo!()
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        Ok(Self::new(Vec::<u8>::read(src)?))
     }
 
     fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
-        // SAFETY: This is safe because all bit patterns are valid for u8
-        let mut buf = unsafe {
-            Box::new_zeroed_slice(PRIVATE_KEY_LEN_BITS.div_ceil(8) as usize).assume_init()
-        };
-        self.pub_key.public_encrypt(
-            bytemuck::bytes_of(&self.data),
-            &mut buf,
-            Padding::PKCS1_OAEP,
-        )?;
-        buf.into_vec().write(dst)?; // FIXME: optimize writing u8 arrays to the network
-        Ok(())
+        self.ciphertext.write(dst)
     }
 }
 
-pub(crate) struct Signed<T: RWBytes>
-where
-    T::Ty: Pod,
-{
+/// the content of a direct message, only the clients know what it looks like
+pub enum DirectMessageContent {}
+
+pub(crate) struct Signed<T: RWBytes> {
     pub data: T,
     pub pub_key: PKeyRef<Public>,
 }
 
-impl<T: RWBytes> Signed<T>
-    where
-        T::Ty: Pod,
-{
+impl<T: RWBytes> Signed<T> {
 
+    /// reads the signature followed by the data, the signature has to cover the data exactly as it was encoded
     pub fn read(src: &mut Bytes, key: &PKeyRef<Public>) -> anyhow::Result<T::Ty> {
         let signature = Vec::<u8>::read(src)?;
+        let encoded = src.clone();
         let data = T::read(src)?;
+        let encoded = encoded.slice(..(encoded.len() - src.len()));
         let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
-        verifier.verify_oneshot(&signature, bytemuck::bytes_of(&data))?;
+        if !verifier.verify_oneshot(&signature, &encoded)? {
+            return Err(anyhow::Error::from(ErrorInvalidSignature));
+        }
         Ok(data)
     }
 
 }
 
+pub struct ErrorInvalidSignature;
+
+impl Debug for ErrorInvalidSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the signature of the signed data is invalid")
+    }
+}
+
+impl Display for ErrorInvalidSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorInvalidSignature {}
+
 /*impl<T: RWBytes> RWBytes for Signed<T>
 where
     T::Ty: Pod,
@@ -390,8 +691,16 @@ impl RWBytes for ChannelUpdate<'_> {
 pub enum ChannelSubUpdate<'a> {
     Name(Cow<'a, String>),
     Desc(Cow<'a, String>),
-    Perms(ChannelPerms),
+    Perms(Option<ChannelPerms>), // none if the channel inherits the perms of its parent
     Client(ChannelSubClientUpdate),
+    Move {
+        parent: Option<Uuid>,
+        sort_id: u16,
+    },
+    Group {
+        user: UserUuid,
+        group: Option<Uuid>, // none if the user's channel group in this channel was removed
+    },
 }
 
 impl RWBytes for ChannelSubUpdate<'_> {
@@ -410,13 +719,23 @@ impl RWBytes for ChannelSubUpdate<'_> {
                 Ok(Self::Desc(desc))
             }
             2 => {
-                let channel_perms = ChannelPerms::read(src)?;
+                let channel_perms = Option::<ChannelPerms>::read(src)?;
                 Ok(Self::Perms(channel_perms))
             }
             3 => {
                 let update = ChannelSubClientUpdate::read(src)?;
                 Ok(Self::Client(update))
             }
+            4 => {
+                let parent = Option::<Uuid>::read(src)?;
+                let sort_id = u16::read(src)?;
+                Ok(Self::Move { parent, sort_id })
+            }
+            5 => {
+                let user = UserUuid::read(src)?;
+                let group = Option::<Uuid>::read(src)?;
+                Ok(Self::Group { user, group })
+            }
             _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
                 "ChannelSubUpdate",
                 disc,
@@ -440,6 +759,14 @@ impl RWBytes for ChannelSubUpdate<'_> {
             ChannelSubUpdate::Client(client_update) => {
                 client_update.write(dst)?;
             }
+            ChannelSubUpdate::Move { parent, sort_id } => {
+                parent.write(dst)?;
+                sort_id.write(dst)?;
+            }
+            ChannelSubUpdate::Group { user, group } => {
+                user.write(dst)?;
+                group.write(dst)?;
+            }
         }
         Ok(())
     }
@@ -530,6 +857,155 @@ impl RWBytes for ClientUpdateServerGroups {
     }
 }
 
+/// informs clients about changes to the server groups themselves, changes to a user's
+/// memberships are sent via `ServerPacket::ClientUpdateServerGroups`
+#[derive(Ordinal)]
+pub enum ServerGroupUpdate {
+    Create(Arc<ServerGroup>),
+    Rename {
+        group: Uuid,
+        name: String,
+    },
+    Perms {
+        group: Uuid,
+        perms: Perms,
+    },
+    Delete(Uuid),
+    Priority {
+        group: Uuid,
+        priority: u64,
+        negate: bool,
+    },
+}
+
+impl RWBytes for ServerGroupUpdate {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+        match disc {
+            0 => Ok(Self::Create(Arc::new(ServerGroup::read(src)?))),
+            1 => {
+                let group = Uuid::read(src)?;
+                let name = String::read(src)?;
+                Ok(Self::Rename { group, name })
+            }
+            2 => {
+                let group = Uuid::read(src)?;
+                let perms = Perms::read(src)?;
+                Ok(Self::Perms { group, perms })
+            }
+            3 => Ok(Self::Delete(Uuid::read(src)?)),
+            4 => {
+                let group = Uuid::read(src)?;
+                let priority = u64::read(src)?;
+                let negate = bool::read(src)?;
+                Ok(Self::Priority { group, priority, negate })
+            }
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "ServerGroupUpdate",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        match self {
+            ServerGroupUpdate::Create(group) => {
+                group.write(dst)?;
+            }
+            ServerGroupUpdate::Rename { group, name } => {
+                group.write(dst)?;
+                name.write(dst)?;
+            }
+            ServerGroupUpdate::Perms { group, perms } => {
+                group.write(dst)?;
+                perms.write(dst)?;
+            }
+            ServerGroupUpdate::Delete(group) => {
+                group.write(dst)?;
+            }
+            ServerGroupUpdate::Priority { group, priority, negate } => {
+                group.write(dst)?;
+                priority.write(dst)?;
+                negate.write(dst)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// a change to a server group requested by a client
+#[derive(Ordinal)]
+pub enum ServerGroupEdit {
+    Create {
+        name: String,
+        perms: Perms,
+    },
+    Rename {
+        group: Uuid,
+        name: String,
+    },
+    Perms {
+        group: Uuid,
+        perms: Perms,
+    },
+    Delete(Uuid),
+}
+
+impl RWBytes for ServerGroupEdit {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+        match disc {
+            0 => {
+                let name = String::read(src)?;
+                let perms = Perms::read(src)?;
+                Ok(Self::Create { name, perms })
+            }
+            1 => {
+                let group = Uuid::read(src)?;
+                let name = String::read(src)?;
+                Ok(Self::Rename { group, name })
+            }
+            2 => {
+                let group = Uuid::read(src)?;
+                let perms = Perms::read(src)?;
+                Ok(Self::Perms { group, perms })
+            }
+            3 => Ok(Self::Delete(Uuid::read(src)?)),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "ServerGroupEdit",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        match self {
+            ServerGroupEdit::Create { name, perms } => {
+                name.write(dst)?;
+                perms.write(dst)?;
+            }
+            ServerGroupEdit::Rename { group, name } => {
+                group.write(dst)?;
+                name.write(dst)?;
+            }
+            ServerGroupEdit::Perms { group, perms } => {
+                group.write(dst)?;
+                perms.write(dst)?;
+            }
+            ServerGroupEdit::Delete(group) => {
+                group.write(dst)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct RemoteProfile {
     pub name: String,
@@ -563,15 +1039,19 @@ impl RWBytes for RemoteProfile {
 
 pub struct Channel {
     pub uuid: Uuid,
+    pub parent: Arc<SwapArc<Option<Uuid>>>, // none if this is a root channel
     pub password: AtomicBool,
     // pub hide_users_if_pw: AtomicBool, // FIXME: add capability to hide users if a password is set
     pub name: Arc<SwapArc<String>>,
     pub desc: Arc<SwapArc<String>>,
-    pub perms: Arc<SwapArc<ChannelPerms>>,
+    pub perms: Arc<SwapArc<Option<ChannelPerms>>>, // none if the perms are inherited from the parent
     pub clients: Arc<tokio::sync::RwLock<Vec<UserUuid>>>, // FIXME: try making this lock free!
     pub proto_clients: Arc<RwLock<Vec<RemoteProfile>>>, // FIXME: is it worth making RemoteProfiles ref-counted?
     pub slots: AtomicI16,
     pub sort_id: AtomicU16,
+    pub user_groups: Arc<SwapArc<Vec<ChannelGroupAssignment>>>,
+    pub lifetime: ChannelLifetime,
+    pub empty_since: AtomicU64, // the time in millis since which a temporary channel has been empty, 0 if it isn't empty
 }
 
 // FIXME: use Arc<Channel<'_>> so that we don't need a clone impl for Channel<'_>
@@ -579,6 +1059,7 @@ impl Clone for Channel {
     fn clone(&self) -> Self {
         Self {
             uuid: self.uuid,
+            parent: self.parent.clone(),
             password: AtomicBool::new(self.password.load(Ordering::Acquire)),
             name: self.name.clone(),
             desc: self.desc.clone(),
@@ -587,6 +1068,19 @@ impl Clone for Channel {
             proto_clients: self.proto_clients.clone(),
             slots: AtomicI16::new(self.slots.load(Ordering::Acquire)),
             sort_id: AtomicU16::new(self.sort_id.load(Ordering::Acquire)),
+            user_groups: self.user_groups.clone(),
+            lifetime: self.lifetime,
+            empty_since: AtomicU64::new(self.empty_since.load(Ordering::Acquire)),
+        }
+    }
+}
+
+impl Channel {
+    /// returns a copy of this channel without its client list, this is sent to users who aren't subscribed to the channel
+    pub fn without_clients(&self) -> Self {
+        Self {
+            proto_clients: Arc::new(RwLock::new(vec![])),
+            ..self.clone()
         }
     }
 }
@@ -608,16 +1102,20 @@ impl RWBytes for Channel {
 
     fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
         let uuid = Uuid::read(src)?;
+        let parent = Arc::new(SwapArc::new(Arc::new(Option::<Uuid>::read(src)?)));
         let password = AtomicBool::new(bool::read(src)?);
         let name = Arc::new(SwapArc::new(Arc::new(String::read(src)?)));
         let desc = Arc::new(SwapArc::new(Arc::new(String::read(src)?)));
-        let perms = Arc::new(SwapArc::new(Arc::new(ChannelPerms::read(src)?)));
+        let perms = Arc::new(SwapArc::new(Arc::new(Option::<ChannelPerms>::read(src)?)));
         let clients = Arc::new(RwLock::new(Vec::<RemoteProfile>::read(src)?));
         let slots = AtomicI16::new(i16::read(src)?);
         let sort_id = AtomicU16::new(u16::read(src)?);
+        let user_groups = Arc::new(SwapArc::new(Arc::new(Vec::<ChannelGroupAssignment>::read(src)?)));
+        let lifetime = ChannelLifetime::read(src)?;
 
         Ok(Self {
             uuid,
+            parent,
             password,
             name,
             desc,
@@ -626,11 +1124,15 @@ impl RWBytes for Channel {
             clients: Arc::new(tokio::sync::RwLock::new(vec![])),
             slots,
             sort_id,
+            user_groups,
+            lifetime,
+            empty_since: AtomicU64::new(0),
         })
     }
 
     fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
         self.uuid.write(dst)?;
+        RWBytes::write(&self.parent, dst)?;
         self.password.write(dst)?;
         RWBytes::write(&self.name, dst)?;
         RWBytes::write(&self.desc, dst)?;
@@ -638,6 +1140,103 @@ impl RWBytes for Channel {
         RWBytes::write(&self.proto_clients, dst)?;
         self.slots.write(dst)?;
         self.sort_id.write(dst)?;
+        RWBytes::write(&self.user_groups, dst)?;
+        self.lifetime.write(dst)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Ordinal, Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ChannelLifetime {
+    Permanent = 0, // persisted in the channel db
+    SemiPermanent = 1, // stays when empty and is persisted unless the server's config says otherwise
+    Temporary = 2, // deleted automatically some time after the last user left
+}
+
+impl Default for ChannelLifetime {
+    fn default() -> Self {
+        Self::Permanent
+    }
+}
+
+impl RWBytes for ChannelLifetime {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Permanent),
+            1 => Ok(Self::SemiPermanent),
+            2 => Ok(Self::Temporary),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "ChannelLifetime",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ChannelGroupAssignment {
+    pub user: UserUuid,
+    pub group: Uuid,
+}
+
+impl RWBytes for ChannelGroupAssignment {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let user = UserUuid::read(src)?;
+        let group = Uuid::read(src)?;
+
+        Ok(Self {
+            user,
+            group,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.user.write(dst)?;
+        self.group.write(dst)?;
+
+        Ok(())
+    }
+}
+
+/// a per-channel role like channel admin, operator or guest
+pub struct ChannelGroup {
+    pub uuid: Uuid,
+    pub name: String,
+    pub perms: Perms, // granted to all members of this group in the channel and its subchannels
+}
+
+impl RWBytes for ChannelGroup {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let uuid = Uuid::read(src)?;
+        let name = String::read(src)?;
+        let perms = Perms::read(src)?;
+
+        Ok(Self {
+            uuid,
+            name,
+            perms,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.uuid.write(dst)?;
+        self.name.write(dst)?;
+        self.perms.write(dst)?;
 
         Ok(())
     }
@@ -705,8 +1304,9 @@ impl RWBytes for ChannelPerms {
 pub struct ServerGroup {
     pub uuid: Uuid,
     pub name: String,
-    pub priority: u64,
-    pub perms: PermsSnapshot,
+    pub priority: u64, // groups with a higher priority get applied later and thus override groups with a lower priority
+    pub negate: bool,
+    pub perms: Perms,
 }
 
 impl RWBytes for ServerGroup {
@@ -716,12 +1316,14 @@ impl RWBytes for ServerGroup {
         let uuid = Uuid::read(src)?;
         let name = String::read(src)?;
         let priority = u64::read(src)?;
-        let perms = PermsSnapshot::read(src)?;
+        let negate = bool::read(src)?;
+        let perms = Perms::read(src)?;
 
         Ok(Self {
             uuid,
             name,
             priority,
+            negate,
             perms,
         })
     }
@@ -730,112 +1332,64 @@ impl RWBytes for ServerGroup {
         self.uuid.write(dst)?;
         self.name.write(dst)?;
         self.priority.write(dst)?;
+        self.negate.write(dst)?;
         self.perms.write(dst)?;
 
         Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
-pub struct PermsSnapshot { // FIXME: snapshot isn't a good name!
-    pub server_group_assign: u64,
-    pub server_group_unassign: u64,
-    pub channel_see: u64,
-    pub channel_join: u64,
-    pub channel_modify: u64,
-    pub channel_talk: u64,
-    pub channel_assign_talk: u64,
-    pub channel_delete: u64,
-    pub can_send: bool,
-    pub channel_create: ChannelCreatePerms,
-}
-
-impl RWBytes for PermsSnapshot {
-    type Ty = Self;
-
-    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
-        let server_group_assign = u64::read(src)?;
-        let server_group_unassign = u64::read(src)?;
-        let channel_see = u64::read(src)?;
-        let channel_join = u64::read(src)?;
-        let channel_modify = u64::read(src)?;
-        let channel_talk = u64::read(src)?;
-        let channel_assign_talk = u64::read(src)?;
-        let channel_delete = u64::read(src)?;
-        let can_send = bool::read(src)?;
-        let channel_create = ChannelCreatePerms::read(src)?;
-
-        Ok(Self {
-            server_group_assign,
-            server_group_unassign,
-            channel_see,
-            channel_join,
-            channel_modify,
-            channel_talk,
-            channel_assign_talk,
-            channel_delete,
-            can_send,
-            channel_create,
-        })
-    }
-
-    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
-        self.server_group_assign.write(dst)?;
-        self.server_group_unassign.write(dst)?;
-        self.channel_see.write(dst)?;
-        self.channel_join.write(dst)?;
-        self.channel_modify.write(dst)?;
-        self.channel_talk.write(dst)?;
-        self.channel_assign_talk.write(dst)?;
-        self.channel_delete.write(dst)?;
-        self.can_send.write(dst)?;
-        self.channel_create.write(dst)?;
-
-        Ok(())
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone, Default)]
-pub struct ChannelCreatePerms {
-    pub power: u64,
-    pub set_desc: bool,
-    pub set_password: bool,
-    pub resort_channel: bool,
-    // FIXME: add other features that channels have
+pub struct ServerInfo {
+    pub name: String,
+    pub welcome_message: String,
+    pub online: u32, // the number of users that are currently online
+    pub slots: u32, // the maximum number of users that may be online at the same time
+    pub req_security_level: u8,
+    pub protocol_versions: VersionRange,
+    pub icon: Option<Vec<u8>>, // a png image
 }
 
-impl RWBytes for ChannelCreatePerms {
+impl RWBytes for ServerInfo {
     type Ty = Self;
 
     fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
-        let power = u64::read(src)?;
-        let set_desc = bool::read(src)?;
-        let set_password = bool::read(src)?;
-        let resort_channel = bool::read(src)?;
-
+        let name = String::read(src)?;
+        let welcome_message = String::read(src)?;
+        let online = u32::read(src)?;
+        let slots = u32::read(src)?;
+        let req_security_level = u8::read(src)?;
+        let protocol_versions = VersionRange::read(src)?;
+        let icon = Option::<Vec<u8>>::read(src)?;
         Ok(Self {
-            power,
-            set_desc,
-            set_password,
-            resort_channel,
+            name,
+            welcome_message,
+            online,
+            slots,
+            req_security_level,
+            protocol_versions,
+            icon,
         })
     }
 
     fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
-        self.power.write(dst)?;
-        self.set_desc.write(dst)?;
-        self.set_password.write(dst)?;
-        self.resort_channel.write(dst)?;
-
-        Ok(())
+        self.name.write(dst)?;
+        self.welcome_message.write(dst)?;
+        self.online.write(dst)?;
+        self.slots.write(dst)?;
+        self.req_security_level.write(dst)?;
+        self.protocol_versions.write(dst)?;
+        self.icon.write(dst)
     }
 }
 
 #[derive(Ordinal)]
 pub enum AuthResponse<'a> {
     Success {
+        protocol_version: u64, // the version both sides agreed on
+        features: Features, // the optional features both sides support
         default_channel_id: Uuid,
         server_groups: Vec<Arc<ServerGroup>>,
+        channel_groups: Vec<Arc<ChannelGroup>>,
         own_groups: Vec<Uuid>,
         channels: Vec<Channel>,
     },
@@ -872,13 +1426,19 @@ impl RWBytes for AuthResponse<'_> {
 
         match disc {
             0 => {
+                let protocol_version = u64::read(src)?;
+                let features = Features::read(src)?;
                 let default_channel_id = Uuid::read(src)?;
                 let server_groups = Vec::<Arc<ServerGroup>>::read(src)?;
+                let channel_groups = Vec::<Arc<ChannelGroup>>::read(src)?;
                 let own_groups = Vec::<Uuid>::read(src)?;
                 let channels = Vec::<Channel>::read(src)?;
                 Ok(Self::Success {
+                    protocol_version,
+                    features,
                     default_channel_id,
                     server_groups,
+                    channel_groups,
                     own_groups,
                     channels,
                 })
@@ -898,13 +1458,19 @@ impl RWBytes for AuthResponse<'_> {
         dst.put_u8(self.ordinal() as u8);
         match self {
             AuthResponse::Success {
+                protocol_version,
+                features,
                 default_channel_id,
                 server_groups,
+                channel_groups,
                 own_groups,
                 channels,
             } => {
+                dst.put_u64_le(*protocol_version);
+                features.write(dst)?;
                 default_channel_id.write(dst)?;
                 server_groups.write(dst)?;
+                channel_groups.write(dst)?;
                 own_groups.write(dst)?;
                 // let mut val = channels.borrow_mut();
                 // RWBytesMut::write(val.deref_mut(), dst)?;
@@ -926,7 +1492,7 @@ pub enum AuthFailure<'a> {
         duration: BanDuration,
     } = 0,
     ReqSec(u8) = 1,
-    OutOfDate(u64) = 2, // protocol version
+    OutOfDate(VersionRange) = 2, // the protocol versions the server speaks
     AlreadyOnline = 3,
     Invalid(Cow<'a, str>) = 4,
 }
@@ -948,8 +1514,10 @@ impl RWBytes for AuthFailure<'_> {
                 Ok(Self::ReqSec(req_ver))
             }
             2 => {
-                let req_ver = src.get_u64_le();
-                Ok(Self::OutOfDate(req_ver))
+                // older servers only sent the single version they spoke
+                let max = u64::read(src)?;
+                let min = if src.has_remaining() { u64::read(src)? } else { max };
+                Ok(Self::OutOfDate(VersionRange { min, max }))
             }
             3 => {
                 Ok(Self::AlreadyOnline)
@@ -975,8 +1543,10 @@ impl RWBytes for AuthFailure<'_> {
             AuthFailure::ReqSec(security) => {
                 dst.put_u8(*security);
             }
-            AuthFailure::OutOfDate(req_protocol) => {
-                dst.put_u64_le(*req_protocol);
+            AuthFailure::OutOfDate(supported) => {
+                // older clients only read a single version, so the lowest one is appended
+                supported.max.write(dst)?;
+                supported.min.write(dst)?;
             }
             AuthFailure::AlreadyOnline => {}
             AuthFailure::Invalid(err) => {
@@ -1020,6 +1590,177 @@ impl RWBytes for SwitchChannelResponse {
     }
 }
 
+#[derive(Ordinal)]
+#[repr(u8)]
+pub enum StoreMessageResponse {
+    Success = 0,
+    UnknownReceiver = 1, // the receiver doesn't have this server as their home server
+    InvalidSize = 2,
+    QuotaExceeded = 3, // the receiver's mailbox is full
+    InvalidSignature = 4, // the sealed message wasn't signed with a valid access key of the receiver
+    NotAnonymous = 5, // sealed messages are only accepted on anonymous connections
+    TooManyMessages = 6, // the sender sent too many messages recently
+}
+
+impl RWBytes for StoreMessageResponse {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Success),
+            1 => Ok(Self::UnknownReceiver),
+            2 => Ok(Self::InvalidSize),
+            3 => Ok(Self::QuotaExceeded),
+            4 => Ok(Self::InvalidSignature),
+            5 => Ok(Self::NotAnonymous),
+            6 => Ok(Self::TooManyMessages),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "StoreMessageResponse",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Ordinal)]
+#[repr(u8)]
+pub enum RegisterSealedKeysResponse {
+    Success = 0,
+    TooManyKeys = 1, // the user already registered as many keys as the server allows
+}
+
+impl RWBytes for RegisterSealedKeysResponse {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Success),
+            1 => Ok(Self::TooManyKeys),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "RegisterSealedKeysResponse",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Ordinal)]
+#[repr(u8)]
+pub enum PokeResponse {
+    Success = 0,
+    UnknownReceiver = 1, // the receiver isn't online
+    NoPermission = 2,
+    RateLimited = 3,
+    TextTooLong = 4,
+}
+
+impl RWBytes for PokeResponse {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Success),
+            1 => Ok(Self::UnknownReceiver),
+            2 => Ok(Self::NoPermission),
+            3 => Ok(Self::RateLimited),
+            4 => Ok(Self::TextTooLong),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "PokeResponse",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct MailMessage {
+    pub id: u64,
+    pub received: Duration, // the time at which the home server received the message
+    pub sealed_key: Option<Uuid>, // the access key the message was sealed with, none if the sender is known to the server
+    pub payload: Encrypted<DirectMessageContent>,
+}
+
+impl RWBytes for MailMessage {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let id = u64::read(src)?;
+        let received = Duration::read(src)?;
+        let sealed_key = Option::<Uuid>::read(src)?;
+        let payload = Encrypted::read(src)?;
+
+        Ok(Self {
+            id,
+            received,
+            sealed_key,
+            payload,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.id.write(dst)?;
+        self.received.write(dst)?;
+        self.sealed_key.write(dst)?;
+        self.payload.write(dst)?;
+
+        Ok(())
+    }
+}
+
+/// a temporary key the receiver hands out to one of their contacts, the server only ever
+/// sees the public half and uses it to check that the sender may message the receiver
+#[derive(Clone)]
+pub struct SealedAccessKey {
+    pub id: Uuid,
+    pub pub_key: Vec<u8>, // DER encoded
+    pub expiry: Duration,
+}
+
+impl RWBytes for SealedAccessKey {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let id = Uuid::read(src)?;
+        let pub_key = Vec::<u8>::read(src)?;
+        let expiry = Duration::read(src)?;
+
+        Ok(Self {
+            id,
+            pub_key,
+            expiry,
+        })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        self.id.write(dst)?;
+        self.pub_key.write(dst)?;
+        self.expiry.write(dst)?;
+
+        Ok(())
+    }
+}
+
 #[derive(Ordinal)]
 pub enum BanDuration {
     Permanent,
@@ -1070,6 +1811,10 @@ pub enum DisconnectReason {
     } = 1,
     DecodeError(String) = 2,
     Timeout = 3,
+    ServerShutdown {
+        message: Option<String>,
+        restart: bool, // whether clients should reconnect once the server is back up
+    } = 4,
 }
 
 impl RWBytes for DisconnectReason {
@@ -1083,6 +1828,7 @@ impl RWBytes for DisconnectReason {
             1 => Ok(Self::ReceivedInvalidPacketSize { allowed: u64::read(src)?, received: u64::read(src)? }),
             2 => Ok(Self::DecodeError(String::read(src)?)),
             3 => Ok(Self::Timeout),
+            4 => Ok(Self::ServerShutdown { message: Option::<String>::read(src)?, restart: bool::read(src)? }),
             _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
                 "DisconnectReason",
                 ord,
@@ -1103,6 +1849,150 @@ impl RWBytes for DisconnectReason {
             }
             DisconnectReason::DecodeError(error) => error.write(dst),
             DisconnectReason::Timeout => Ok(()),
+            DisconnectReason::ServerShutdown { message, restart } => {
+                message.write(dst)?;
+                restart.write(dst)
+            }
+        }
+    }
+}
+
+#[derive(Ordinal)]
+#[repr(u8)]
+pub enum AssignChannelGroupResponse {
+    Success = 0,
+    UnknownChannel = 1,
+    UnknownUser = 2, // the user isn't online
+    UnknownGroup = 3,
+    NoPermission = 4,
+}
+
+impl RWBytes for AssignChannelGroupResponse {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Success),
+            1 => Ok(Self::UnknownChannel),
+            2 => Ok(Self::UnknownUser),
+            3 => Ok(Self::UnknownGroup),
+            4 => Ok(Self::NoPermission),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "AssignChannelGroupResponse",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Ordinal)]
+#[repr(u8)]
+pub enum CreateChannelResponse {
+    Success = 0,
+    UnknownParent = 1,
+    NoPermission = 2,
+    InvalidName = 3, // the name is empty or already taken
+    InvalidLifetime = 4, // the channel could outlive its parent
+    Failed = 5, // the server couldn't store the channel
+}
+
+impl RWBytes for CreateChannelResponse {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Success),
+            1 => Ok(Self::UnknownParent),
+            2 => Ok(Self::NoPermission),
+            3 => Ok(Self::InvalidName),
+            4 => Ok(Self::InvalidLifetime),
+            5 => Ok(Self::Failed),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "CreateChannelResponse",
+                disc,
+            ))),
         }
     }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Ordinal)]
+#[repr(u8)]
+pub enum ServerGroupResponse {
+    Success = 0,
+    UnknownGroup = 1,
+    UnknownUser = 2,
+    NoPermission = 3,
+    InvalidName = 4, // the name is empty or already taken
+    ProtectedGroup = 5, // the admin and default groups can't be deleted
+    Failed = 6, // the server couldn't store the change
+}
+
+impl RWBytes for ServerGroupResponse {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Success),
+            1 => Ok(Self::UnknownGroup),
+            2 => Ok(Self::UnknownUser),
+            3 => Ok(Self::NoPermission),
+            4 => Ok(Self::InvalidName),
+            5 => Ok(Self::ProtectedGroup),
+            6 => Ok(Self::Failed),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "ServerGroupResponse",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
+}
+
+#[derive(Ordinal)]
+#[repr(u8)]
+pub enum RedeemTokenResponse {
+    Success = 0,
+    InvalidToken = 1, // the token doesn't exist, expired or was used up
+}
+
+impl RWBytes for RedeemTokenResponse {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+
+        match disc {
+            0 => Ok(Self::Success),
+            1 => Ok(Self::InvalidToken),
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound(
+                "RedeemTokenResponse",
+                disc,
+            ))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u8(self.ordinal() as u8);
+        Ok(())
+    }
 }
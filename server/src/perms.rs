@@ -0,0 +1,415 @@
+// perms are stored in a registry of named keys, every key holds the power a user was
+// granted and the power others need to perform the action on the holder of the perms
+// (a user or a group), an action is allowed if the granted power reaches the needed power
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde_derive::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::packet::{ChannelGroup, ServerGroup};
+use crate::protocol::RWBytes;
+
+pub const SERVER_GROUP_ASSIGN: &str = "server_group_assign";
+pub const SERVER_GROUP_UNASSIGN: &str = "server_group_unassign";
+pub const CHANNEL_SEE: &str = "channel_see";
+pub const CHANNEL_JOIN: &str = "channel_join";
+pub const CHANNEL_MODIFY: &str = "channel_modify";
+pub const CHANNEL_TALK: &str = "channel_talk";
+pub const CHANNEL_ASSIGN_TALK: &str = "channel_assign_talk";
+pub const CHANNEL_DELETE: &str = "channel_delete";
+pub const SEND: &str = "send";
+pub const POKE: &str = "poke";
+pub const CHANNEL_CREATE: &str = "channel_create";
+pub const CHANNEL_CREATE_SET_DESC: &str = "channel_create_set_desc";
+pub const CHANNEL_CREATE_SET_PASSWORD: &str = "channel_create_set_password";
+pub const CHANNEL_CREATE_RESORT: &str = "channel_create_resort";
+pub const CHANNEL_CREATE_PERMANENT: &str = "channel_create_permanent";
+pub const CHANNEL_CREATE_SEMI_PERMANENT: &str = "channel_create_semi_permanent";
+pub const CHANNEL_CREATE_TEMPORARY: &str = "channel_create_temporary";
+
+/// all perms the server knows about, new features only have to add their key here
+pub const PERM_KEYS: &[&str] = &[
+    SERVER_GROUP_ASSIGN,
+    SERVER_GROUP_UNASSIGN,
+    CHANNEL_SEE,
+    CHANNEL_JOIN,
+    CHANNEL_MODIFY,
+    CHANNEL_TALK,
+    CHANNEL_ASSIGN_TALK,
+    CHANNEL_DELETE,
+    SEND,
+    POKE,
+    CHANNEL_CREATE,
+    CHANNEL_CREATE_SET_DESC,
+    CHANNEL_CREATE_SET_PASSWORD,
+    CHANNEL_CREATE_RESORT,
+    CHANNEL_CREATE_PERMANENT,
+    CHANNEL_CREATE_SEMI_PERMANENT,
+    CHANNEL_CREATE_TEMPORARY,
+];
+
+pub fn is_known(key: &str) -> bool {
+    PERM_KEYS.contains(&key)
+}
+
+/// resolves the perms of a user from all of their sources, this is the only place where they get combined.
+/// the user's server groups are applied in the order of their priority, then the channel group the user has
+/// in the channel the perms are resolved for (if any) grants its perms and the user's overrides are applied last
+pub fn resolve(server_groups: &HashMap<Uuid, Arc<ServerGroup>>, groups: &[Uuid], channel_group: Option<&ChannelGroup>, overrides: &Perms) -> Perms {
+    let mut perms = Perms::default();
+    // groups that were deleted in the meantime don't grant any perms
+    let mut groups = groups.iter().filter_map(|group| server_groups.get(group)).collect::<Vec<_>>();
+    groups.sort_by_key(|group| group.priority);
+    for group in groups {
+        if group.negate {
+            perms.revoke(&group.perms);
+        } else {
+            perms.grant(&group.perms);
+        }
+    }
+    if let Some(channel_group) = channel_group {
+        perms.grant(&channel_group.perms);
+    }
+    perms.apply_overrides(overrides);
+    perms
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct PermValue {
+    pub granted: u64,
+    #[serde(default)]
+    pub needed: u64,
+}
+
+/// perms that aren't present in the registry neither grant nor require any power
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(from = "StoredPerms", into = "BTreeMap<String, PermValue>")]
+pub struct Perms {
+    values: BTreeMap<String, PermValue>,
+}
+
+impl Perms {
+
+    pub fn get(&self, key: &str) -> PermValue {
+        self.values.get(key).copied().unwrap_or_default()
+    }
+
+    pub fn granted(&self, key: &str) -> u64 {
+        self.get(key).granted
+    }
+
+    pub fn needed(&self, key: &str) -> u64 {
+        self.get(key).needed
+    }
+
+    /// returns whether the perm is granted at all, this is used for perms that are simple flags
+    pub fn has(&self, key: &str) -> bool {
+        self.granted(key) > 0
+    }
+
+    pub fn set(&mut self, key: &str, value: PermValue) {
+        if value == PermValue::default() {
+            self.values.remove(key);
+        } else {
+            self.values.insert(key.to_string(), value);
+        }
+    }
+
+    /// unlike `set` this keeps perms that neither grant nor need any power, overrides use this to deny perms
+    pub fn insert(&mut self, key: &str, value: PermValue) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<PermValue> {
+        self.values.remove(key)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn set_granted(&mut self, key: &str, granted: u64) {
+        let needed = self.needed(key);
+        self.set(key, PermValue { granted, needed });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &PermValue)> {
+        self.values.iter()
+    }
+
+    /// raises every perm to the power `other` grants or needs if that is higher
+    pub fn grant(&mut self, other: &Perms) {
+        for (key, value) in other.iter() {
+            let curr = self.get(key);
+            self.set(key, PermValue {
+                granted: curr.granted.max(value.granted),
+                needed: curr.needed.max(value.needed),
+            });
+        }
+    }
+
+    /// revokes every perm `other` grants, perms it doesn't grant stay untouched
+    pub fn revoke(&mut self, other: &Perms) {
+        for (key, value) in other.iter() {
+            if value.granted > 0 {
+                let needed = self.needed(key);
+                self.set(key, PermValue { granted: 0, needed });
+            }
+        }
+    }
+
+    /// replaces every perm that is present in `overrides` with its value in there
+    pub fn apply_overrides(&mut self, overrides: &Perms) {
+        for (key, value) in overrides.iter() {
+            self.set(key, *value);
+        }
+    }
+
+}
+
+impl RWBytes for Perms {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let len = src.get_u64_le() as usize;
+        let mut values = BTreeMap::new();
+        for _ in 0..len {
+            let key = String::read(src)?;
+            let granted = u64::read(src)?;
+            let needed = u64::read(src)?;
+            values.insert(key, PermValue { granted, needed });
+        }
+        Ok(Self { values })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u64_le(self.values.len() as u64);
+        for (key, value) in self.values.iter() {
+            key.write(dst)?;
+            value.granted.write(dst)?;
+            value.needed.write(dst)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Perms> for BTreeMap<String, PermValue> {
+    fn from(perms: Perms) -> Self {
+        perms.values
+    }
+}
+
+/// server group dbs that were written before perms were stored in a registry contain fixed fields
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPerms {
+    Registry(BTreeMap<String, PermValue>),
+    Legacy(LegacyPerms),
+}
+
+#[derive(Deserialize)]
+struct LegacyPerms {
+    server_group_assign: u64,
+    server_group_unassign: u64,
+    channel_see: u64,
+    channel_join: u64,
+    channel_modify: u64,
+    channel_talk: u64,
+    channel_assign_talk: u64,
+    channel_delete: u64,
+    can_send: bool,
+    channel_create: LegacyChannelCreatePerms,
+    #[serde(default)]
+    can_poke: bool,
+}
+
+#[derive(Deserialize)]
+struct LegacyChannelCreatePerms {
+    power: u64,
+    set_desc: bool,
+    set_password: bool,
+    resort_channel: bool,
+    #[serde(default)]
+    permanent: bool,
+    #[serde(default)]
+    semi_permanent: bool,
+    #[serde(default)]
+    temporary: bool,
+}
+
+impl From<StoredPerms> for Perms {
+    fn from(stored: StoredPerms) -> Self {
+        match stored {
+            StoredPerms::Registry(values) => Self { values },
+            StoredPerms::Legacy(legacy) => {
+                let mut perms = Perms::default();
+                perms.set_granted(SERVER_GROUP_ASSIGN, legacy.server_group_assign);
+                perms.set_granted(SERVER_GROUP_UNASSIGN, legacy.server_group_unassign);
+                perms.set_granted(CHANNEL_SEE, legacy.channel_see);
+                perms.set_granted(CHANNEL_JOIN, legacy.channel_join);
+                perms.set_granted(CHANNEL_MODIFY, legacy.channel_modify);
+                perms.set_granted(CHANNEL_TALK, legacy.channel_talk);
+                perms.set_granted(CHANNEL_ASSIGN_TALK, legacy.channel_assign_talk);
+                perms.set_granted(CHANNEL_DELETE, legacy.channel_delete);
+                perms.set_granted(SEND, legacy.can_send as u64);
+                perms.set_granted(POKE, legacy.can_poke as u64);
+                perms.set_granted(CHANNEL_CREATE, legacy.channel_create.power);
+                perms.set_granted(CHANNEL_CREATE_SET_DESC, legacy.channel_create.set_desc as u64);
+                perms.set_granted(CHANNEL_CREATE_SET_PASSWORD, legacy.channel_create.set_password as u64);
+                perms.set_granted(CHANNEL_CREATE_RESORT, legacy.channel_create.resort_channel as u64);
+                perms.set_granted(CHANNEL_CREATE_PERMANENT, legacy.channel_create.permanent as u64);
+                perms.set_granted(CHANNEL_CREATE_SEMI_PERMANENT, legacy.channel_create.semi_permanent as u64);
+                perms.set_granted(CHANNEL_CREATE_TEMPORARY, legacy.channel_create.temporary as u64);
+                perms
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perms(values: &[(&str, u64, u64)]) -> Perms {
+        let mut perms = Perms::default();
+        for (key, granted, needed) in values {
+            perms.insert(key, PermValue { granted: *granted, needed: *needed });
+        }
+        perms
+    }
+
+    fn server_group(priority: u64, negate: bool, perms: Perms) -> Arc<ServerGroup> {
+        Arc::new(ServerGroup {
+            uuid: Uuid::new_v4(),
+            name: String::new(),
+            priority,
+            negate,
+            perms,
+        })
+    }
+
+    #[test]
+    fn perms_survive_a_round_trip() {
+        let perms = perms(&[(CHANNEL_JOIN, 50, 10), (SEND, 1, 0), ("unknown_perm", 7, 3)]);
+
+        let mut buf = BytesMut::new();
+        perms.write(&mut buf).unwrap();
+        let read = Perms::read(&mut buf.freeze()).unwrap();
+        assert_eq!(read.iter().collect::<Vec<_>>(), perms.iter().collect::<Vec<_>>());
+
+        let json = serde_json::to_string(&perms).unwrap();
+        let read = serde_json::from_str::<Perms>(&json).unwrap();
+        assert_eq!(read.iter().collect::<Vec<_>>(), perms.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn needed_power_is_optional_in_the_registry() {
+        let perms = serde_json::from_str::<Perms>(r#"{"channel_join":{"granted":50}}"#).unwrap();
+        assert_eq!(perms.get(CHANNEL_JOIN), PermValue { granted: 50, needed: 0 });
+    }
+
+    #[test]
+    fn legacy_perms_are_migrated() {
+        let legacy = r#"{
+            "server_group_assign": 1,
+            "server_group_unassign": 2,
+            "channel_see": 3,
+            "channel_join": 4,
+            "channel_modify": 5,
+            "channel_talk": 6,
+            "channel_assign_talk": 7,
+            "channel_delete": 8,
+            "can_send": true,
+            "channel_create": {
+                "power": 9,
+                "set_desc": true,
+                "set_password": false,
+                "resort_channel": true
+            }
+        }"#;
+        let perms = serde_json::from_str::<Perms>(legacy).unwrap();
+        assert_eq!(perms.granted(SERVER_GROUP_ASSIGN), 1);
+        assert_eq!(perms.granted(SERVER_GROUP_UNASSIGN), 2);
+        assert_eq!(perms.granted(CHANNEL_SEE), 3);
+        assert_eq!(perms.granted(CHANNEL_JOIN), 4);
+        assert_eq!(perms.granted(CHANNEL_MODIFY), 5);
+        assert_eq!(perms.granted(CHANNEL_TALK), 6);
+        assert_eq!(perms.granted(CHANNEL_ASSIGN_TALK), 7);
+        assert_eq!(perms.granted(CHANNEL_DELETE), 8);
+        assert!(perms.has(SEND));
+        assert_eq!(perms.granted(CHANNEL_CREATE), 9);
+        assert!(perms.has(CHANNEL_CREATE_SET_DESC));
+        assert!(!perms.has(CHANNEL_CREATE_SET_PASSWORD));
+        assert!(perms.has(CHANNEL_CREATE_RESORT));
+        // fields that were added later default to not being granted
+        assert!(!perms.has(POKE));
+        assert!(!perms.has(CHANNEL_CREATE_PERMANENT));
+        assert!(!perms.has(CHANNEL_CREATE_SEMI_PERMANENT));
+        assert!(!perms.has(CHANNEL_CREATE_TEMPORARY));
+        // perms that aren't granted aren't stored
+        assert!(!perms.contains(CHANNEL_CREATE_SET_PASSWORD));
+        // migrated perms are stored in the registry format
+        let json = serde_json::to_string(&perms).unwrap();
+        assert!(!json.contains("can_send"));
+        assert_eq!(serde_json::from_str::<Perms>(&json).unwrap().granted(SEND), 1);
+    }
+
+    #[test]
+    fn groups_are_applied_by_priority() {
+        let low = server_group(1, false, perms(&[(CHANNEL_JOIN, 50, 0), (SEND, 1, 0)]));
+        let negate = server_group(2, true, perms(&[(SEND, 1, 0)]));
+        let high = server_group(3, false, perms(&[(CHANNEL_JOIN, 20, 30)]));
+        let groups = [high.uuid, negate.uuid, low.uuid];
+        let server_groups = [low, negate, high].into_iter().map(|group| (group.uuid, group)).collect::<HashMap<_, _>>();
+
+        let resolved = resolve(&server_groups, &groups, None, &Perms::default());
+        // grants only ever raise the power
+        assert_eq!(resolved.get(CHANNEL_JOIN), PermValue { granted: 50, needed: 30 });
+        assert!(!resolved.has(SEND));
+    }
+
+    #[test]
+    fn negated_groups_only_revoke_lower_groups() {
+        let negate = server_group(1, true, perms(&[(SEND, 1, 0)]));
+        let grant = server_group(2, false, perms(&[(SEND, 1, 0)]));
+        let groups = [negate.uuid, grant.uuid];
+        let server_groups = [negate, grant].into_iter().map(|group| (group.uuid, group)).collect::<HashMap<_, _>>();
+
+        assert!(resolve(&server_groups, &groups, None, &Perms::default()).has(SEND));
+    }
+
+    #[test]
+    fn deleted_groups_dont_grant_anything() {
+        let resolved = resolve(&HashMap::new(), &[Uuid::new_v4()], None, &Perms::default());
+        assert_eq!(resolved.iter().count(), 0);
+    }
+
+    #[test]
+    fn overrides_win_over_all_groups() {
+        let group = server_group(1, false, perms(&[(CHANNEL_JOIN, 50, 0), (CHANNEL_MODIFY, 10, 0)]));
+        let groups = [group.uuid];
+        let server_groups = HashMap::from([(group.uuid, group)]);
+        let channel_group = ChannelGroup {
+            uuid: Uuid::new_v4(),
+            name: String::new(),
+            perms: perms(&[(CHANNEL_JOIN, 75, 0), (CHANNEL_TALK, 20, 0)]),
+        };
+        let overrides = perms(&[(CHANNEL_JOIN, 0, 0), (SEND, 1, 0)]);
+
+        let resolved = resolve(&server_groups, &groups, Some(&channel_group), &overrides);
+        // overrides that neither grant nor need anything deny the perm
+        assert_eq!(resolved.granted(CHANNEL_JOIN), 0);
+        assert_eq!(resolved.granted(CHANNEL_MODIFY), 10);
+        assert_eq!(resolved.granted(CHANNEL_TALK), 20);
+        assert!(resolved.has(SEND));
+
+        // without overrides the channel group grants on top of the server groups
+        let resolved = resolve(&server_groups, &groups, Some(&channel_group), &Perms::default());
+        assert_eq!(resolved.granted(CHANNEL_JOIN), 75);
+        // and the channel group doesn't apply outside of its channel
+        let resolved = resolve(&server_groups, &groups, None, &Perms::default());
+        assert_eq!(resolved.granted(CHANNEL_JOIN), 50);
+        assert!(!resolved.has(CHANNEL_TALK));
+    }
+}
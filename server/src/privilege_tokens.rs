@@ -0,0 +1,64 @@
+// privilege tokens let operators hand out groups without knowing the uuid of the user who should
+// get them, whoever redeems a token first gets the server group or channel group it grants
+
+use std::time::Duration;
+use crate::channel_tree::{ErrorUnknownChannel, ErrorUnknownChannelGroup};
+use crate::server_groups::ErrorUnknownServerGroup;
+use crate::user_db::{PrivilegeToken, TokenGrant};
+use crate::utils::current_time_millis;
+use crate::{Server, UserUuid};
+
+// the number of random bytes a token consists of
+const TOKEN_LEN: usize = 16;
+
+impl Server {
+
+    /// creates a token that grants `grant` `uses` times or any number of times if `uses` is none,
+    /// the token can't be used anymore after `valid_for` has passed
+    pub async fn create_privilege_token(&self, grant: TokenGrant, uses: Option<u64>, valid_for: Option<Duration>) -> anyhow::Result<String> {
+        match &grant {
+            TokenGrant::ServerGroup(group) => {
+                if !self.server_groups.read().await.contains_key(group) {
+                    return Err(anyhow::Error::from(ErrorUnknownServerGroup(*group)));
+                }
+            }
+            TokenGrant::ChannelGroup { channel, group } => {
+                if !self.channels.read().await.contains_key(channel) {
+                    return Err(anyhow::Error::from(ErrorUnknownChannel(*channel)));
+                }
+                if !self.channel_groups.read().await.contains_key(group) {
+                    return Err(anyhow::Error::from(ErrorUnknownChannelGroup(*group)));
+                }
+            }
+        }
+        // tokens are secrets, so they are generated by a cryptographically secure generator
+        let mut raw = [0; TOKEN_LEN];
+        openssl::rand::rand_bytes(&mut raw)?;
+        let token = PrivilegeToken {
+            token: raw.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            grant,
+            uses: uses.filter(|uses| *uses > 0),
+            expiry: valid_for.map(|valid_for| current_time_millis() + valid_for),
+        };
+        self.user_db.insert_token(&token)?;
+        Ok(token.token)
+    }
+
+    /// grants `user` the group `token` grants, returns false if the token is invalid
+    pub async fn redeem_privilege_token(&self, user: &UserUuid, token: &str) -> anyhow::Result<bool> {
+        let Some(redeemed) = self.user_db.redeem_token(token)? else {
+            return Ok(false);
+        };
+        let result = match &redeemed.grant {
+            TokenGrant::ServerGroup(group) => self.assign_server_group(user, group).await,
+            TokenGrant::ChannelGroup { channel, group } => self.assign_channel_group(channel, user, Some(*group)).await,
+        };
+        // the use must not get lost if the group couldn't be granted (e.g. because it was deleted in the meantime)
+        if let Err(err) = result {
+            self.user_db.restore_token_use(&redeemed)?;
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+}
@@ -12,7 +12,106 @@ use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
-pub const PROTOCOL_VERSION: u64 = 1;
+/// the newest protocol version we speak, it gets bumped whenever packets change in an incompatible way
+pub const PROTOCOL_VERSION: u64 = 3; // 3 added info queries
+/// the oldest protocol version we still speak, the client and the server agree on the
+/// highest version both of them support so they don't have to be upgraded at the same time
+pub const MIN_PROTOCOL_VERSION: u64 = 2;
+/// the optional features we support, additions to the protocol that don't break older
+/// peers get a feature flag instead of a new protocol version
+pub const FEATURES: Features = Features::NONE;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VersionRange {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl VersionRange {
+    pub const SUPPORTED: Self = Self {
+        min: MIN_PROTOCOL_VERSION,
+        max: PROTOCOL_VERSION,
+    };
+
+    /// returns the highest version contained in both ranges or none if they don't overlap
+    pub fn negotiate(&self, other: &VersionRange) -> Option<u64> {
+        let version = self.max.min(other.max);
+        if version >= self.min.max(other.min) {
+            Some(version)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for VersionRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+impl RWBytes for VersionRange {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let min = src.get_u64_le();
+        let max = src.get_u64_le();
+        Ok(Self { min, max })
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u64_le(self.min);
+        dst.put_u64_le(self.max);
+        Ok(())
+    }
+}
+
+/// a set of optional protocol features, unknown flags are simply ignored
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Features(u64);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+
+    #[inline]
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    #[inline]
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// returns the features supported by both sides, only these may be used
+    #[inline]
+    pub const fn intersect(self, other: Features) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl RWBytes for Features {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        Ok(Self(src.get_u64_le()))
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        dst.put_u64_le(self.0);
+        Ok(())
+    }
+}
 
 #[derive(Copy, Clone, Hash, Ord, PartialOrd, Eq, PartialEq, Debug)]
 #[repr(transparent)]
@@ -612,3 +711,37 @@ impl ReadSlice for Bytes {
         slice
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(min: u64, max: u64) -> VersionRange {
+        VersionRange { min, max }
+    }
+
+    #[test]
+    fn the_highest_common_version_is_negotiated() {
+        assert_eq!(range(2, 3).negotiate(&range(2, 3)), Some(3));
+        assert_eq!(range(2, 3).negotiate(&range(1, 2)), Some(2));
+        assert_eq!(range(1, 5).negotiate(&range(3, 4)), Some(4));
+        assert_eq!(range(3, 3).negotiate(&range(1, 7)), Some(3));
+        // negotiating is symmetric
+        assert_eq!(range(1, 2).negotiate(&range(2, 3)), Some(2));
+    }
+
+    #[test]
+    fn disjoint_ranges_dont_negotiate() {
+        assert_eq!(range(1, 2).negotiate(&range(3, 4)), None);
+        assert_eq!(range(3, 4).negotiate(&range(1, 2)), None);
+    }
+
+    #[test]
+    fn version_ranges_survive_a_round_trip() {
+        let mut buf = BytesMut::new();
+        VersionRange::SUPPORTED.write(&mut buf).unwrap();
+        assert_eq!(VersionRange::read(&mut buf.freeze()).unwrap(), VersionRange::SUPPORTED);
+        assert_eq!(range(2, 3).to_string(), "2-3");
+        assert_eq!(range(3, 3).to_string(), "3");
+    }
+}
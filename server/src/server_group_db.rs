@@ -1,24 +1,30 @@
-use crate::packet::PermsSnapshot;
+use crate::perms::Perms;
 use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
+use std::sync::Mutex;
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize)]
 pub struct ServerGroupEntry<'a> {
     pub uuid: u128,
     pub name: Cow<'a, str>,
-    pub perms: PermsSnapshot,
+    pub perms: Perms,
+    #[serde(default)]
+    pub priority: u64,
+    #[serde(default)]
+    pub negate: bool, // negating groups revoke the perms they contain instead of granting them
 }
 
 pub struct ServerGroupDb {
     pub(crate) path: String,
+    lock: Mutex<()>, // held during every read-modify-write so concurrent changes don't overwrite each other
 }
 
 impl ServerGroupDb {
     pub fn new(path: String) -> Self {
-        Self { path }
+        Self { path, lock: Mutex::new(()) }
     }
 
     pub fn read_or_create<'db, F: FnOnce() -> anyhow::Result<Vec<ServerGroupEntry<'db>>>>(
@@ -43,8 +49,63 @@ impl ServerGroupDb {
     pub fn write(&self, server_groups: &Vec<ServerGroupEntry>) -> anyhow::Result<()> {
         let val = serde_json::to_string(server_groups)?;
         let mut file = File::create(self.path.clone())?;
-        file.write(val.as_bytes())?;
+        file.write_all(val.as_bytes())?;
 
         Ok(())
     }
+
+    /// makes sure the last write reached the disk
+    pub fn flush(&self) -> anyhow::Result<()> {
+        // windows only allows syncing files that were opened for writing
+        OpenOptions::new().write(true).open(self.path.clone())?.sync_all()?;
+        Ok(())
+    }
+
+    fn read(&self) -> anyhow::Result<Vec<ServerGroupEntry<'static>>> {
+        let mut content = String::new();
+        File::open(self.path.clone())?.read_to_string(&mut content)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn insert(&self, server_group: ServerGroupEntry) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut server_groups = self.read()?;
+        server_groups.push(server_group);
+        self.write(&server_groups)
+    }
+
+    pub fn rename(&self, uuid: &Uuid, name: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut server_groups = self.read()?;
+        if let Some(server_group) = server_groups.iter_mut().find(|server_group| server_group.uuid == uuid.as_u128()) {
+            server_group.name = Cow::Owned(name.to_string());
+        }
+        self.write(&server_groups)
+    }
+
+    pub fn set_perms(&self, uuid: &Uuid, perms: Perms) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut server_groups = self.read()?;
+        if let Some(server_group) = server_groups.iter_mut().find(|server_group| server_group.uuid == uuid.as_u128()) {
+            server_group.perms = perms;
+        }
+        self.write(&server_groups)
+    }
+
+    pub fn set_priority(&self, uuid: &Uuid, priority: u64, negate: bool) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut server_groups = self.read()?;
+        if let Some(server_group) = server_groups.iter_mut().find(|server_group| server_group.uuid == uuid.as_u128()) {
+            server_group.priority = priority;
+            server_group.negate = negate;
+        }
+        self.write(&server_groups)
+    }
+
+    pub fn remove(&self, uuid: &Uuid) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut server_groups = self.read()?;
+        server_groups.retain(|server_group| server_group.uuid != uuid.as_u128());
+        self.write(&server_groups)
+    }
 }
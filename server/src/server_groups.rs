@@ -0,0 +1,448 @@
+// server groups grant perms on the whole server, the active perms of a user combine
+// the perms of all groups they are a member of with the user's own overrides
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::packet::{ClientUpdateServerGroups, ServerGroup, ServerGroupEdit, ServerGroupUpdate, ServerPacket};
+use crate::perms;
+use crate::perms::{PermValue, Perms};
+use crate::server_group_db::ServerGroupEntry;
+use crate::utils::parse_bool;
+use crate::{ADMIN_GROUP_UUID, calculate_active_perms, DEFAULT_GROUP_UUID, Server, User, UserUuid};
+
+impl Server {
+
+    /// recalculates the active perms of all online members of `group` or of all online users if `group` is none
+    async fn refresh_active_perms(&self, group: Option<&Uuid>) -> anyhow::Result<()> {
+        let affected = self.online_users.iter()
+            .filter(|user| group.map_or(true, |group| user.groups.read().unwrap().contains(group)))
+            .map(|user| user.value().clone())
+            .collect::<Vec<_>>();
+        for user in affected {
+            self.update_active_perms(&user).await?;
+        }
+        Ok(())
+    }
+
+    /// recalculates the active perms of `user` and informs them about their new perms
+    async fn update_active_perms(&self, user: &Arc<User>) -> anyhow::Result<()> {
+        let active_perms = {
+            let server_groups = self.server_groups.read().await;
+            let groups = user.groups.read().unwrap().clone();
+            calculate_active_perms(&server_groups, &user.perms.load(), &groups)
+        };
+        user.active_perms.store(Arc::new(active_perms.clone()));
+        let packet = ServerPacket::ActivePerms(active_perms).encode()?;
+        user.connection.send_reliable(&packet).await?;
+        // the user's see power may have changed
+        self.refresh_visibility(user).await
+    }
+
+    /// updates the profile other users see when they join after a change of `user`'s groups
+    fn update_proto_profile(&self, user: &Arc<User>) {
+        let channel = user.channel.load();
+        let groups = user.groups.read().unwrap().clone();
+        if let Some(profile) = channel.proto_clients.write().unwrap().iter_mut().find(|profile| profile.uuid == user.uuid) {
+            profile.server_groups = groups;
+        }
+    }
+
+    async fn get_server_group(&self, group: &Uuid) -> anyhow::Result<Arc<ServerGroup>> {
+        self.server_groups.read().await.get(group).cloned().ok_or(anyhow::Error::from(ErrorUnknownServerGroup(*group)))
+    }
+
+    /// applies `edit` if `may_edit` allows it, `may_edit` receives the edited group or none if a group gets created.
+    /// the checks and the change happen under the same write lock so concurrent edits can't invalidate the checks.
+    /// returns the uuid of the edited group
+    pub async fn edit_server_group<F: FnOnce(Option<&ServerGroup>) -> bool>(&self, edit: ServerGroupEdit, may_edit: F) -> anyhow::Result<Uuid> {
+        let mut server_groups = self.server_groups.write().await;
+        let prev = match &edit {
+            ServerGroupEdit::Create { .. } => None,
+            ServerGroupEdit::Rename { group, .. } | ServerGroupEdit::Perms { group, .. } | ServerGroupEdit::Delete(group) => {
+                Some(server_groups.get(group).cloned().ok_or(anyhow::Error::from(ErrorUnknownServerGroup(*group)))?)
+            }
+        };
+        if !may_edit(prev.as_deref()) {
+            return Err(anyhow::Error::from(ErrorServerGroupNoPermission));
+        }
+        match &edit {
+            ServerGroupEdit::Create { name, .. } | ServerGroupEdit::Rename { name, .. } => {
+                if name.trim().is_empty() {
+                    return Err(anyhow::Error::from(ErrorEmptyServerGroupName));
+                }
+                if server_groups.values().any(|group| group.name.eq_ignore_ascii_case(name)) {
+                    return Err(anyhow::Error::from(ErrorServerGroupNameTaken(name.to_string())));
+                }
+            }
+            ServerGroupEdit::Delete(group) => {
+                if group == &ADMIN_GROUP_UUID || group == &DEFAULT_GROUP_UUID {
+                    return Err(anyhow::Error::from(ErrorProtectedServerGroup(*group)));
+                }
+            }
+            ServerGroupEdit::Perms { .. } => {}
+        }
+        let (uuid, update) = match edit {
+            ServerGroupEdit::Create { name, perms } => {
+                let mut uuid = Uuid::new_v4();
+                while server_groups.contains_key(&uuid) {
+                    uuid = Uuid::new_v4();
+                }
+                self.server_group_db.insert(ServerGroupEntry {
+                    uuid: uuid.as_u128(),
+                    name: Cow::Borrowed(name.as_str()),
+                    perms: perms.clone(),
+                    priority: 0,
+                    negate: false,
+                })?;
+                let group = Arc::new(ServerGroup {
+                    uuid,
+                    name,
+                    priority: 0,
+                    negate: false,
+                    perms,
+                });
+                server_groups.insert(uuid, group.clone());
+                (uuid, ServerGroupUpdate::Create(group))
+            }
+            ServerGroupEdit::Rename { group, name } => {
+                let prev = prev.unwrap();
+                self.server_group_db.rename(&group, &name)?;
+                server_groups.insert(group, Arc::new(ServerGroup {
+                    uuid: group,
+                    name: name.clone(),
+                    priority: prev.priority,
+                    negate: prev.negate,
+                    perms: prev.perms.clone(),
+                }));
+                (group, ServerGroupUpdate::Rename { group, name })
+            }
+            ServerGroupEdit::Perms { group, perms } => {
+                let prev = prev.unwrap();
+                self.server_group_db.set_perms(&group, perms.clone())?;
+                server_groups.insert(group, Arc::new(ServerGroup {
+                    uuid: group,
+                    name: prev.name.clone(),
+                    priority: prev.priority,
+                    negate: prev.negate,
+                    perms: perms.clone(),
+                }));
+                (group, ServerGroupUpdate::Perms { group, perms })
+            }
+            ServerGroupEdit::Delete(group) => {
+                self.server_group_db.remove(&group)?;
+                server_groups.remove(&group);
+                (group, ServerGroupUpdate::Delete(group))
+            }
+        };
+        // recalculating the active perms needs the groups, so the lock has to be released first
+        drop(server_groups);
+        match &update {
+            ServerGroupUpdate::Create(_) | ServerGroupUpdate::Rename { .. } => {}
+            ServerGroupUpdate::Perms { .. } => self.refresh_active_perms(Some(&uuid)).await?,
+            ServerGroupUpdate::Delete(_) => {
+                self.user_db.remove_group(&uuid)?;
+                for user in self.online_users.iter() {
+                    let removed = {
+                        let mut groups = user.groups.write().unwrap();
+                        let len = groups.len();
+                        groups.retain(|member| member != &uuid);
+                        len != groups.len()
+                    };
+                    if removed {
+                        self.update_proto_profile(user.value());
+                    }
+                }
+                self.refresh_active_perms(None).await?;
+            }
+            ServerGroupUpdate::Priority { .. } => unreachable!(),
+        }
+
+        // clients remove deleted groups from all of their members on their own
+        let packet = ServerPacket::ServerGroupUpdate(update).encode()?;
+        self.broadcast(&packet).await;
+        Ok(uuid)
+    }
+
+    pub async fn create_server_group(&self, name: String, perms: Perms) -> anyhow::Result<Uuid> {
+        self.edit_server_group(ServerGroupEdit::Create { name, perms }, |_| true).await
+    }
+
+    pub async fn rename_server_group(&self, group: &Uuid, name: String) -> anyhow::Result<()> {
+        self.edit_server_group(ServerGroupEdit::Rename { group: *group, name }, |_| true).await?;
+        Ok(())
+    }
+
+    pub async fn set_server_group_perms(&self, group: &Uuid, perms: Perms) -> anyhow::Result<()> {
+        self.edit_server_group(ServerGroupEdit::Perms { group: *group, perms }, |_| true).await?;
+        Ok(())
+    }
+
+    /// changes the order in which `group` gets applied and whether it revokes its perms instead of granting them
+    pub async fn set_server_group_priority(&self, group: &Uuid, priority: u64, negate: bool) -> anyhow::Result<()> {
+        {
+            let mut server_groups = self.server_groups.write().await;
+            let prev = server_groups.get(group).cloned().ok_or(anyhow::Error::from(ErrorUnknownServerGroup(*group)))?;
+            self.server_group_db.set_priority(group, priority, negate)?;
+            server_groups.insert(*group, Arc::new(ServerGroup {
+                uuid: *group,
+                name: prev.name.clone(),
+                priority,
+                negate,
+                perms: prev.perms.clone(),
+            }));
+        }
+        self.refresh_active_perms(Some(group)).await?;
+
+        let packet = ServerPacket::ServerGroupUpdate(ServerGroupUpdate::Priority { group: *group, priority, negate }).encode()?;
+        self.broadcast(&packet).await;
+        Ok(())
+    }
+
+    /// deletes `group` and removes it from all of its members
+    pub async fn delete_server_group(&self, group: &Uuid) -> anyhow::Result<()> {
+        self.edit_server_group(ServerGroupEdit::Delete(*group), |_| true).await?;
+        Ok(())
+    }
+
+    pub async fn assign_server_group(&self, user: &UserUuid, group: &Uuid) -> anyhow::Result<()> {
+        self.update_server_group_membership(user, ClientUpdateServerGroups::Add(*group)).await
+    }
+
+    pub async fn unassign_server_group(&self, user: &UserUuid, group: &Uuid) -> anyhow::Result<()> {
+        self.update_server_group_membership(user, ClientUpdateServerGroups::Remove(*group)).await
+    }
+
+    async fn update_server_group_membership(&self, user: &UserUuid, update: ClientUpdateServerGroups) -> anyhow::Result<()> {
+        let group = match &update {
+            ClientUpdateServerGroups::Add(group) => *group,
+            ClientUpdateServerGroups::Remove(group) => *group,
+        };
+        self.get_server_group(&group).await?;
+        let mut db_user = self.user_db.get(user)?.ok_or(anyhow::Error::from(ErrorUnknownUser(*user)))?;
+        let add = matches!(update, ClientUpdateServerGroups::Add(_));
+        if db_user.groups.contains(&group) == add {
+            // nothing changes
+            return Ok(());
+        }
+        if add {
+            db_user.groups.push(group);
+        } else {
+            db_user.groups.retain(|member| member != &group);
+        }
+        self.user_db.insert(db_user.clone())?;
+        if let Some(online) = self.online_users.get(user).map(|user| user.value().clone()) {
+            *online.groups.write().unwrap() = db_user.groups;
+            self.update_proto_profile(&online);
+            self.update_active_perms(&online).await?;
+        }
+
+        let packet = ServerPacket::ClientUpdateServerGroups { client: *user, update }.encode()?;
+        self.broadcast(&packet).await;
+        Ok(())
+    }
+
+    /// overrides the perm `key` of `user` with `value` regardless of the user's groups or removes the override if `value` is none,
+    /// an override that grants no power denies the perm
+    pub async fn set_perm_override(&self, user: &UserUuid, key: &str, value: Option<PermValue>) -> anyhow::Result<()> {
+        let key = key.to_lowercase();
+        if !perms::is_known(&key) {
+            return Err(anyhow::Error::from(ErrorUnknownPerm(key)));
+        }
+        let mut db_user = self.user_db.get(user)?.ok_or(anyhow::Error::from(ErrorUnknownUser(*user)))?;
+        match value {
+            Some(value) => db_user.perms.insert(&key, value),
+            None => {
+                db_user.perms.remove(&key);
+            }
+        }
+        self.user_db.insert(db_user.clone())?;
+        if let Some(online) = self.online_users.get(user).map(|user| user.value().clone()) {
+            online.perms.store(Arc::new(db_user.perms));
+            self.update_active_perms(&online).await?;
+        }
+        Ok(())
+    }
+
+}
+
+/// sets the perm `key` to the `granted` power and optionally to the `needed` power, only keys in the registry can be set
+pub fn set_perm(perms: &mut Perms, key: &str, granted: &str, needed: Option<&str>) -> anyhow::Result<()> {
+    let key = key.to_lowercase();
+    if !perms::is_known(&key) {
+        return Err(anyhow::Error::from(ErrorUnknownPerm(key)));
+    }
+    let granted = parse_power(granted)?;
+    let needed = match needed {
+        Some(needed) => parse_power(needed)?,
+        None => perms.needed(&key),
+    };
+    perms.set(&key, PermValue { granted, needed });
+    Ok(())
+}
+
+/// parses a power, flags may also be given as true or false
+pub fn parse_power(value: &str) -> anyhow::Result<u64> {
+    match value.parse::<u64>() {
+        Ok(power) => Ok(power),
+        Err(_) => parse_bool(value).map(|flag| flag as u64).ok_or(anyhow::Error::from(ErrorInvalidPermValue(value.to_string()))),
+    }
+}
+
+pub fn parse_flag(value: &str) -> anyhow::Result<bool> {
+    parse_bool(value).ok_or(anyhow::Error::from(ErrorInvalidFlag(value.to_string())))
+}
+
+pub struct ErrorUnknownServerGroup(pub(crate) Uuid);
+
+impl Debug for ErrorUnknownServerGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there is no server group with the uuid ")?;
+        f.write_str(self.0.to_string().as_str())
+    }
+}
+
+impl Display for ErrorUnknownServerGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownServerGroup {}
+
+pub struct ErrorServerGroupNameTaken(String);
+
+impl Debug for ErrorServerGroupNameTaken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there already is a server group named ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorServerGroupNameTaken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorServerGroupNameTaken {}
+
+pub struct ErrorEmptyServerGroupName;
+
+impl Debug for ErrorEmptyServerGroupName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("server group names may not be empty")
+    }
+}
+
+impl Display for ErrorEmptyServerGroupName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorEmptyServerGroupName {}
+
+pub struct ErrorServerGroupNoPermission;
+
+impl Debug for ErrorServerGroupNoPermission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the server group may not be edited")
+    }
+}
+
+impl Display for ErrorServerGroupNoPermission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorServerGroupNoPermission {}
+
+pub struct ErrorProtectedServerGroup(Uuid);
+
+impl Debug for ErrorProtectedServerGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the server group ")?;
+        f.write_str(self.0.to_string().as_str())?;
+        f.write_str(" is built in and may not be deleted")
+    }
+}
+
+impl Display for ErrorProtectedServerGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorProtectedServerGroup {}
+
+pub struct ErrorUnknownUser(UserUuid);
+
+impl Debug for ErrorUnknownUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there is no user with the uuid ")?;
+        f.write_str(format!("{:?}", self.0).as_str())
+    }
+}
+
+impl Display for ErrorUnknownUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownUser {}
+
+pub struct ErrorUnknownPerm(String);
+
+impl Debug for ErrorUnknownPerm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("there is no perm named ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorUnknownPerm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorUnknownPerm {}
+
+pub struct ErrorInvalidPermValue(String);
+
+impl Debug for ErrorInvalidPermValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("expected a power, true or false but got ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorInvalidPermValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorInvalidPermValue {}
+
+pub struct ErrorInvalidFlag(String);
+
+impl Debug for ErrorInvalidFlag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("expected true or false but got ")?;
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl Display for ErrorInvalidFlag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorInvalidFlag {}
@@ -0,0 +1,89 @@
+// an orderly shutdown tells every user why they get disconnected, waits until everything that was sent to
+// them got delivered and flushes all databases before the runtime and with it the process exits
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use futures::future::join_all;
+use quinn::VarInt;
+use crate::packet::{DisconnectReason, ServerPacket};
+use crate::Server;
+
+/// the exit code of the process after a restart was requested, whatever supervises the server
+/// (e.g. a service manager or a start script) is expected to start it again when it sees this code
+pub const RESTART_EXIT_CODE: i32 = 3;
+pub const ERROR_EXIT_CODE: i32 = 1;
+// the maximum time we wait for clients to acknowledge the data that was sent to them
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Server {
+
+    /// shuts the server down after `delay` has passed, users get notified immediately if there is a delay.
+    /// if `restart` is true clients are told to reconnect once the server is back up
+    pub async fn shutdown(&self, delay: Duration, message: Option<String>, restart: bool) -> anyhow::Result<()> {
+        let scheduled = ServerPacket::ShutdownScheduled { delay, message: message.clone(), restart }.encode()?;
+        let disconnect = ServerPacket::ForceDisconnect { reason: DisconnectReason::ServerShutdown { message, restart } }.encode()?;
+        if self.shutting_down.swap(true, Ordering::AcqRel) {
+            return Err(anyhow::Error::from(ErrorShutdownScheduled));
+        }
+        if !delay.is_zero() {
+            self.broadcast(&scheduled).await;
+            tokio::time::sleep(delay).await;
+        }
+
+        // refuse new connections, the established ones get closed below
+        self.network_server.endpoint.set_server_config(None);
+        let connections = self.online_users.iter().map(|user| user.connection.clone()).collect::<Vec<_>>();
+        let disconnect = &disconnect;
+        let closing = join_all(connections.into_iter().map(|connection| async move {
+            connection.send_reliable(disconnect).await?;
+            // this waits until the client acknowledged everything we sent on the stream
+            connection.close().await
+        }));
+        match tokio::time::timeout(DRAIN_TIMEOUT, closing).await {
+            Ok(results) => {
+                for err in results.into_iter().filter_map(|result| result.err()) {
+                    self.println(format!("An error occurred while disconnecting a client: {}", err).as_str());
+                }
+            }
+            Err(_) => self.println("Not all clients acknowledged their disconnect in time"),
+        }
+        let _ = tokio::time::timeout(DRAIN_TIMEOUT, self.network_server.endpoint.wait_idle()).await;
+
+        let result = self.flush_dbs();
+        self.exit_code.store(match (&result, restart) {
+            (Err(_), _) => ERROR_EXIT_CODE,
+            (Ok(_), true) => RESTART_EXIT_CODE,
+            (Ok(_), false) => 0,
+        }, Ordering::Release);
+        // this ends the loop accepting connections and with it the runtime, so it has to happen last
+        self.network_server.endpoint.close(VarInt::from_u32(0), b"shutdown");
+        result
+    }
+
+    fn flush_dbs(&self) -> anyhow::Result<()> {
+        self.user_db.flush()?;
+        self.mailbox_db.flush()?;
+        self.channel_db.flush()?;
+        self.server_group_db.flush()?;
+        self.channel_group_db.flush()
+    }
+
+}
+
+pub struct ErrorShutdownScheduled;
+
+impl Debug for ErrorShutdownScheduled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("a shutdown is already scheduled")
+    }
+}
+
+impl Display for ErrorShutdownScheduled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Error for ErrorShutdownScheduled {}
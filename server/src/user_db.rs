@@ -1,24 +1,36 @@
 use crate::{RWBytes, UserUuid};
 use bytemuck_derive::Zeroable;
-use bytes::{Bytes, BytesMut};
-use sled::{Db, IVec};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use sled::{Db, IVec, Tree};
 use std::borrow::Cow;
 use std::io::Read;
+use std::time::Duration;
 use ruint::aliases::U256;
 use uuid::Uuid;
-use crate::packet::PermsSnapshot;
+use crate::protocol::ErrorEnumVariantNotFound;
+use crate::perms::Perms;
+use crate::utils::current_time_millis;
 
 pub struct UserDb {
     db: Db,
+    tokens: Tree, // privilege tokens which grant a group to whoever redeems them
 }
 
 impl UserDb {
     pub fn new(path: String) -> anyhow::Result<Self> {
+        let db = sled::open(path)?;
         Ok(Self {
-            db: sled::open(path)?,
+            tokens: db.open_tree("privilege_tokens")?,
+            db,
         })
     }
 
+    pub fn flush(&self) -> anyhow::Result<()> {
+        // this flushes all trees as they share their buffers
+        self.db.flush()?;
+        Ok(())
+    }
+
     pub fn get(&self, uuid: &UserUuid) -> anyhow::Result<Option<DbUser>> {
         Ok(match self.db.get(uuid)? {
             None => None,
@@ -37,6 +49,169 @@ impl UserDb {
         self.db.insert(user.uuid.clone(), user.to_bytes()?)?;
         Ok(())
     }
+
+    /// removes `group` from the groups of every user, this is used when a group gets deleted
+    pub fn remove_group(&self, group: &Uuid) -> anyhow::Result<()> {
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            if value.is_empty() {
+                continue;
+            }
+            let mut user = DbUser::from_bytes(value)?;
+            if user.groups.contains(group) {
+                user.groups.retain(|member| member != group);
+                self.db.insert(key, user.to_bytes()?)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert_token(&self, token: &PrivilegeToken) -> anyhow::Result<()> {
+        self.tokens.insert(token.token.as_bytes(), token.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn tokens(&self) -> anyhow::Result<Vec<PrivilegeToken>> {
+        let now = current_time_millis();
+        let mut result = vec![];
+        for entry in self.tokens.iter() {
+            let (key, value) = entry?;
+            let token = PrivilegeToken::from_bytes(value)?;
+            if token.is_expired(now) {
+                self.tokens.remove(key)?;
+                continue;
+            }
+            result.push(token);
+        }
+        Ok(result)
+    }
+
+    pub fn remove_token(&self, token: &str) -> anyhow::Result<bool> {
+        Ok(self.tokens.remove(token.as_bytes())?.is_some())
+    }
+
+    /// uses up one use of `token` and returns the token as it was before, none if the token doesn't exist, expired or was used up
+    pub fn redeem_token(&self, token: &str) -> anyhow::Result<Option<PrivilegeToken>> {
+        let now = current_time_millis();
+        loop {
+            let Some(raw) = self.tokens.get(token.as_bytes())? else {
+                return Ok(None);
+            };
+            let stored = PrivilegeToken::from_bytes(raw.clone())?;
+            if stored.is_expired(now) {
+                self.tokens.remove(token.as_bytes())?;
+                return Ok(None);
+            }
+            let new = match stored.uses {
+                Some(1) => None,
+                Some(uses) => {
+                    let mut updated = stored.clone();
+                    updated.uses = Some(uses - 1);
+                    Some(updated.to_bytes()?)
+                }
+                None => Some(raw.clone()),
+            };
+            // another user may have redeemed the token in the meantime
+            if self.tokens.compare_and_swap(token.as_bytes(), Some(raw), new)?.is_ok() {
+                return Ok(Some(stored));
+            }
+        }
+    }
+
+    /// gives back the use `redeem_token` took from `redeemed`, this is used if the grant couldn't be applied
+    pub fn restore_token_use(&self, redeemed: &PrivilegeToken) -> anyhow::Result<()> {
+        if redeemed.uses.is_none() {
+            // redeeming tokens with unlimited uses doesn't use anything up
+            return Ok(());
+        }
+        let key = redeemed.token.as_bytes();
+        loop {
+            let current = self.tokens.get(key)?;
+            let mut restored = match current.clone() {
+                Some(raw) => PrivilegeToken::from_bytes(raw)?,
+                // the last use was taken, so the token was removed
+                None => PrivilegeToken { uses: Some(0), ..redeemed.clone() },
+            };
+            restored.uses = restored.uses.map(|uses| uses + 1);
+            if self.tokens.compare_and_swap(key, current, Some(restored.to_bytes()?))?.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PrivilegeToken {
+    pub token: String,
+    pub grant: TokenGrant,
+    pub uses: Option<u64>, // the number of uses left, none if the token can be used any number of times
+    pub expiry: Option<Duration>, // the time in millis after which the token can't be used anymore
+}
+
+impl PrivilegeToken {
+    fn is_expired(&self, now: Duration) -> bool {
+        self.expiry.map_or(false, |expiry| expiry < now)
+    }
+
+    fn to_bytes(self) -> anyhow::Result<IVec> {
+        let mut buf = BytesMut::new();
+        self.token.write(&mut buf)?;
+        self.grant.write(&mut buf)?;
+        self.uses.write(&mut buf)?;
+        self.expiry.write(&mut buf)?;
+        Ok(IVec::from(buf.to_vec()))
+    }
+
+    fn from_bytes(bytes: IVec) -> anyhow::Result<Self> {
+        let mut buf = Bytes::from(bytes.to_vec());
+        Ok(Self {
+            token: String::read(&mut buf)?,
+            grant: TokenGrant::read(&mut buf)?,
+            uses: Option::<u64>::read(&mut buf)?,
+            expiry: Option::<Duration>::read(&mut buf)?,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum TokenGrant {
+    ServerGroup(Uuid),
+    ChannelGroup {
+        channel: Uuid,
+        group: Uuid,
+    },
+}
+
+impl RWBytes for TokenGrant {
+    type Ty = Self;
+
+    fn read(src: &mut Bytes) -> anyhow::Result<Self::Ty> {
+        let disc = src.get_u8();
+        match disc {
+            0 => Ok(Self::ServerGroup(Uuid::read(src)?)),
+            1 => {
+                let channel = Uuid::read(src)?;
+                let group = Uuid::read(src)?;
+                Ok(Self::ChannelGroup { channel, group })
+            }
+            _ => Err(anyhow::Error::from(ErrorEnumVariantNotFound("TokenGrant", disc))),
+        }
+    }
+
+    fn write(&self, dst: &mut BytesMut) -> anyhow::Result<()> {
+        match self {
+            TokenGrant::ServerGroup(group) => {
+                dst.put_u8(0);
+                group.write(dst)?;
+            }
+            TokenGrant::ChannelGroup { channel, group } => {
+                dst.put_u8(1);
+                channel.write(dst)?;
+                group.write(dst)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -46,7 +221,8 @@ pub struct DbUser {
     pub last_security_proof: U256,
     pub last_verified_security_level: u8,
     pub groups: Vec<Uuid>,
-    pub perms: PermsSnapshot,
+    pub perms: Perms, // overrides the perms granted by the user's groups
+    pub last_channel: Option<Uuid>, // the channel the user was in when they disconnected
 }
 
 impl DbUser {
@@ -58,6 +234,7 @@ impl DbUser {
         self.last_verified_security_level.write(&mut buf)?;
         self.groups.write(&mut buf)?;
         self.perms.write(&mut buf)?;
+        self.last_channel.write(&mut buf)?;
         Ok(IVec::from(buf.to_vec()))
     }
 
@@ -69,7 +246,105 @@ impl DbUser {
             last_security_proof: U256::read(&mut buf)?,
             last_verified_security_level: u8::read(&mut buf)?,
             groups: Vec::<Uuid>::read(&mut buf)?,
-            perms: PermsSnapshot::read(&mut buf)?,
+            perms: Perms::read(&mut buf)?,
+            // users that were stored before the last channel was remembered don't have one
+            last_channel: if buf.has_remaining() {
+                Option::<Uuid>::read(&mut buf)?
+            } else {
+                None
+            },
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db() -> (UserDb, String) {
+        let path = std::env::temp_dir().join(format!("rustspeak_user_test_{}", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        (UserDb::new(path.clone()).unwrap(), path)
+    }
+
+    fn token(uses: Option<u64>, expiry: Option<Duration>) -> PrivilegeToken {
+        PrivilegeToken {
+            token: Uuid::new_v4().simple().to_string(),
+            grant: TokenGrant::ServerGroup(Uuid::new_v4()),
+            uses,
+            expiry,
+        }
+    }
+
+    #[test]
+    fn privilege_tokens_survive_a_round_trip() {
+        let (channel, group) = (Uuid::new_v4(), Uuid::new_v4());
+        for (grant, uses, expiry) in [
+            (TokenGrant::ServerGroup(group), None, None),
+            (TokenGrant::ChannelGroup { channel, group }, Some(3), Some(Duration::from_millis(1234))),
+        ] {
+            let token = PrivilegeToken {
+                token: "0123456789abcdef".to_string(),
+                grant,
+                uses,
+                expiry,
+            };
+            let read = PrivilegeToken::from_bytes(token.clone().to_bytes().unwrap()).unwrap();
+            assert_eq!(read.token, token.token);
+            assert_eq!(read.uses, token.uses);
+            assert_eq!(read.expiry, token.expiry);
+            match (read.grant, token.grant) {
+                (TokenGrant::ServerGroup(read), TokenGrant::ServerGroup(group)) => assert_eq!(read, group),
+                (TokenGrant::ChannelGroup { channel: read_channel, group: read_group }, TokenGrant::ChannelGroup { channel, group }) => {
+                    assert_eq!(read_channel, channel);
+                    assert_eq!(read_group, group);
+                }
+                _ => panic!("the grant changed"),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_grants_are_rejected() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(2);
+        assert!(TokenGrant::read(&mut buf.freeze()).is_err());
+    }
+
+    #[test]
+    fn tokens_are_used_up() {
+        let (db, path) = temp_db();
+        let limited = token(Some(2), None);
+        let unlimited = token(None, None);
+        let expired = token(None, Some(Duration::from_millis(1)));
+        for token in [&limited, &unlimited, &expired] {
+            db.insert_token(token).unwrap();
+        }
+
+        assert_eq!(db.redeem_token(&limited.token).unwrap().unwrap().uses, Some(2));
+        assert_eq!(db.redeem_token(&limited.token).unwrap().unwrap().uses, Some(1));
+        assert!(db.redeem_token(&limited.token).unwrap().is_none());
+        for _ in 0..3 {
+            assert!(db.redeem_token(&unlimited.token).unwrap().is_some());
+        }
+        assert!(db.redeem_token(&expired.token).unwrap().is_none());
+        assert!(db.redeem_token("unknown").unwrap().is_none());
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn failed_grants_give_the_use_back() {
+        let (db, path) = temp_db();
+        let token = token(Some(1), None);
+        db.insert_token(&token).unwrap();
+
+        // redeeming the last use removes the token, restoring the use brings it back
+        let redeemed = db.redeem_token(&token.token).unwrap().unwrap();
+        assert!(db.tokens().unwrap().is_empty());
+        db.restore_token_use(&redeemed).unwrap();
+        assert_eq!(db.redeem_token(&token.token).unwrap().unwrap().uses, Some(1));
+        drop(db);
+        let _ = std::fs::remove_dir_all(path);
+    }
+}